@@ -0,0 +1,963 @@
+// Assembler for Octo source (https://github.com/JohnEarnest/Octo). Covers the parts of the language
+// homebrew actually uses: labels, :alias, :const, :calc, :macro, :org, :byte, :call, loop/while/again,
+// if ... then and if ... begin/else/end, the comparison pseudo-ops and raw data. The output starts at
+// 0x200 and goes straight into Chip8::load_rom.
+//
+// Every instruction remembers which source line it came from (SourceMap) so the debugger can show the
+// source instead of a disassembly.
+
+use crate::cpu::PROGRAM_START;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Macros calling macros calling macros... stop somewhere.
+const MAX_EXPANSIONS: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+// ROM address -> 1-based source line.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    // The source file, if we know it
+    pub file: Option<String>,
+    pub lines: BTreeMap<u16, usize>,
+}
+
+// A source map with the source it points into, for the debuggers.
+#[derive(Clone, Debug)]
+pub struct Listing {
+    pub map: SourceMap,
+    pub lines: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    pub source_map: SourceMap,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Where a forward reference needs patching once the label turns up.
+#[derive(Clone, Copy)]
+enum Width {
+    // Low 12 bits of an opcode
+    Nnn,
+    // A whole word, for i := long
+    Long,
+}
+
+struct Fixup {
+    address: u16,
+    width: Width,
+    name: String,
+    line: usize,
+}
+
+enum Block {
+    // Address of the jump that skips to else/end
+    If { jump: u16 },
+    Else { jump: u16 },
+    // Jumps out from while, patched at again
+    Loop { start: u16, exits: Vec<u16> },
+}
+
+// A condition compiled down to a skip. `skip_if_false` skips the next instruction when the condition
+// doesn't hold, `skip_if_true` when it does. Some need setup instructions first, which clobber VF.
+struct Condition {
+    setup: Vec<u16>,
+    skip_if_false: u16,
+    skip_if_true: u16,
+}
+
+struct Assembler {
+    input: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: u16,
+    line: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    lines: BTreeMap<u16, usize>,
+    expansions: usize,
+}
+
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    // Execution starts at 0x200, so if main isn't the first thing in the program we need a jump to it.
+    // Easiest to find out by assembling once without.
+    let program = assemble_pass(source, false)?;
+    match program.labels.get("main") {
+        Some(&main) if main != PROGRAM_START => assemble_pass(source, true),
+        _ => Ok(program),
+    }
+}
+
+fn assemble_pass(source: &str, jump_to_main: bool) -> Result<Program, AssembleError> {
+    let mut asm = Assembler {
+        input: tokenize(source).into(),
+        rom: vec![],
+        here: PROGRAM_START,
+        line: 1,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: vec![],
+        blocks: vec![],
+        lines: BTreeMap::new(),
+        expansions: 0,
+    };
+    if jump_to_main {
+        asm.reference("main", Width::Nnn, 0x1000)?;
+    }
+
+    while let Some(token) = asm.next() {
+        asm.statement(token)?;
+    }
+    if let Some(block) = asm.blocks.last() {
+        let what = match block {
+            Block::If { .. } | Block::Else { .. } => "if ... begin without end",
+            Block::Loop { .. } => "loop without again",
+        };
+        return Err(asm.error(what));
+    }
+    asm.patch_fixups()?;
+
+    Ok(Program {
+        rom: asm.rom,
+        labels: asm.labels.into_iter().collect(),
+        source_map: SourceMap {
+            file: None,
+            lines: asm.lines,
+        },
+    })
+}
+
+pub fn assemble_file(path: &Path) -> Result<Program, AssembleError> {
+    let source = fs::read_to_string(path).map_err(|e| AssembleError {
+        line: 0,
+        message: format!("Could not read {}: {}", path.display(), e),
+    })?;
+    let mut program = assemble(&source)?;
+    // Absolute, so the map still finds the source when it's used from somewhere else
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    program.source_map.file = Some(path.display().to_string());
+    Ok(program)
+}
+
+// Reads a ROM and whatever source we can find for it. Octo source (.8o) is assembled on the spot,
+// anything else is loaded as a ROM and picks up the source map chip8-asm --map leaves next to it
+// (game.ch8 -> game.map) if there is one.
+pub fn load_program(path: &Path) -> Result<(Vec<u8>, Option<Listing>), String> {
+    if path.extension().is_some_and(|e| e == "8o") {
+        let program = assemble_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let listing = Listing::load(program.source_map)?;
+        return Ok((program.rom, Some(listing)));
+    }
+    let rom = fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let map_path = path.with_extension("map");
+    if !map_path.exists() {
+        return Ok((rom, None));
+    }
+    let map = SourceMap::load_from_file(&map_path)?;
+    Ok((rom, Some(Listing::load(map)?)))
+}
+
+impl Listing {
+    pub fn load(map: SourceMap) -> Result<Listing, String> {
+        let file = map
+            .file
+            .clone()
+            .ok_or("Source map doesn't say where the source is")?;
+        let source =
+            fs::read_to_string(&file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+        Ok(Listing {
+            map,
+            lines: source.lines().map(str::to_string).collect(),
+        })
+    }
+
+    // The line number and text of the source for the instruction at `address`
+    pub fn line_at(&self, address: u16) -> Option<(usize, &str)> {
+        let line = self.map.line(address)?;
+        Some((line, self.lines.get(line - 1)?.as_str()))
+    }
+}
+
+impl SourceMap {
+    pub fn line(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| e.to_string())
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<SourceMap, String> {
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    }
+}
+
+// Octo tokens are separated by whitespace, # starts a comment.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for (j, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for text in code.split_whitespace() {
+            tokens.push(Token {
+                text: text.to_string(),
+                line: j + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+fn register_number(text: &str) -> Option<u16> {
+    let text = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if text.len() != 1 {
+        return None;
+    }
+    u16::from_str_radix(text, 16).ok()
+}
+
+impl Assembler {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.input.pop_front()?;
+        self.line = token.line;
+        Some(token)
+    }
+
+    fn error(&self, message: &str) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    fn expect_any(&mut self, what: &str) -> Result<Token, AssembleError> {
+        self.next()
+            .ok_or_else(|| self.error(&format!("Expected {} at the end of the file", what)))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssembleError> {
+        let token = self.expect_any(text)?;
+        if token.text != text {
+            return Err(self.error(&format!("Expected {}, found {}", text, token.text)));
+        }
+        Ok(())
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.input.front().is_some_and(|t| t.text == text)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AssembleError> {
+        let offset = (self.here - PROGRAM_START) as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here = self
+            .here
+            .checked_add(1)
+            .ok_or_else(|| self.error("Program doesn't fit in memory"))?;
+        Ok(())
+    }
+
+    // An instruction, which goes in the source map.
+    fn emit(&mut self, opcode: u16) -> Result<(), AssembleError> {
+        self.lines.insert(self.here, self.line);
+        self.emit_word(opcode)
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), AssembleError> {
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    fn patch(&mut self, address: u16, width: Width, value: u16) {
+        let offset = (address - PROGRAM_START) as usize;
+        match width {
+            Width::Nnn => {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | ((value >> 8) & 0x0F) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+            Width::Long => {
+                self.rom[offset] = (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+        }
+    }
+
+    // The blocks (if ... begin, loop, while) jump with a plain 1NNN, so like a jump to a label their
+    // targets have to stay below 0x1000.
+    fn jump_target(&self, target: u16) -> Result<u16, AssembleError> {
+        if target > 0xFFF {
+            return Err(self.error(&format!(
+                "Block jumps to {:#X}, which is past 0xFFF",
+                target
+            )));
+        }
+        Ok(target)
+    }
+
+    // Emits `opcode` with an address in it, or a placeholder to fill in once the label is defined.
+    fn reference(&mut self, name: &str, width: Width, opcode: u16) -> Result<(), AssembleError> {
+        let address = self.here;
+        match self.known_value(name) {
+            Some(value) => {
+                let value = value as i64;
+                let max = match width {
+                    Width::Nnn => 0xFFF,
+                    Width::Long => 0xFFFF,
+                };
+                if !(0..=max).contains(&value) {
+                    return Err(self.error(&format!("Address {} is out of range", name)));
+                }
+                match width {
+                    Width::Nnn => self.emit(opcode | value as u16),
+                    Width::Long => self.emit_word(value as u16),
+                }
+            }
+            None if is_identifier(name) => {
+                self.fixups.push(Fixup {
+                    address,
+                    width,
+                    name: name.to_string(),
+                    line: self.line,
+                });
+                match width {
+                    Width::Nnn => self.emit(opcode),
+                    Width::Long => self.emit_word(0),
+                }
+            }
+            None => Err(self.error(&format!("Not an address: {}", name))),
+        }
+    }
+
+    fn patch_fixups(&mut self) -> Result<(), AssembleError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&value) = self.labels.get(&fixup.name) else {
+                return Err(AssembleError {
+                    line: fixup.line,
+                    message: format!("Undefined name: {}", fixup.name),
+                });
+            };
+            if let Width::Nnn = fixup.width
+                && value > 0xFFF
+            {
+                return Err(AssembleError {
+                    line: fixup.line,
+                    message: format!("{} is past 0xFFF, use i := long", fixup.name),
+                });
+            }
+            self.patch(fixup.address, fixup.width, value);
+        }
+        Ok(())
+    }
+
+    // Numbers, constants and labels defined so far.
+    fn known_value(&self, text: &str) -> Option<f64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|a| *a as f64))
+    }
+
+    fn value(&self, text: &str) -> Result<f64, AssembleError> {
+        self.known_value(text)
+            .ok_or_else(|| self.error(&format!("Undefined name: {}", text)))
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, AssembleError> {
+        let value = self.value(text)? as i64;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(&format!("{} doesn't fit in a byte", text)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&self, text: &str) -> Result<u16, AssembleError> {
+        let value = self.value(text)? as i64;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(&format!("{} doesn't fit in a nibble", text)));
+        }
+        Ok(value as u16)
+    }
+
+    fn register(&self, text: &str) -> Option<u16> {
+        register_number(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn expect_register(&mut self) -> Result<u16, AssembleError> {
+        let token = self.expect_any("a register")?;
+        self.register(&token.text)
+            .ok_or_else(|| self.error(&format!("Expected a register, found {}", token.text)))
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), AssembleError> {
+        if !is_identifier(name) || self.labels.contains_key(name) {
+            return Err(self.error(&format!("Bad or duplicate label: {}", name)));
+        }
+        self.labels.insert(name.to_string(), self.here);
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AssembleError> {
+        let text = token.text.as_str();
+        match text {
+            ":" => {
+                let name = self.expect_any("a label name")?;
+                self.define_label(&name.text)?;
+            }
+            ":alias" => {
+                let name = self.expect_any("an alias name")?.text;
+                let register = self.expect_register()?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.expect_any("a constant name")?.text;
+                let value = self.expect_any("a value")?;
+                let value = self.value(&value.text)?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.expect_any("a constant name")?.text;
+                let value = self.braced_expression()?;
+                self.constants.insert(name, value);
+            }
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address = self.expect_any("an address")?;
+                let address = self.value(&address.text)? as i64;
+                if !(PROGRAM_START as i64..=0xFFFF).contains(&address) {
+                    return Err(self.error(":org address out of range"));
+                }
+                self.here = address as u16;
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.braced_expression()?
+                } else {
+                    let token = self.expect_any("a value")?;
+                    self.value(&token.text)?
+                };
+                let value = value as i64;
+                if !(-128..=255).contains(&value) {
+                    return Err(self.error(":byte value doesn't fit in a byte"));
+                }
+                self.emit_byte(value as u8)?;
+            }
+            ":call" => {
+                let target = self.expect_any("an address")?;
+                self.reference(&target.text, Width::Nnn, 0x2000)?;
+            }
+            "clear" => self.emit(0x00E0)?,
+            "return" | ";" => self.emit(0x00EE)?,
+            "exit" => self.emit(0x00FD)?,
+            "lores" => self.emit(0x00FE)?,
+            "hires" => self.emit(0x00FF)?,
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "audio" => self.emit(0xF002)?,
+            "scroll-down" | "scroll-up" | "plane" => {
+                let n = self.expect_any("a number")?;
+                let n = self.nibble(&n.text)?;
+                self.emit(match text {
+                    "scroll-down" => 0x00C0 | n,
+                    "scroll-up" => 0x00D0 | n,
+                    _ => 0xF001 | (n << 8),
+                })?;
+            }
+            "jump" | "jump0" => {
+                let target = self.expect_any("an address")?;
+                let opcode = if text == "jump" { 0x1000 } else { 0xB000 };
+                self.reference(&target.text, Width::Nnn, opcode)?;
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.expect_any("a height")?;
+                let n = self.nibble(&n.text)?;
+                self.emit(0xD000 | (x << 8) | (y << 4) | n)?;
+            }
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.emit(0xF033 | (x << 8))?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                if self.peek_is("-") {
+                    self.next();
+                    let y = self.expect_register()?;
+                    let opcode = if text == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(opcode | (x << 8) | (y << 4))?;
+                } else {
+                    let opcode = if text == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(opcode | (x << 8))?;
+                }
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.expect_register()?;
+                let opcode = if text == "saveflags" { 0xF075 } else { 0xF085 };
+                self.emit(opcode | (x << 8))?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                let opcode = match text {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(opcode | (x << 8))?;
+            }
+            "i" => self.index_statement()?,
+            "if" => self.if_statement()?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    let end_jump = self.here;
+                    self.emit(0x1000)?;
+                    let target = self.jump_target(self.here)?;
+                    self.patch(jump, Width::Nnn, target);
+                    self.blocks.push(Block::Else { jump: end_jump });
+                }
+                _ => return Err(self.error("else without if ... begin")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump } | Block::Else { jump }) => {
+                    let target = self.jump_target(self.here)?;
+                    self.patch(jump, Width::Nnn, target)
+                }
+                _ => return Err(self.error("end without if ... begin")),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                exits: vec![],
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                for opcode in condition.setup {
+                    self.emit(opcode)?;
+                }
+                self.emit(condition.skip_if_true)?;
+                let exit = self.here;
+                self.emit(0x1000)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|b| matches!(b, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => return Err(self.error("while outside a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(0x1000 | self.jump_target(start)?)?;
+                    let target = self.jump_target(self.here)?;
+                    for exit in exits {
+                        self.patch(exit, Width::Nnn, target);
+                    }
+                }
+                _ => return Err(self.error("again without loop")),
+            },
+            "{" | "}" | "then" | "begin" => {
+                return Err(self.error(&format!("Unexpected {}", text)));
+            }
+            _ if self.register(text).is_some() => {
+                let x = self.register(text).unwrap_or_default();
+                self.register_statement(x)?;
+            }
+            _ if self.macros.contains_key(text) => self.expand_macro(text)?,
+            _ if self.known_value(text).is_some() && !self.labels.contains_key(text) => {
+                let byte = self.byte(text)?;
+                self.emit_byte(byte)?;
+            }
+            // Anything else is a subroutine call, possibly to a label further down
+            _ => self.reference(text, Width::Nnn, 0x2000)?,
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AssembleError> {
+        let op = self.expect_any(":= or +=")?;
+        match op.text.as_str() {
+            ":=" => {
+                let source = self.expect_any("a value")?;
+                match source.text.as_str() {
+                    "hex" | "bighex" => {
+                        let x = self.expect_register()?;
+                        let opcode = if source.text == "hex" { 0xF029 } else { 0xF030 };
+                        self.emit(opcode | (x << 8))?;
+                    }
+                    "long" => {
+                        let target = self.expect_any("an address")?;
+                        self.emit(0xF000)?;
+                        self.reference(&target.text, Width::Long, 0)?;
+                    }
+                    _ => self.reference(&source.text, Width::Nnn, 0xA000)?,
+                }
+            }
+            "+=" => {
+                let x = self.expect_register()?;
+                self.emit(0xF01E | (x << 8))?;
+            }
+            _ => return Err(self.error(&format!("Can't do i {}", op.text))),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), AssembleError> {
+        let op = self.expect_any("an operator")?;
+        let source = self.expect_any("a value")?;
+        let y = self.register(&source.text);
+        let opcode = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | (x << 8) | (y << 4),
+            (":=", None) => match source.text.as_str() {
+                "random" => {
+                    let mask = self.expect_any("a mask")?;
+                    0xC000 | (x << 8) | self.byte(&mask.text)? as u16
+                }
+                "delay" => 0xF007 | (x << 8),
+                "key" => 0xF00A | (x << 8),
+                _ => 0x6000 | (x << 8) | self.byte(&source.text)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | (x << 8) | (y << 4),
+            ("+=", None) => 0x7000 | (x << 8) | self.byte(&source.text)? as u16,
+            ("-=", Some(y)) => 0x8005 | (x << 8) | (y << 4),
+            ("-=", None) => {
+                let n = self.byte(&source.text)?;
+                0x7000 | (x << 8) | n.wrapping_neg() as u16
+            }
+            ("=-", Some(y)) => 0x8007 | (x << 8) | (y << 4),
+            ("|=", Some(y)) => 0x8001 | (x << 8) | (y << 4),
+            ("&=", Some(y)) => 0x8002 | (x << 8) | (y << 4),
+            ("^=", Some(y)) => 0x8003 | (x << 8) | (y << 4),
+            (">>=", Some(y)) => 0x8006 | (x << 8) | (y << 4),
+            ("<<=", Some(y)) => 0x800E | (x << 8) | (y << 4),
+            _ => {
+                return Err(self.error(&format!("Can't do v{:x} {} {}", x, op.text, source.text)));
+            }
+        };
+        self.emit(opcode)
+    }
+
+    fn if_statement(&mut self) -> Result<(), AssembleError> {
+        let condition = self.condition()?;
+        for opcode in &condition.setup {
+            self.emit(*opcode)?;
+        }
+        let keyword = self.expect_any("then or begin")?;
+        match keyword.text.as_str() {
+            "then" => self.emit(condition.skip_if_false),
+            "begin" => {
+                self.emit(condition.skip_if_true)?;
+                let jump = self.here;
+                self.emit(0x1000)?;
+                self.blocks.push(Block::If { jump });
+                Ok(())
+            }
+            _ => Err(self.error(&format!("Expected then or begin, found {}", keyword.text))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.expect_register()?;
+        let op = self.expect_any("a comparison")?.text;
+        if op == "key" || op == "-key" {
+            let pressed = 0xE09E | (x << 8);
+            let released = 0xE0A1 | (x << 8);
+            return Ok(if op == "key" {
+                Condition {
+                    setup: vec![],
+                    skip_if_false: released,
+                    skip_if_true: pressed,
+                }
+            } else {
+                Condition {
+                    setup: vec![],
+                    skip_if_false: pressed,
+                    skip_if_true: released,
+                }
+            });
+        }
+
+        let rhs = self.expect_any("a value")?;
+        let y = self.register(&rhs.text);
+        let (equal, not_equal) = match y {
+            Some(y) => (0x5000 | (x << 8) | (y << 4), 0x9000 | (x << 8) | (y << 4)),
+            None => {
+                let nn = self.byte(&rhs.text)? as u16;
+                (0x3000 | (x << 8) | nn, 0x4000 | (x << 8) | nn)
+            }
+        };
+        // The ordered comparisons go through VF like Octo does: load the right hand side into VF and
+        // subtract, which leaves the no-borrow flag in VF.
+        let load_vf = match y {
+            Some(y) => 0x8F00 | (y << 4),
+            None => 0x6F00 | self.byte(&rhs.text)? as u16,
+        };
+        // flag = vx >= rhs
+        let vx_minus_vf = 0x8F07 | (x << 4);
+        // flag = rhs >= vx
+        let vf_minus_vx = 0x8F05 | (x << 4);
+        let (flag_set, flag_clear) = (0x3F01, 0x3F00);
+        Ok(match op.as_str() {
+            "==" => Condition {
+                setup: vec![],
+                skip_if_false: not_equal,
+                skip_if_true: equal,
+            },
+            "!=" => Condition {
+                setup: vec![],
+                skip_if_false: equal,
+                skip_if_true: not_equal,
+            },
+            "<" => Condition {
+                setup: vec![load_vf, vx_minus_vf],
+                skip_if_false: flag_set,
+                skip_if_true: flag_clear,
+            },
+            ">=" => Condition {
+                setup: vec![load_vf, vx_minus_vf],
+                skip_if_false: flag_clear,
+                skip_if_true: flag_set,
+            },
+            ">" => Condition {
+                setup: vec![load_vf, vf_minus_vx],
+                skip_if_false: flag_set,
+                skip_if_true: flag_clear,
+            },
+            "<=" => Condition {
+                setup: vec![load_vf, vf_minus_vx],
+                skip_if_false: flag_clear,
+                skip_if_true: flag_set,
+            },
+            _ => return Err(self.error(&format!("Unknown comparison {}", op))),
+        })
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.expect_any("a macro name")?.text;
+        let mut args = vec![];
+        loop {
+            let token = self.expect_any("{")?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+        let body = self.braced_tokens()?;
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error("Too many macro expansions, is a macro calling itself?"));
+        }
+        // Expanded code maps back to the line that used the macro, that's where you are when stepping
+        let line = self.line;
+        let arg_count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+        for j in 0..arg_count {
+            let value = self.expect_any("a macro argument")?;
+            values.insert(self.macros[name].args[j].clone(), value.text);
+        }
+        let expanded: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: values
+                    .get(&token.text)
+                    .cloned()
+                    .unwrap_or_else(|| token.text.clone()),
+                line,
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.input.push_front(token);
+        }
+        Ok(())
+    }
+
+    // Everything up to the matching }, with the opening { already consumed.
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, AssembleError> {
+        let mut depth = 1;
+        let mut tokens = vec![];
+        loop {
+            let token = self.expect_any("}")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(tokens);
+                    }
+                }
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    fn braced_expression(&mut self) -> Result<f64, AssembleError> {
+        self.expect("{")?;
+        let tokens = self.braced_tokens()?;
+        let mut parser = Expression {
+            asm: self,
+            tokens: &tokens,
+            pos: 0,
+        };
+        let value = parser.binary(0)?;
+        if parser.pos != tokens.len() {
+            return Err(self.error("Junk at the end of the expression"));
+        }
+        Ok(value)
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+// :calc expressions. Precedence climbing over the usual integer operators, evaluated in f64 like Octo.
+struct Expression<'a> {
+    asm: &'a Assembler,
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+const OPERATORS: [(&str, u8); 10] = [
+    ("|", 1),
+    ("^", 2),
+    ("&", 3),
+    ("<<", 4),
+    (">>", 4),
+    ("+", 5),
+    ("-", 5),
+    ("*", 6),
+    ("/", 6),
+    ("%", 6),
+];
+
+impl Expression<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text.as_str())
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<f64, AssembleError> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek()
+            && let Some(&(op, precedence)) = OPERATORS.iter().find(|(o, _)| *o == op)
+            && precedence > min_precedence
+        {
+            self.pos += 1;
+            let right = self.binary(precedence)?;
+            let (a, b) = (left as i64, right as i64);
+            left = match op {
+                "|" => (a | b) as f64,
+                "^" => (a ^ b) as f64,
+                "&" => (a & b) as f64,
+                "<<" | ">>" => {
+                    let shifted = u32::try_from(b).ok().and_then(|b| match op {
+                        "<<" => a.checked_shl(b),
+                        _ => a.checked_shr(b),
+                    });
+                    shifted.ok_or_else(|| self.asm.error("Shift out of range"))? as f64
+                }
+                "+" => left + right,
+                "-" => left - right,
+                "*" => left * right,
+                "/" if right == 0.0 => return Err(self.asm.error("Division by zero")),
+                "/" => left / right,
+                _ if b == 0 => return Err(self.asm.error("Division by zero")),
+                _ => a
+                    .checked_rem(b)
+                    .ok_or_else(|| self.asm.error("Remainder out of range"))?
+                    as f64,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<f64, AssembleError> {
+        let token = self
+            .peek()
+            .ok_or_else(|| self.asm.error("Expression ends too early"))?
+            .to_string();
+        self.pos += 1;
+        match token.as_str() {
+            "-" => Ok(-self.unary()?),
+            "~" => Ok(!(self.unary()? as i64) as f64),
+            "(" => {
+                let value = self.binary(0)?;
+                if self.peek() != Some(")") {
+                    return Err(self.asm.error("Missing )"));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            "HERE" => Ok(self.asm.here as f64),
+            _ => self.asm.value(&token),
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Line 0 is for errors that aren't about the source, like not being able to read it
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
@@ -2,11 +2,20 @@
 // Rodio docs: https://docs.rs/rodio/0.13.0/rodio/
 
 use rodio::{OutputStream, Sink, Source};
+use std::f32::consts::TAU;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+const SAMPLE_RATE: u32 = 44100;
+const BEEP_HZ: f32 = 440.0;
+
+// XO-CHIP pattern and playback rate. None means the plain 440Hz beep.
+type SharedPattern = Arc<Mutex<Option<([u8; 16], f32)>>>;
+
 pub struct Audio {
     _stream: OutputStream,
     sink: Sink,
+    pattern: SharedPattern,
 }
 
 impl Audio {
@@ -14,27 +23,93 @@ impl Audio {
         let (_stream, stream_handle) = OutputStream::try_default().map_err(|e| e.to_string())?;
         let sink = Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
 
-        let source = rodio::source::SineWave::new(440.0)
-            .take_duration(Duration::from_secs_f32(0.05))
-            .repeat_infinite();
-        sink.append(source);
+        let pattern = Arc::new(Mutex::new(None));
+        sink.append(Tone {
+            pattern: Arc::clone(&pattern),
+            phase: 0.0,
+        });
         sink.pause();
 
-        Ok(Audio { _stream, sink })
+        Ok(Audio {
+            _stream,
+            sink,
+            pattern,
+        })
     }
 
     pub fn new_silent() -> Audio {
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&stream_handle).unwrap();
         sink.pause();
-        Audio { _stream, sink }
+        Audio {
+            _stream,
+            sink,
+            pattern: Arc::new(Mutex::new(None)),
+        }
     }
+}
 
-    pub fn play(&self) {
+impl chip_8::Audio for Audio {
+    fn play(&mut self) {
         self.sink.play();
     }
 
-    pub fn pause(&self) {
+    fn pause(&mut self) {
         self.sink.pause();
     }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        if let Ok(mut current) = self.pattern.lock() {
+            *current = Some((*pattern, rate));
+        }
+    }
+}
+
+// Endless tone that picks up pattern changes while it is playing, so we never have to touch the sink queue.
+struct Tone {
+    pattern: SharedPattern,
+    // Position in the current waveform: 0..1 for the beep, 0..128 bits for a pattern.
+    phase: f32,
+}
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let pattern = self.pattern.lock().ok().and_then(|p| *p);
+        let sample = match pattern {
+            None => {
+                self.phase = (self.phase + BEEP_HZ / SAMPLE_RATE as f32) % 1.0;
+                (self.phase * TAU).sin()
+            }
+            Some((bits, rate)) => {
+                self.phase = (self.phase + rate / SAMPLE_RATE as f32) % 128.0;
+                let bit = self.phase as usize;
+                if bits[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    0.25
+                } else {
+                    -0.25
+                }
+            }
+        };
+        Some(sample)
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
 }
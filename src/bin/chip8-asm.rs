@@ -0,0 +1,57 @@
+// Assembles Octo source into a ROM.
+//
+//   chip8-asm [-o OUT] [--map] SOURCE
+//
+// OUT defaults to SOURCE with a .ch8 extension. --map also writes a source map next to it (game.ch8 ->
+// game.map), which the debuggers pick up to show source lines alongside the disassembly.
+
+use chip_8::asm;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: chip8-asm [-o OUT] [--map] SOURCE";
+
+fn main() {
+    let mut source = None;
+    let mut out = None;
+    let mut map = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => out = Some(PathBuf::from(path)),
+                None => exit_with(USAGE),
+            },
+            "--map" => map = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => exit_with(USAGE),
+        }
+    }
+
+    let Some(source) = source else {
+        exit_with(USAGE);
+    };
+    let program = match asm::assemble_file(&source) {
+        Ok(program) => program,
+        Err(e) => exit_with(&format!("{}: {}", source.display(), e)),
+    };
+    let out = out.unwrap_or_else(|| source.with_extension("ch8"));
+    if let Err(e) = std::fs::write(&out, &program.rom) {
+        exit_with(&format!("Could not write {}: {}", out.display(), e));
+    }
+    if map {
+        let map = out.with_extension("map");
+        if let Err(e) = program.source_map.save_to_file(&map) {
+            exit_with(&format!("Could not write {}: {}", map.display(), e));
+        }
+    }
+    println!("{}: {} bytes", out.display(), program.rom.len());
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
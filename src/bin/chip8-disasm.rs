@@ -0,0 +1,53 @@
+// Prints an annotated listing of a ROM.
+//
+//   chip8-disasm [--classic] [--mode chip8|schip|xochip] [--base ADDR] ROM
+//
+// Octo syntax by default, so the output can go straight back through chip8-asm.
+
+use chip_8::Mode;
+use chip_8::disasm::{self, Options, Syntax};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: chip8-disasm [--classic] [--mode chip8|schip|xochip] [--base ADDR] ROM";
+
+fn main() {
+    let mut options = Options::default();
+    let mut rom = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--classic" => options.syntax = Syntax::Classic,
+            "--octo" => options.syntax = Syntax::Octo,
+            "--mode" => match args.next().as_deref().and_then(Mode::from_preset) {
+                Some(mode) => options.mode = mode,
+                None => exit_with(USAGE),
+            },
+            "--base" => {
+                let base = args.next().unwrap_or_default();
+                match u16::from_str_radix(base.trim_start_matches("0x"), 16) {
+                    Ok(base) => options.base = base,
+                    Err(_) => exit_with(USAGE),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => exit_with(USAGE),
+        }
+    }
+
+    let Some(rom) = rom else {
+        exit_with(USAGE);
+    };
+    match std::fs::read(&rom) {
+        Ok(bytes) => print!("{}", disasm::disassemble(&bytes, &options)),
+        Err(e) => exit_with(&format!("Could not read {}: {}", rom.display(), e)),
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
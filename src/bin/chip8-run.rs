@@ -0,0 +1,27 @@
+// `chip_8 run ROM --headless ...` without the launcher attached, so it builds on CI machines that don't
+// have SDL or GTK:
+//
+//   chip8-run ROM [options]
+//
+// Takes the same options as `chip_8 run` (see chip_8::cli) and is always headless.
+
+use chip_8::cli::{self, DEFAULT_INSTRUCTIONS_PER_FRAME, RunArgs};
+
+fn main() {
+    let args = std::iter::once("--headless".to_string()).chain(std::env::args().skip(1));
+    let run = match RunArgs::parse(args, DEFAULT_INSTRUCTIONS_PER_FRAME) {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run.start_tracing() {
+        eprintln!("{}\n{}", e, cli::USAGE);
+        std::process::exit(2);
+    }
+    if let Err(e) = cli::run_headless(&run) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,133 @@
+// All memory traffic goes through here so a ROM poking past the end of RAM gets an error instead of
+// panicking the whole process.
+
+use crate::error::Fault;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+// What happens when an address falls off the end of memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MemoryOverflow {
+    // Wrap around to the start, like the address lines on real hardware.
+    #[default]
+    Wrap,
+    // Fail with Fault::MemoryOutOfBounds.
+    Fault,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// One data access, with the address after wrapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub access: Access,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bus {
+    memory: Vec<u8>,
+    overflow: MemoryOverflow,
+    // Only there while a debugger has watchpoints. A RefCell because reads take &self.
+    log: Option<RefCell<Vec<MemoryAccess>>>,
+}
+
+impl Bus {
+    pub fn new(size: usize) -> Self {
+        Bus {
+            memory: vec![0; size],
+            overflow: MemoryOverflow::default(),
+            log: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty()
+    }
+
+    pub fn overflow(&self) -> MemoryOverflow {
+        self.overflow
+    }
+
+    pub fn set_overflow(&mut self, overflow: MemoryOverflow) {
+        self.overflow = overflow;
+    }
+
+    fn resolve(&self, address: usize) -> Result<usize, Fault> {
+        if address < self.memory.len() {
+            return Ok(address);
+        }
+        match self.overflow {
+            MemoryOverflow::Wrap => Ok(address % self.memory.len()),
+            MemoryOverflow::Fault => Err(Fault::MemoryOutOfBounds { address }),
+        }
+    }
+
+    fn record(&self, address: usize, access: Access) {
+        if let Some(log) = &self.log {
+            log.borrow_mut().push(MemoryAccess { address, access });
+        }
+    }
+
+    pub fn read(&self, address: usize) -> Result<u8, Fault> {
+        let address = self.resolve(address)?;
+        self.record(address, Access::Read);
+        Ok(self.memory[address])
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) -> Result<(), Fault> {
+        let address = self.resolve(address)?;
+        self.record(address, Access::Write);
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    // Instruction fetch. Same as read_word but never shows up in the access log, a read watchpoint on
+    // code would otherwise fire on every pass through it.
+    pub fn fetch_word(&self, address: usize) -> Result<u16, Fault> {
+        let hi = self.memory[self.resolve(address)?];
+        let lo = self.memory[self.resolve(address + 1)?];
+        Ok(((hi as u16) << 8) | lo as u16)
+    }
+
+    pub fn is_logging(&self) -> bool {
+        self.log.is_some()
+    }
+
+    pub fn set_logging(&mut self, enabled: bool) {
+        if enabled != self.log.is_some() {
+            self.log = enabled.then(|| RefCell::new(vec![]));
+        }
+    }
+
+    // Everything read or written since the last call.
+    pub fn take_log(&mut self) -> Vec<MemoryAccess> {
+        self.log.as_mut().map(|log| log.take()).unwrap_or_default()
+    }
+
+    // Big endian, the way opcodes are stored.
+    pub fn read_word(&self, address: usize) -> Result<u16, Fault> {
+        Ok(((self.read(address)? as u16) << 8) | self.read(address + 1)? as u16)
+    }
+
+    pub fn read_range(&self, address: usize, len: usize) -> Result<Vec<u8>, Fault> {
+        (0..len).map(|j| self.read(address + j)).collect()
+    }
+
+    // For fonts and ROMs, where we already know it fits.
+    pub fn load(&mut self, address: usize, bytes: &[u8]) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.memory
+    }
+}
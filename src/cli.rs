@@ -0,0 +1,364 @@
+// `chip_8 run ROM ...`, running a ROM straight from the command line without the launcher. With
+// --headless no window or audio device is opened at all: the ROM runs for --frames frames with keys
+// from --input/--press, then whatever was asked for is dumped. Meant for batch jobs and regression
+// tests in CI. chip8-run is the same thing with --headless always on, and since it's in the library it
+// builds without SDL and GTK (cargo build --no-default-features --bin chip8-run).
+//
+// Movies work in both modes. --record writes every frame's keypad to a movie file, --play feeds one back
+// in on the same machine it was recorded on. With both, playback goes on into recording: the movie is
+// replayed and whatever happens after it ends gets appended, and --from-state starts everything from a
+// save state instead of a freshly loaded ROM.
+
+use crate::display::{self, PALETTE};
+use crate::headless::{self, InputScript};
+use crate::{Chip8, Mode, Movie, Quirks, Random, RngKind, Snapshot, asm, trace};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: chip_8 run ROM [options]
+       chip8-run ROM [options]    (always headless)
+  --quirks NAME            chip8, chip48, schip or xochip (default chip8, windows use the ROM's profile)
+  --speed N                instructions per frame (default 8, windows use the ROM's profile)
+  --seed N                 seed for CXNN's random numbers, decimal or 0x hex (default: new every run)
+  --rng NAME               splitmix or vip (COSMAC VIP style 8 bit generator)
+  --from-state FILE        start from a save state instead of a fresh machine
+  --record FILE            record the keypad on every frame into a movie
+  --play FILE              play a movie back, its machine settings replace the ones above. Headless,
+                           the run fails if the final screen isn't the recorded one
+  --palette NAME           lcd, classic, amber, phosphor, gameboy or custom (default: the launcher's)
+  --trace SPEC             logging, e.g. cpu=trace,input=debug or all=info
+  --trace-file FILE        write the trace to FILE instead of stderr
+  --headless               no window or audio, everything below needs this
+  --frames N               frames to run (default 600, 10 seconds, or the movie's length)
+  --input FILE             scripted keys, lines of \"FRAME KEY down|up|FRAMES\"
+  --press FRAME:KEY[:N]    hold KEY (hex) for N frames (default 1) from FRAME
+  --screenshot FILE        save the final screen as a PNG
+  --scale N                screenshot pixel size (default 1)
+  --dump-screen FILE       final screen as text, - for stdout
+  --dump-registers FILE    final registers, timers and stack as text, - for stdout
+  --dump-memory FILE       raw memory
+  --dump-state FILE        save state JSON, loadable like a save slot";
+
+const DEFAULT_FRAMES: u64 = 600;
+// The launcher's default speed, ~500Hz at 60Hz frame rate
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 8;
+
+pub struct RunArgs {
+    pub rom: PathBuf,
+    pub preset: String,
+    pub instructions_per_frame: u32,
+    pub headless: bool,
+    pub seed: Option<u64>,
+    pub rng: RngKind,
+    pub from_state: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    // Windowed runs use the ROM's profile from rom_db.json unless --quirks or --speed is given.
+    // Headless runs never do, they should come out the same on every machine.
+    pub rom_profile: bool,
+    pub palette: Option<String>,
+    // --trace specs in the order given, applied by start_tracing()
+    trace: Vec<String>,
+    pub trace_file: Option<PathBuf>,
+    frames: Option<u64>,
+    input: InputScript,
+    screenshot: Option<PathBuf>,
+    scale: u32,
+    dump_screen: Option<PathBuf>,
+    dump_registers: Option<PathBuf>,
+    dump_memory: Option<PathBuf>,
+    dump_state: Option<PathBuf>,
+}
+
+impl RunArgs {
+    // Everything after "run".
+    pub fn parse(
+        mut args: impl Iterator<Item = String>,
+        default_speed: u32,
+    ) -> Result<RunArgs, String> {
+        let mut rom = None;
+        let mut run = RunArgs {
+            rom: PathBuf::new(),
+            preset: "chip8".to_string(),
+            instructions_per_frame: default_speed,
+            headless: false,
+            seed: None,
+            rng: RngKind::default(),
+            from_state: None,
+            record: None,
+            play: None,
+            rom_profile: true,
+            palette: None,
+            trace: vec![],
+            trace_file: None,
+            frames: None,
+            input: InputScript::new(),
+            screenshot: None,
+            scale: 1,
+            dump_screen: None,
+            dump_registers: None,
+            dump_memory: None,
+            dump_state: None,
+        };
+        let mut headless_only = None;
+        let mut machine_setting = None;
+        while let Some(arg) = args.next() {
+            if arg.starts_with("--")
+                && !matches!(
+                    arg.as_str(),
+                    "--quirks"
+                        | "--speed"
+                        | "--seed"
+                        | "--rng"
+                        | "--from-state"
+                        | "--record"
+                        | "--play"
+                        | "--palette"
+                        | "--trace"
+                        | "--trace-file"
+                        | "--headless"
+                )
+            {
+                headless_only = Some(arg.clone());
+            }
+            // A movie brings its own machine and keys
+            if matches!(
+                arg.as_str(),
+                "--quirks"
+                    | "--speed"
+                    | "--seed"
+                    | "--rng"
+                    | "--from-state"
+                    | "--input"
+                    | "--press"
+            ) {
+                machine_setting = Some(arg.clone());
+            }
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--quirks" => {
+                    run.preset = value()?;
+                    run.rom_profile = false;
+                }
+                "--speed" => {
+                    run.instructions_per_frame = number::<u32>(&value()?)?.max(1);
+                    run.rom_profile = false;
+                }
+                "--seed" => run.seed = Some(parse_seed(&value()?)?),
+                "--rng" => {
+                    let name = value()?;
+                    run.rng = RngKind::from_name(&name)
+                        .ok_or(format!("Unknown random number generator: {}", name))?;
+                }
+                "--from-state" => run.from_state = Some(value()?.into()),
+                "--record" => run.record = Some(value()?.into()),
+                "--play" => run.play = Some(value()?.into()),
+                "--palette" => run.palette = Some(value()?),
+                "--trace" => run.trace.push(value()?),
+                "--trace-file" => run.trace_file = Some(value()?.into()),
+                "--headless" => run.headless = true,
+                "--frames" => run.frames = Some(number(&value()?)?),
+                "--input" => {
+                    for event in InputScript::load_from_file(Path::new(&value()?))?.events() {
+                        run.input.push(*event);
+                    }
+                }
+                "--press" => {
+                    let press = value()?;
+                    let parts: Vec<&str> = press.split(':').collect();
+                    let (frame, key, frames) = match parts[..] {
+                        [frame, key] => (frame, key, "1"),
+                        [frame, key, frames] => (frame, key, frames),
+                        _ => return Err(format!("--press {}: expected FRAME:KEY[:N]", press)),
+                    };
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|k| *k < 16)
+                        .ok_or(format!("--press {}: key must be 0-F", press))?;
+                    if !run.input.press(number(frame)?, key, number(frames)?) {
+                        return Err(format!("--press {}: frame out of range", press));
+                    }
+                }
+                "--screenshot" => run.screenshot = Some(value()?.into()),
+                "--scale" => run.scale = number(&value()?)?,
+                "--dump-screen" => run.dump_screen = Some(value()?.into()),
+                "--dump-registers" => run.dump_registers = Some(value()?.into()),
+                "--dump-memory" => run.dump_memory = Some(value()?.into()),
+                "--dump-state" => run.dump_state = Some(value()?.into()),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument {}", arg)),
+            }
+        }
+
+        run.rom = rom.ok_or("No ROM given")?;
+        if let Some(arg) = headless_only
+            && !run.headless
+        {
+            return Err(format!("{} only works with --headless", arg));
+        }
+        if let Some(arg) = machine_setting
+            && run.play.is_some()
+        {
+            return Err(format!("{} can't be used with --play", arg));
+        }
+        Ok(run)
+    }
+
+    pub fn quirks(&self) -> Result<(Mode, Quirks), String> {
+        let quirks = Quirks::preset(&self.preset)
+            .ok_or(format!("Unknown quirks preset: {}", self.preset))?;
+        Ok((
+            Mode::from_preset(&self.preset).unwrap_or(Mode::Chip8),
+            quirks,
+        ))
+    }
+
+    pub fn random(&self) -> Random {
+        match self.seed {
+            Some(seed) => Random::new(self.rng, seed),
+            None => Random::from_entropy(self.rng),
+        }
+    }
+
+    pub fn start_state(&self) -> Result<Option<Snapshot>, String> {
+        self.from_state
+            .as_deref()
+            .map(|path| Snapshot::load_from_file(path).map_err(|e| e.to_string()))
+            .transpose()
+    }
+
+    // Turns on the logging asked for with --trace and --trace-file.
+    pub fn start_tracing(&self) -> Result<(), String> {
+        for spec in &self.trace {
+            trace::configure(spec)?;
+        }
+        trace::set_output_file(self.trace_file.as_deref())
+            .map_err(|e| format!("Could not open trace file: {}", e))
+    }
+
+    pub fn movie(&self) -> Result<Option<Movie>, String> {
+        self.play.as_deref().map(Movie::load_from_file).transpose()
+    }
+}
+
+// Runs the ROM and writes the dumps. The dumps are written even when the ROM crashes, that's usually
+// when you want them most, but the error still comes back so the exit code shows it.
+pub fn run_headless(args: &RunArgs) -> Result<(), String> {
+    let (rom, _) = asm::load_program(&args.rom)?;
+    let movie = args.movie()?;
+    let (mut cpu, input, instructions_per_frame) = match &movie {
+        Some(movie) => (
+            movie.setup(&rom)?,
+            movie.input_script(),
+            movie.instructions_per_frame,
+        ),
+        None => {
+            let (mode, quirks) = args.quirks()?;
+            let mut cpu = Chip8::with_mode(mode, quirks);
+            cpu.set_random(args.random());
+            cpu.load_rom(&rom).map_err(|e| e.to_string())?;
+            if let Some(state) = args.start_state()? {
+                cpu.restore(&state).map_err(|e| e.to_string())?;
+            }
+            (cpu, args.input.clone(), args.instructions_per_frame)
+        }
+    };
+    let frames = args
+        .frames
+        .or(movie.as_ref().map(|movie| movie.len()))
+        .unwrap_or(DEFAULT_FRAMES);
+    let mut recording = args.record.as_ref().map(|_| match &movie {
+        Some(movie) => movie.restart(),
+        None => Movie::new(
+            &cpu,
+            &rom,
+            instructions_per_frame,
+            args.from_state.is_some(),
+        ),
+    });
+
+    let result = headless::run(&mut cpu, frames, instructions_per_frame, &input);
+    write_dumps(&cpu, args)?;
+    // Same keys the run just saw, frame by frame. A frame that crashed half way isn't in the movie.
+    if let (Some(path), Some(recording)) = (&args.record, &mut recording) {
+        let completed = match &result {
+            Ok(ran) => *ran,
+            Err(stopped) => stopped.frames,
+        };
+        let mut keys = [false; 16];
+        for frame in 0..completed {
+            input.apply(frame, &mut keys);
+            recording.push(&keys);
+        }
+        recording.finish(cpu.framebuffer());
+        recording.save_to_file(path)?;
+    }
+    let ran = match result {
+        Ok(ran) => ran,
+        Err(stopped) => {
+            return Err(format!(
+                "Stopped at PC={:04X} in frame {}: {}",
+                cpu.pc(),
+                stopped.frames,
+                stopped.error
+            ));
+        }
+    };
+    if ran < frames {
+        eprintln!("ROM exited after {} frames", ran);
+    }
+    // A movie only proves anything if it was played to the end
+    match &movie {
+        Some(movie) if frames == movie.len() => movie.verify(cpu.framebuffer()),
+        _ => Ok(()),
+    }
+}
+
+fn write_dumps(cpu: &Chip8, args: &RunArgs) -> Result<(), String> {
+    if let Some(path) = &args.screenshot {
+        // No launcher settings headless, custom isn't a thing here
+        let palette = match &args.palette {
+            Some(name) => display::palette(name).ok_or(format!("Unknown palette: {}", name))?,
+            None => PALETTE,
+        };
+        headless::save_screenshot(cpu.framebuffer(), path, args.scale, &palette)?;
+    }
+    if let Some(path) = &args.dump_screen {
+        write_text(path, &headless::screen_text(cpu.framebuffer()))?;
+    }
+    if let Some(path) = &args.dump_registers {
+        write_text(path, &headless::registers_text(cpu))?;
+    }
+    if let Some(path) = &args.dump_memory {
+        fs::write(path, cpu.memory())
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &args.dump_state {
+        cpu.snapshot()
+            .save_to_file(path)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn write_text(path: &Path, text: &str) -> Result<(), String> {
+    if path == Path::new("-") {
+        print!("{}", text);
+        return Ok(());
+    }
+    fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+// Decimal, or hex with 0x in front. The launcher's seed box takes the same.
+pub fn parse_seed(text: &str) -> Result<u64, String> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).map_err(|_| format!("Not a number: {}", text)),
+        None => number(text),
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("Not a number: {}", text))
+}
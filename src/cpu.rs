@@ -1,12 +1,62 @@
-use crate::audio::Audio;
-use crate::input::InputHandler;
-use crate::window::Window;
-use rand::Rng;
-
-pub struct CPU {
-    pub window: Window,
-    pub audio: Audio,
-    memory: [u8; 4096],
+use crate::bus::{Bus, MemoryAccess, MemoryOverflow};
+use crate::display::{Framebuffer, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+use crate::error::{EmulatorError, Fault, UnknownOpcodePolicy};
+use crate::font::{BIG_FONT, BIG_FONT_ADDR, SMALL_FONT, SMALL_FONT_ADDR};
+use crate::instruction::{Instruction, decode};
+use crate::peripherals::{Audio, Keypad};
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::random::Random;
+use crate::savestate::{SAVE_STATE_VERSION, Snapshot};
+use crate::trace;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Where ROMs are loaded and execution starts.
+pub const PROGRAM_START: u16 = 0x200;
+
+// Which instruction set is available. Quirks are separate, this only decides which opcodes exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Mode {
+    // Maps the quirk preset names onto an instruction set.
+    pub fn from_preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" | "vip" | "chip48" | "chip-48" => Some(Mode::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Mode::SuperChip),
+            "xochip" | "xo-chip" => Some(Mode::XoChip),
+            _ => None,
+        }
+    }
+
+    // XO-CHIP is a superset of SUPER-CHIP.
+    pub fn has_schip(&self) -> bool {
+        matches!(self, Mode::SuperChip | Mode::XoChip)
+    }
+
+    pub fn has_xochip(&self) -> bool {
+        matches!(self, Mode::XoChip)
+    }
+
+    // XO-CHIP gets the full 16 bit address space.
+    pub fn memory_size(&self) -> usize {
+        if self.has_xochip() { 0x10000 } else { 0x1000 }
+    }
+}
+
+// FX0A in progress: which register gets the key, and the key we are waiting to see released.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyWait {
+    pub register: usize,
+    pub pressed: Option<u8>,
+}
+
+pub struct Chip8 {
+    memory: Bus,
     v: [u8; 16],
     i: u16,
     pc: u16,
@@ -14,340 +64,909 @@ pub struct CPU {
     sp: u8,
     delay_timer: u8,
     sound_timer: u8,
+    framebuffer: Framebuffer,
+    quirks: Quirks,
+    // Cleared by DXYN and set again by tick_timers() when the display wait quirk is on.
+    vblank_ready: bool,
+    mode: Mode,
+    // SUPER-CHIP "RPL user flags" (FX75/FX85). They outlive the ROM on the HP48, so the frontend persists them.
+    rpl: [u8; 16],
+    // Set by 00FD.
+    halted: bool,
+    // XO-CHIP audio: a 128 bit waveform (F002) played back at a rate set by FX3A.
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    // Set when the pattern or pitch changed and the frontend hasn't heard about it yet.
+    audio_dirty: bool,
+    // Pending FX0A, if any.
+    key_wait: Option<KeyWait>,
+    unknown_opcode_policy: UnknownOpcodePolicy,
+    trap: Option<EmulatorError>,
+    // CXNN's generator. Seeded from the OS unless someone picks a seed, see set_random().
+    random: Random,
 }
 // As specified by - https://tobiasvl.github.io/blog/write-a-chip-8-emulator/
 // Thank you for the tutorial, sir.
-impl CPU {
-    pub fn new(window: Window, audio: Audio) -> Self {
-        let mut memory = [0u8; 4096];
-
-        // load fontset
-        let fontset: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-
-        for (i, b) in fontset.iter().enumerate() {
-            memory[i] = *b;
-        }
+impl Chip8 {
+    pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_mode(Mode::Chip8, quirks)
+    }
 
-        CPU {
-            window,
-            audio,
+    pub fn with_mode(mode: Mode, quirks: Quirks) -> Self {
+        let mut memory = Bus::new(mode.memory_size());
+
+        // load fonts
+        memory.load(SMALL_FONT_ADDR as usize, &SMALL_FONT);
+        memory.load(BIG_FONT_ADDR as usize, &BIG_FONT);
+
+        Chip8 {
             memory,
             v: [0; 16],
             i: 0,
-            pc: 0x200,
+            pc: PROGRAM_START,
             stack: [0; 16],
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
+            framebuffer: Framebuffer::new(),
+            quirks,
+            vblank_ready: true,
+            mode,
+            rpl: [0; 16],
+            halted: false,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            audio_dirty: false,
+            key_wait: None,
+            unknown_opcode_policy: UnknownOpcodePolicy::default(),
+            trap: None,
+            random: Random::default(),
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
-        if rom.len() > 0x1000 - 0x200 {
-            return Err("ROM is too big".to_string());
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        let max = self.memory.len() - PROGRAM_START as usize;
+        if rom.len() > max {
+            return Err(EmulatorError::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
         }
 
-        for (i, b) in rom.iter().enumerate() {
-            self.memory[0x200 + i] = *b;
-        }
+        self.memory.load(PROGRAM_START as usize, rom);
 
         Ok(())
     }
 
-    pub fn run_loop(&mut self, input: &InputHandler) -> Result<(), String> {
-        let mut executing = true;
-        let mut waiting_for_keypress = false;
-        let mut store_keypress_in: usize = 0;
-
-        let keys_pressed = input.get_keys();
-        for (j, k) in keys_pressed.iter().enumerate() {
-            if *k {
-                if waiting_for_keypress {
-                    executing = true;
-                    waiting_for_keypress = false;
-                    self.v[store_keypress_in] = j as u8;
-                    println!("Stored keypress {} in V{}", j, store_keypress_in);
-                    break;
-                }
-                println!("Key {} pressed!", j);
-            }
+    pub fn load_rom_file(&mut self, path: &Path) -> Result<(), EmulatorError> {
+        let rom = std::fs::read(path)?;
+        self.load_rom(&rom)
+    }
+
+    // One instruction plus whatever the speaker needs to hear afterwards. Timers are not touched here,
+    // see tick_timers(). The screen isn't either: whoever runs the frames hands framebuffer() to their
+    // Display once per frame, so drawing costs the same at 8 instructions per frame as at 1000.
+    pub fn run_loop(
+        &mut self,
+        keypad: &dyn Keypad,
+        audio: &mut dyn Audio,
+    ) -> Result<(), EmulatorError> {
+        self.step(keypad)?;
+
+        if self.audio_dirty {
+            audio.set_pattern(&self.audio_pattern, self.playback_rate());
+            self.audio_dirty = false;
+        }
+
+        if self.sound_timer > 0 {
+            audio.play();
+        } else {
+            audio.pause();
         }
 
-        if !executing {
+        Ok(())
+    }
+
+    // Fetch, decode and execute a single instruction. No frontend involved.
+    pub fn step(&mut self, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        if self.halted || self.trap.is_some() {
             return Ok(());
         }
 
+        // FX0A stops instruction fetch until a key goes down (and back up), the timers keep running.
+        if self.key_wait.is_some() {
+            self.poll_key_wait(keypad);
+            if self.key_wait.is_some() {
+                return Ok(());
+            }
+        }
+
         // Fetch
-        let opcode = ((self.memory[self.pc as usize] as u16) << 8)
-            | self.memory[(self.pc + 1) as usize] as u16;
-        self.pc += 2;
-
-        // Decode
-        let x = ((opcode & 0x0F00) >> 8) as usize;
-        let y = ((opcode & 0x00F0) >> 4) as usize;
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let n = (opcode & 0x000F) as u8;
-
-        // Execute
-        match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 => {
-                    self.window.clear_screen();
-                    println!("Opcode 00E0: Clear screen");
-                }
-                0x00EE => {
-                    if self.sp > 0 {
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp as usize];
-                        println!("Opcode 00EE: Return from subroutine to PC={:04X}", self.pc);
-                    } else {
-                        return Err("Stack underflow".to_string());
-                    }
-                }
-                _ => {
-                    println!("Unimplemented machine code routine: {:04X}", opcode);
+        let pc = self.pc;
+        let opcode = self
+            .memory
+            .fetch_word(pc as usize)
+            .map_err(|fault| EmulatorError::Cpu {
+                pc,
+                opcode: 0,
+                fault,
+            })?;
+        self.advance_pc(2)
+            .map_err(|fault| EmulatorError::Cpu { pc, opcode, fault })?;
+
+        match self.execute(opcode, keypad) {
+            Ok(()) => Ok(()),
+            Err(Fault::UnknownOpcode) => self.unknown_opcode(pc, opcode),
+            Err(fault) => Err(EmulatorError::Cpu { pc, opcode, fault }),
+        }
+    }
+
+    // Decode and execute. PC already points past the instruction. Faults get the PC and opcode attached
+    // by step().
+    fn execute(&mut self, opcode: u16, keypad: &dyn Keypad) -> Result<(), Fault> {
+        let mut instruction = decode(opcode);
+        if !self.mode.supports(&instruction) {
+            // Outside its platform a SUPER-CHIP/XO-CHIP 00xx is just another machine code routine
+            instruction = match opcode & 0xF000 {
+                0x0000 => Instruction::MachineCode(opcode & 0x0FFF),
+                _ => Instruction::Unknown(opcode),
+            };
+        }
+
+        match instruction {
+            Instruction::Clear => {
+                self.framebuffer.clear();
+                trace!(Display, Trace, "Opcode 00E0: Clear screen");
+            }
+            Instruction::Return => {
+                if self.sp > 0 {
+                    self.sp -= 1;
+                    self.pc = self.stack[self.sp as usize];
+                    trace!(
+                        Cpu,
+                        Trace, "Opcode 00EE: Return from subroutine to PC={:04X}", self.pc
+                    );
+                } else {
+                    return Err(Fault::StackUnderflow);
                 }
-            },
-            0x1000 => {
+            }
+            Instruction::ScrollUp(n) => {
+                self.framebuffer.scroll_up(n as usize);
+                trace!(Display, Trace, "Opcode 00DN: Scroll up {}", n);
+            }
+            Instruction::ScrollRight => {
+                self.framebuffer.scroll_right(4);
+                trace!(Display, Trace, "Opcode 00FB: Scroll right 4");
+            }
+            Instruction::ScrollLeft => {
+                self.framebuffer.scroll_left(4);
+                trace!(Display, Trace, "Opcode 00FC: Scroll left 4");
+            }
+            Instruction::Exit => {
+                self.halted = true;
+                trace!(Cpu, Trace, "Opcode 00FD: Exit interpreter");
+            }
+            Instruction::Lores => {
+                self.framebuffer.set_hires(false);
+                trace!(Display, Trace, "Opcode 00FE: Lo-res mode");
+            }
+            Instruction::Hires => {
+                self.framebuffer.set_hires(true);
+                trace!(Display, Trace, "Opcode 00FF: Hi-res mode");
+            }
+            Instruction::ScrollDown(n) => {
+                self.framebuffer.scroll_down(n as usize);
+                trace!(Display, Trace, "Opcode 00CN: Scroll down {}", n);
+            }
+            Instruction::MachineCode(_) => {
+                trace!(
+                    Cpu,
+                    Warn, "Unimplemented machine code routine: {:04X}", opcode
+                );
+            }
+            Instruction::Jump(nnn) => {
                 self.pc = nnn;
-                println!("Opcode 1NNN: Jump to {:04X}", nnn);
+                trace!(Cpu, Trace, "Opcode 1NNN: Jump to {:04X}", nnn);
             }
-            0x2000 => {
+            Instruction::Call(nnn) => {
                 if self.sp < 16 {
                     self.stack[self.sp as usize] = self.pc;
                     self.sp += 1;
                     self.pc = nnn;
-                    println!(
-                        "Opcode 2NNN: Call subroutine at {:04X}, SP={}",
-                        nnn, self.sp
+                    trace!(
+                        Cpu,
+                        Trace, "Opcode 2NNN: Call subroutine at {:04X}, SP={}", nnn, self.sp
                     );
                 } else {
-                    return Err("Stack overflow".to_string());
+                    return Err(Fault::StackOverflow);
                 }
             }
-            0x3000 => {
+            Instruction::SkipIfEqual { x, nn } => {
                 if self.v[x] == nn {
-                    self.pc += 2;
-                    println!("Opcode 3XNN: Skip if V{}={} (true)", x, nn);
+                    self.skip_next()?;
+                    trace!(Cpu, Trace, "Opcode 3XNN: Skip if V{}={} (true)", x, nn);
                 } else {
-                    println!("Opcode 3XNN: Skip if V{}={} (false)", x, nn);
+                    trace!(Cpu, Trace, "Opcode 3XNN: Skip if V{}={} (false)", x, nn);
                 }
             }
-            0x4000 => {
+            Instruction::SkipIfNotEqual { x, nn } => {
                 if self.v[x] != nn {
-                    self.pc += 2;
-                    println!("Opcode 4XNN: Skip if V{}!={} (true)", x, nn);
+                    self.skip_next()?;
+                    trace!(Cpu, Trace, "Opcode 4XNN: Skip if V{}!={} (true)", x, nn);
                 } else {
-                    println!("Opcode 4XNN: Skip if V{}!={} (false)", x, nn);
+                    trace!(Cpu, Trace, "Opcode 4XNN: Skip if V{}!={} (false)", x, nn);
                 }
             }
-            0x5000 => {
+            Instruction::SkipIfRegistersEqual { x, y } => {
                 if self.v[x] == self.v[y] {
-                    self.pc += 2;
-                    println!("Opcode 5XY0: Skip if V{}==V{} (true)", x, y);
+                    self.skip_next()?;
+                    trace!(Cpu, Trace, "Opcode 5XY0: Skip if V{}==V{} (true)", x, y);
                 } else {
-                    println!("Opcode 5XY0: Skip if V{}==V{} (false)", x, y);
+                    trace!(Cpu, Trace, "Opcode 5XY0: Skip if V{}==V{} (false)", x, y);
                 }
             }
-            0x6000 => {
+            Instruction::SaveRange { x, y } => {
+                for (k, r) in Self::register_range(x, y).enumerate() {
+                    self.memory.write(self.i as usize + k, self.v[r])?;
+                }
+                trace!(Cpu, Trace, "Opcode 5XY2: Store V{}-V{} at I", x, y);
+            }
+            Instruction::LoadRange { x, y } => {
+                for (k, r) in Self::register_range(x, y).enumerate() {
+                    self.v[r] = self.memory.read(self.i as usize + k)?;
+                }
+                trace!(Cpu, Trace, "Opcode 5XY3: Load V{}-V{} from I", x, y);
+            }
+            Instruction::Set { x, nn } => {
                 self.v[x] = nn;
-                println!("Opcode 6XNN: Set V{}={}", x, nn);
+                trace!(Cpu, Trace, "Opcode 6XNN: Set V{}={}", x, nn);
             }
-            0x7000 => {
+            Instruction::Add { x, nn } => {
                 self.v[x] = self.v[x].wrapping_add(nn);
-                println!("Opcode 7XNN: Add {} to V{}, result={}", nn, x, self.v[x]);
+                trace!(
+                    Cpu,
+                    Trace, "Opcode 7XNN: Add {} to V{}, result={}", nn, x, self.v[x]
+                );
             }
-            0x8000 => match opcode & 0x000F {
-                0x0000 => {
-                    self.v[x] = self.v[y];
-                    println!("Opcode 8XY0: Set V{}=V{}", x, y);
-                }
-                0x0001 => {
-                    self.v[x] |= self.v[y];
-                    println!("Opcode 8XY1: V{} |= V{}", x, y);
-                }
-                0x0002 => {
-                    self.v[x] &= self.v[y];
-                    println!("Opcode 8XY2: V{} &= V{}", x, y);
-                }
-                0x0003 => {
-                    self.v[x] ^= self.v[y];
-                    println!("Opcode 8XY3: V{} ^= V{}", x, y);
-                }
-                0x0004 => {
-                    let sum = self.v[x] as u16 + self.v[y] as u16;
-                    self.v[0xF] = if sum > 255 { 1 } else { 0 };
-                    self.v[x] = sum as u8;
-                    println!("Opcode 8XY4: V{} += V{}, VF={}", x, y, self.v[0xF]);
-                }
-                0x0005 => {
-                    self.v[0xF] = if self.v[x] >= self.v[y] { 1 } else { 0 };
-                    self.v[x] = self.v[x].wrapping_sub(self.v[y]);
-                    println!("Opcode 8XY5: V{} -= V{}, VF={}", x, y, self.v[0xF]);
-                }
-                0x0006 => {
-                    self.v[0xF] = self.v[x] & 0x1;
-                    self.v[x] >>= 1;
-                    println!("Opcode 8XY6: V{} >>= 1, VF={}", x, self.v[0xF]);
-                }
-                0x0007 => {
-                    self.v[0xF] = if self.v[y] >= self.v[x] { 1 } else { 0 };
-                    self.v[x] = self.v[y].wrapping_sub(self.v[x]);
-                    println!("Opcode 8XY7: V{} = V{}-V{}, VF={}", x, y, x, self.v[0xF]);
+            Instruction::Copy { x, y } => {
+                self.v[x] = self.v[y];
+                trace!(Cpu, Trace, "Opcode 8XY0: Set V{}=V{}", x, y);
+            }
+            Instruction::Or { x, y } => {
+                self.v[x] |= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
                 }
-                0x000E => {
-                    self.v[0xF] = (self.v[x] & 0x80) >> 7;
-                    self.v[x] <<= 1;
-                    println!("Opcode 8XYE: V{} <<= 1, VF={}", x, self.v[0xF]);
+                trace!(Cpu, Trace, "Opcode 8XY1: V{} |= V{}", x, y);
+            }
+            Instruction::And { x, y } => {
+                self.v[x] &= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
                 }
-                _ => {
-                    return Err(format!("Unknown opcode: {:04X}", opcode));
+                trace!(Cpu, Trace, "Opcode 8XY2: V{} &= V{}", x, y);
+            }
+            Instruction::Xor { x, y } => {
+                self.v[x] ^= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
                 }
-            },
-            0x9000 => {
+                trace!(Cpu, Trace, "Opcode 8XY3: V{} ^= V{}", x, y);
+            }
+            Instruction::AddRegisters { x, y } => {
+                // The flag goes in last so it wins when X is F, same as the shifts
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = if sum > 255 { 1 } else { 0 };
+                trace!(
+                    Cpu,
+                    Trace, "Opcode 8XY4: V{} += V{}, VF={}", x, y, self.v[0xF]
+                );
+            }
+            Instruction::Subtract { x, y } => {
+                let flag = if self.v[x] >= self.v[y] { 1 } else { 0 };
+                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0xF] = flag;
+                trace!(
+                    Cpu,
+                    Trace, "Opcode 8XY5: V{} -= V{}, VF={}", x, y, self.v[0xF]
+                );
+            }
+            Instruction::ShiftRight { x, y } => {
+                let src = if self.quirks.shift {
+                    self.v[x]
+                } else {
+                    self.v[y]
+                };
+                self.v[x] = src >> 1;
+                self.v[0xF] = src & 0x1;
+                trace!(Cpu, Trace, "Opcode 8XY6: V{} >>= 1, VF={}", x, self.v[0xF]);
+            }
+            Instruction::SubtractReversed { x, y } => {
+                let flag = if self.v[y] >= self.v[x] { 1 } else { 0 };
+                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0xF] = flag;
+                trace!(
+                    Cpu,
+                    Trace, "Opcode 8XY7: V{} = V{}-V{}, VF={}", x, y, x, self.v[0xF]
+                );
+            }
+            Instruction::ShiftLeft { x, y } => {
+                let src = if self.quirks.shift {
+                    self.v[x]
+                } else {
+                    self.v[y]
+                };
+                self.v[x] = src << 1;
+                self.v[0xF] = (src & 0x80) >> 7;
+                trace!(Cpu, Trace, "Opcode 8XYE: V{} <<= 1, VF={}", x, self.v[0xF]);
+            }
+            Instruction::SkipIfRegistersNotEqual { x, y } => {
                 if self.v[x] != self.v[y] {
-                    self.pc += 2;
-                    println!("Opcode 9XY0: Skip if V{}!=V{} (true)", x, y);
+                    self.skip_next()?;
+                    trace!(Cpu, Trace, "Opcode 9XY0: Skip if V{}!=V{} (true)", x, y);
                 } else {
-                    println!("Opcode 9XY0: Skip if V{}!=V{} (false)", x, y);
+                    trace!(Cpu, Trace, "Opcode 9XY0: Skip if V{}!=V{} (false)", x, y);
                 }
             }
-            0xA000 => {
+            Instruction::SetIndex(nnn) => {
                 self.i = nnn;
-                println!("Opcode ANNN: Set I={:04X}", nnn);
+                trace!(Cpu, Trace, "Opcode ANNN: Set I={:04X}", nnn);
             }
-            0xB000 => {
-                self.pc = nnn + self.v[0] as u16;
-                println!("Opcode BNNN: Jump to {:04X}+V0", nnn);
+            Instruction::JumpOffset { x, nnn } => {
+                if self.quirks.jump {
+                    self.pc = nnn + self.v[x] as u16;
+                    trace!(Cpu, Trace, "Opcode BXNN: Jump to {:04X}+V{}", nnn, x);
+                } else {
+                    self.pc = nnn + self.v[0] as u16;
+                    trace!(Cpu, Trace, "Opcode BNNN: Jump to {:04X}+V0", nnn);
+                }
             }
-            0xC000 => {
-                let random: u8 = rand::thread_rng().r#gen();
-                self.v[x] = random & nn;
-                println!("Opcode CXNN: V{} = random & {}", x, nn);
+            Instruction::Random { x, nn } => {
+                self.v[x] = self.random.next_byte() & nn;
+                trace!(Cpu, Trace, "Opcode CXNN: V{} = random & {}", x, nn);
             }
-            0xD000 => {
-                let mut sprite = Vec::new();
-                for j in 0..n {
-                    sprite.push(self.memory[(self.i + j as u16) as usize]);
+            Instruction::Draw { x, y, n } => {
+                if self.quirks.display_wait {
+                    if !self.vblank_ready {
+                        // Come back to this instruction after the next vblank.
+                        self.pc = self.pc.wrapping_sub(2);
+                        return Ok(());
+                    }
+                    self.vblank_ready = false;
                 }
-                self.v[0xF] = self.window.draw(&sprite, self.v[x], self.v[y]);
-                println!(
+                // SUPER-CHIP draws a 16x16 sprite (two bytes per row) for DXY0.
+                let (rows, sprite_width) = if n == 0 && self.mode.has_schip() {
+                    (16, 16)
+                } else {
+                    (n as usize, 8)
+                };
+                let sprite = self.memory.read_range(
+                    self.i as usize,
+                    self.framebuffer.sprite_bytes(rows, sprite_width),
+                )?;
+                self.v[0xF] = self.framebuffer.draw(
+                    &sprite,
+                    sprite_width,
+                    self.v[x],
+                    self.v[y],
+                    self.quirks.clipping,
+                );
+                trace!(
+                    Display,
+                    Trace,
                     "Opcode DXYN: Draw sprite at ({}, {}), height={}",
-                    self.v[x], self.v[y], n
+                    self.v[x],
+                    self.v[y],
+                    n
                 );
             }
-            0xE000 => match opcode & 0x00FF {
-                0x009E => {
-                    if keys_pressed[self.v[x] as usize] {
-                        self.pc += 2;
-                        println!("Opcode EX9E: Skip if key V{} pressed (true)", x);
-                    } else {
-                        println!("Opcode EX9E: Skip if key V{} pressed (false)", x);
-                    }
-                }
-                0x00A1 => {
-                    if !keys_pressed[self.v[x] as usize] {
-                        self.pc += 2;
-                        println!("Opcode EXA1: Skip if key V{} not pressed (true)", x);
-                    } else {
-                        println!("Opcode EXA1: Skip if key V{} not pressed (false)", x);
-                    }
-                }
-                _ => {
-                    return Err(format!("Unknown opcode: {:04X}", opcode));
-                }
-            },
-            0xF000 => match opcode & 0x00FF {
-                0x0007 => {
-                    self.v[x] = self.delay_timer;
-                    println!("Opcode FX07: V{} = delay_timer ({})", x, self.delay_timer);
-                }
-                0x000A => {
-                    executing = false;
-                    waiting_for_keypress = true;
-                    store_keypress_in = x;
-                    println!("Opcode FX0A: Wait for keypress, store in V{}", x);
-                }
-                0x0015 => {
-                    self.delay_timer = self.v[x];
-                    println!("Opcode FX15: Set delay_timer=V{}", x);
-                }
-                0x0018 => {
-                    self.sound_timer = self.v[x];
-                    println!("Opcode FX18: Set sound_timer=V{}", x);
-                }
-                0x001E => {
-                    self.i += self.v[x] as u16;
-                    println!("Opcode FX1E: I += V{}", x);
-                }
-                0x0029 => {
-                    self.i = (self.v[x] * 5) as u16;
-                    println!("Opcode FX29: Set I to sprite address for V{}", x);
-                }
-                0x0033 => {
-                    self.memory[self.i as usize] = self.v[x] / 100;
-                    self.memory[(self.i + 1) as usize] = (self.v[x] % 100) / 10;
-                    self.memory[(self.i + 2) as usize] = self.v[x] % 10;
-                    println!("Opcode FX33: Store BCD of V{} at I", x);
+            Instruction::SkipIfKey(x) => {
+                if keypad.is_pressed(self.v[x]) {
+                    self.skip_next()?;
+                    trace!(Cpu, Trace, "Opcode EX9E: Skip if key V{} pressed (true)", x);
+                } else {
+                    trace!(
+                        Cpu,
+                        Trace, "Opcode EX9E: Skip if key V{} pressed (false)", x
+                    );
                 }
-                0x0055 => {
-                    for j in 0..=x {
-                        self.memory[(self.i + j as u16) as usize] = self.v[j];
-                    }
-                    println!("Opcode FX55: Store V0-V{} at I", x);
+            }
+            Instruction::SkipIfNotKey(x) => {
+                if !keypad.is_pressed(self.v[x]) {
+                    self.skip_next()?;
+                    trace!(
+                        Cpu,
+                        Trace, "Opcode EXA1: Skip if key V{} not pressed (true)", x
+                    );
+                } else {
+                    trace!(
+                        Cpu,
+                        Trace, "Opcode EXA1: Skip if key V{} not pressed (false)", x
+                    );
                 }
-                0x0065 => {
-                    for j in 0..=x {
-                        self.v[j] = self.memory[(self.i + j as u16) as usize];
-                    }
-                    println!("Opcode FX65: Load V0-V{} from I", x);
+            }
+            Instruction::SetIndexLong => {
+                // F000 NNNN: the address is the next word
+                self.i = self.memory.read_word(self.pc as usize)?;
+                self.advance_pc(2)?;
+                trace!(Cpu, Trace, "Opcode F000: Set I={:04X}", self.i);
+            }
+            Instruction::Plane(n) => {
+                self.framebuffer.set_planes(n);
+                trace!(Display, Trace, "Opcode FN01: Select planes {}", n);
+            }
+            Instruction::Audio => {
+                let pattern = self.memory.read_range(self.i as usize, 16)?;
+                self.audio_pattern.copy_from_slice(&pattern);
+                self.audio_dirty = true;
+                trace!(Cpu, Trace, "Opcode F002: Load audio pattern from I");
+            }
+            Instruction::GetDelay(x) => {
+                self.v[x] = self.delay_timer;
+                trace!(
+                    Cpu,
+                    Trace, "Opcode FX07: V{} = delay_timer ({})", x, self.delay_timer
+                );
+            }
+            Instruction::WaitKey(x) => {
+                self.key_wait = Some(KeyWait {
+                    register: x,
+                    pressed: None,
+                });
+                trace!(
+                    Cpu,
+                    Trace, "Opcode FX0A: Wait for keypress, store in V{}", x
+                );
+            }
+            Instruction::SetDelay(x) => {
+                self.delay_timer = self.v[x];
+                trace!(Cpu, Trace, "Opcode FX15: Set delay_timer=V{}", x);
+            }
+            Instruction::SetSound(x) => {
+                self.sound_timer = self.v[x];
+                trace!(Cpu, Trace, "Opcode FX18: Set sound_timer=V{}", x);
+            }
+            Instruction::AddIndex(x) => {
+                self.i = self.i.wrapping_add(self.v[x] as u16);
+                trace!(Cpu, Trace, "Opcode FX1E: I += V{}", x);
+            }
+            Instruction::Font(x) => {
+                self.i = SMALL_FONT_ADDR + (self.v[x] & 0xF) as u16 * 5;
+                trace!(
+                    Cpu,
+                    Trace, "Opcode FX29: Set I to sprite address for V{}", x
+                );
+            }
+            Instruction::BigFont(x) => {
+                self.i = BIG_FONT_ADDR + (self.v[x] & 0xF) as u16 * 10;
+                trace!(
+                    Cpu,
+                    Trace, "Opcode FX30: Set I to hi-res sprite address for V{}", x
+                );
+            }
+            Instruction::Bcd(x) => {
+                let i = self.i as usize;
+                self.memory.write(i, self.v[x] / 100)?;
+                self.memory.write(i + 1, (self.v[x] % 100) / 10)?;
+                self.memory.write(i + 2, self.v[x] % 10)?;
+                trace!(Cpu, Trace, "Opcode FX33: Store BCD of V{} at I", x);
+            }
+            Instruction::Store(x) => {
+                for j in 0..=x {
+                    self.memory.write(self.i as usize + j, self.v[j])?;
                 }
-                _ => {
-                    return Err(format!("Unknown opcode: {:04X}", opcode));
+                self.increment_i_after_transfer(x);
+                trace!(Cpu, Trace, "Opcode FX55: Store V0-V{} at I", x);
+            }
+            Instruction::Load(x) => {
+                for j in 0..=x {
+                    self.v[j] = self.memory.read(self.i as usize + j)?;
                 }
-            },
-            _ => {
-                return Err(format!("Unknown opcode: {:04X}", opcode));
+                self.increment_i_after_transfer(x);
+                trace!(Cpu, Trace, "Opcode FX65: Load V0-V{} from I", x);
+            }
+            Instruction::Pitch(x) => {
+                self.pitch = self.v[x];
+                self.audio_dirty = true;
+                trace!(Cpu, Trace, "Opcode FX3A: Set pitch=V{}", x);
+            }
+            Instruction::SaveFlags(x) => {
+                self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+                trace!(Cpu, Trace, "Opcode FX75: Store V0-V{} in RPL flags", x);
+            }
+            Instruction::LoadFlags(x) => {
+                self.v[..=x].copy_from_slice(&self.rpl[..=x]);
+                trace!(Cpu, Trace, "Opcode FX85: Load V0-V{} from RPL flags", x);
+            }
+            Instruction::Unknown(_) => {
+                return Err(Fault::UnknownOpcode);
             }
         }
 
-        if self.window.is_open() {
-            self.window.refresh()?;
-        }
+        Ok(())
+    }
+
+    // The 60Hz tick. The frontend calls this once per frame no matter how many instructions it runs, so
+    // the timers count down at the same rate whatever the emulation speed. It is also the vertical blank
+    // the display wait quirk waits for.
+    pub fn tick_timers(&mut self) {
+        self.vblank_ready = true;
 
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
-            println!("Delay timer decremented to {}", self.delay_timer);
+            trace!(
+                Timers,
+                Trace, "Delay timer decremented to {}", self.delay_timer
+            );
         }
         if self.sound_timer > 0 {
-            self.audio.play();
             self.sound_timer -= 1;
-            println!(
-                "Sound timer decremented to {}, playing audio",
-                self.sound_timer
+            trace!(
+                Timers,
+                Trace, "Sound timer decremented to {}, playing audio", self.sound_timer
             );
+        }
+    }
+
+    // Finishes FX0A once the key is released, like the COSMAC VIP did, or as soon as it is pressed with
+    // the key_wait_on_press quirk.
+    fn poll_key_wait(&mut self, keypad: &dyn Keypad) {
+        let Some(wait) = self.key_wait.as_mut() else {
+            return;
+        };
+        let key = match wait.pressed {
+            Some(key) if !keypad.is_pressed(key) => key,
+            Some(_) => return,
+            None => match (0..16u8).find(|k| keypad.is_pressed(*k)) {
+                Some(key) if self.quirks.key_wait_on_press => key,
+                Some(key) => {
+                    wait.pressed = Some(key);
+                    return;
+                }
+                None => return,
+            },
+        };
+        self.v[wait.register] = key;
+        trace!(
+            Input,
+            Debug, "Stored keypress {} in V{}", key, wait.register
+        );
+        self.key_wait = None;
+    }
+
+    fn unknown_opcode(&mut self, pc: u16, opcode: u16) -> Result<(), EmulatorError> {
+        let error = EmulatorError::Cpu {
+            pc,
+            opcode,
+            fault: Fault::UnknownOpcode,
+        };
+        match self.unknown_opcode_policy {
+            UnknownOpcodePolicy::Halt => Err(error),
+            UnknownOpcodePolicy::Skip => {
+                trace!(
+                    Cpu,
+                    Warn, "Skipping unknown opcode {:04X} at {:04X}", opcode, pc
+                );
+                Ok(())
+            }
+            UnknownOpcodePolicy::Trap => {
+                // Park PC on the bad instruction so whoever looks at the trap sees where it happened.
+                self.pc = pc;
+                self.trap = Some(error);
+                Ok(())
+            }
+        }
+    }
+
+    // Skips the next instruction. On XO-CHIP that might be the 4 byte F000 NNNN.
+    fn skip_next(&mut self) -> Result<(), Fault> {
+        if self.mode.has_xochip() && self.memory.fetch_word(self.pc as usize)? == 0xF000 {
+            self.advance_pc(2)?;
+        }
+        self.advance_pc(2)
+    }
+
+    // Moves PC forward. With MemoryOverflow::Fault running off the end of a 64K XO-CHIP memory is a fault
+    // like any other out of bounds access, not a quiet wrap back to 0000.
+    fn advance_pc(&mut self, by: u16) -> Result<(), Fault> {
+        let address = self.pc as usize + by as usize;
+        match u16::try_from(address) {
+            Ok(pc) if address <= self.memory.len() => self.pc = pc,
+            _ if self.memory.overflow() == MemoryOverflow::Fault => {
+                return Err(Fault::MemoryOutOfBounds { address });
+            }
+            _ => self.pc = self.pc.wrapping_add(by),
+        }
+        Ok(())
+    }
+
+    // 5XY2/5XY3 work in either direction, VX first.
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
         } else {
-            self.audio.pause();
+            Box::new((y..=x).rev())
+        }
+    }
+
+    fn increment_i_after_transfer(&mut self, x: usize) {
+        match self.quirks.memory_increment {
+            MemoryIncrement::None => {}
+            MemoryIncrement::X => self.i = self.i.wrapping_add(x as u16),
+            MemoryIncrement::XPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    // Copies out the complete machine state. Settings that aren't part of the machine (unknown opcode
+    // policy, memory overflow behaviour) stay with the Chip8.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SAVE_STATE_VERSION,
+            mode: self.mode,
+            quirks: self.quirks,
+            memory: self.memory.as_slice().to_vec(),
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            framebuffer: self.framebuffer.clone(),
+            vblank_ready: self.vblank_ready,
+            rpl: self.rpl,
+            halted: self.halted,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            key_wait: self.key_wait,
+            random: self.random,
         }
+    }
 
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), EmulatorError> {
+        if snapshot.version != SAVE_STATE_VERSION {
+            return Err(EmulatorError::SaveState(format!(
+                "Save state version {} is not supported (expected {})",
+                snapshot.version, SAVE_STATE_VERSION
+            )));
+        }
+        if snapshot.memory.len() != snapshot.mode.memory_size() {
+            return Err(EmulatorError::SaveState(format!(
+                "{} bytes of memory is wrong for {:?}",
+                snapshot.memory.len(),
+                snapshot.mode
+            )));
+        }
+        if snapshot.sp as usize > self.stack.len() {
+            return Err(EmulatorError::SaveState(format!(
+                "Stack pointer {} out of range",
+                snapshot.sp
+            )));
+        }
+        // A bad framebuffer or FX0A register wouldn't fail here, it would panic on the next DXYN or frame
+        let framebuffer = &snapshot.framebuffer;
+        let size = (framebuffer.width(), framebuffer.height());
+        if size != (WIDTH, HEIGHT)
+            && !(snapshot.mode.has_schip() && size == (HIRES_WIDTH, HIRES_HEIGHT))
+        {
+            return Err(EmulatorError::SaveState(format!(
+                "A {}x{} screen is wrong for {:?}",
+                size.0, size.1, snapshot.mode
+            )));
+        }
+        if framebuffer.pixels().len() != size.0 * size.1 {
+            return Err(EmulatorError::SaveState(format!(
+                "{} pixels is wrong for a {}x{} screen",
+                framebuffer.pixels().len(),
+                size.0,
+                size.1
+            )));
+        }
+        let planes = if snapshot.mode.has_xochip() { 0x3 } else { 0x1 };
+        if framebuffer.planes() & !planes != 0
+            || framebuffer.pixels().iter().any(|p| p & !planes != 0)
+        {
+            return Err(EmulatorError::SaveState(format!(
+                "The screen uses bit planes {:?} doesn't have",
+                snapshot.mode
+            )));
+        }
+        if let Some(wait) = &snapshot.key_wait
+            && (wait.register > 0xF || wait.pressed.is_some_and(|key| key > 0xF))
+        {
+            return Err(EmulatorError::SaveState(format!(
+                "FX0A state out of range: register V{}, key {:?}",
+                wait.register, wait.pressed
+            )));
+        }
+
+        let mut memory = Bus::new(snapshot.memory.len());
+        memory.set_overflow(self.memory.overflow());
+        memory.set_logging(self.memory.is_logging());
+        memory.load(0, &snapshot.memory);
+        self.memory = memory;
+        self.mode = snapshot.mode;
+        self.quirks = snapshot.quirks;
+        self.v = snapshot.v;
+        self.i = snapshot.i;
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack;
+        self.sp = snapshot.sp;
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.framebuffer = snapshot.framebuffer.clone();
+        self.vblank_ready = snapshot.vblank_ready;
+        self.rpl = snapshot.rpl;
+        self.halted = snapshot.halted;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        // Let the frontend pick up the restored pattern
+        self.audio_dirty = true;
+        self.key_wait = snapshot.key_wait;
+        self.random = snapshot.random;
+        self.trap = None;
         Ok(())
     }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl = flags;
+    }
+
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Bits per second the audio pattern should be played at, 4000Hz at the default pitch of 64.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    pub fn memory_overflow(&self) -> MemoryOverflow {
+        self.memory.overflow()
+    }
+
+    pub fn set_memory_overflow(&mut self, overflow: MemoryOverflow) {
+        self.memory.set_overflow(overflow);
+    }
+
+    pub fn unknown_opcode_policy(&self) -> UnknownOpcodePolicy {
+        self.unknown_opcode_policy
+    }
+
+    pub fn set_unknown_opcode_policy(&mut self, policy: UnknownOpcodePolicy) {
+        self.unknown_opcode_policy = policy;
+    }
+
+    // The fault the CPU is frozen on under UnknownOpcodePolicy::Trap. Nothing executes until it is cleared.
+    pub fn trap(&self) -> Option<&EmulatorError> {
+        self.trap.as_ref()
+    }
+
+    // Resumes after a trap. PC still points at the bad instruction, so move it first or it traps again.
+    pub fn clear_trap(&mut self) {
+        self.trap = None;
+    }
+
+    pub fn random(&self) -> &Random {
+        &self.random
+    }
+
+    // Swap in a seeded generator to make runs repeatable. Do it before the ROM starts, or the numbers it
+    // already drew came from the old one.
+    pub fn set_random(&mut self, random: Random) {
+        trace!(
+            Cpu,
+            Info,
+            "Random seed {} ({:?})",
+            random.seed(),
+            random.kind()
+        );
+        self.random = random;
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    // The whole stack, only the first sp entries are in use.
+    pub fn stack(&self) -> &[u16; 16] {
+        &self.stack
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    // Debugger edits. These don't go through the watchpoint log.
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.v[register] = value;
+    }
+
+    pub fn set_index(&mut self, value: u16) {
+        self.i = value;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn poke(&mut self, address: usize, value: u8) -> Result<(), Fault> {
+        if address >= self.memory.len() {
+            return Err(Fault::MemoryOutOfBounds { address });
+        }
+        self.memory.load(address, &[value]);
+        Ok(())
+    }
+
+    // Start or stop recording data reads and writes for take_memory_accesses().
+    pub fn set_memory_watch(&mut self, enabled: bool) {
+        self.memory.set_logging(enabled);
+    }
+
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        self.memory.take_log()
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
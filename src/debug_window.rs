@@ -0,0 +1,375 @@
+// GTK debugger panel, opened next to the SDL window when "Open Debugger" is ticked in the launcher.
+// The SDL loop in start_emulator stays in charge: it pumps GTK events once a frame, collects whatever
+// the buttons asked for with take_actions() and calls update() to show the new state. If we have the
+// source (see asm::load_program) the lines around PC are shown under the disassembly.
+use gtk::prelude::*;
+use gtk::{Box as GtkBox, Button, CheckButton, Entry, Grid, Label, Orientation, SpinButton};
+use gtk4 as gtk;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use chip_8::asm::Listing;
+use chip_8::debugger::Stop;
+use chip_8::disasm::{self, Syntax};
+use chip_8::{Chip8, Debugger, Keypad};
+
+// Instructions shown before and after PC
+const LINES_BEFORE: u16 = 8;
+const LINES_AFTER: u16 = 15;
+// Bytes in the hex view
+const HEX_ROWS: usize = 16;
+const HEX_COLUMNS: usize = 16;
+// Source lines shown before and after the current one
+const SOURCE_CONTEXT: usize = 6;
+
+// What the buttons ask the main loop to do.
+#[derive(Clone, Copy)]
+pub enum Action {
+    Pause,
+    Continue,
+    Step,
+    StepFrame,
+    // Step over the unknown opcode the machine is trapped on
+    SkipOpcode,
+    BreakOnUnknownOpcode(bool),
+    ToggleBreakpoint(u16),
+    Poke(usize, u8),
+}
+
+struct DisassemblyLine {
+    address: Rc<Cell<u16>>,
+    gutter: Button,
+    text: Label,
+}
+
+pub struct DebugWindow {
+    window: gtk::Window,
+    actions: Rc<RefCell<Vec<Action>>>,
+    closed: Rc<Cell<bool>>,
+    status: Label,
+    break_on_unknown: CheckButton,
+    registers: Label,
+    stack: Label,
+    timers: Label,
+    keys: Label,
+    disassembly: Vec<DisassemblyLine>,
+    listing: Option<Listing>,
+    source: Label,
+    hex_start: SpinButton,
+    hex_labels: Vec<Label>,
+    hex_cells: Vec<Entry>,
+}
+
+impl DebugWindow {
+    pub fn new(title: &str, listing: Option<Listing>) -> DebugWindow {
+        let actions = Rc::new(RefCell::new(vec![]));
+        let closed = Rc::new(Cell::new(false));
+
+        let window = gtk::Window::builder()
+            .title(format!("Debugger: {}", title))
+            .default_width(900)
+            .default_height(600)
+            .build();
+        let closed_clone = closed.clone();
+        window.connect_close_request(move |_| {
+            closed_clone.set(true);
+            gtk::glib::Propagation::Proceed
+        });
+
+        let vbox = GtkBox::new(Orientation::Vertical, 5);
+        vbox.set_margin_top(10);
+        vbox.set_margin_bottom(10);
+        vbox.set_margin_start(10);
+        vbox.set_margin_end(10);
+
+        // Pause / step / continue
+        let button_box = GtkBox::new(Orientation::Horizontal, 5);
+        for (label, action) in [
+            ("Pause", Action::Pause),
+            ("Step", Action::Step),
+            ("Step Frame", Action::StepFrame),
+            ("Continue", Action::Continue),
+            ("Skip Opcode", Action::SkipOpcode),
+        ] {
+            let button = Button::with_label(label);
+            let actions = actions.clone();
+            button.connect_clicked(move |_| actions.borrow_mut().push(action));
+            button_box.append(&button);
+        }
+        let break_on_unknown = CheckButton::with_label("Break on unknown opcodes");
+        let actions_clone = actions.clone();
+        break_on_unknown.connect_toggled(move |check| {
+            actions_clone
+                .borrow_mut()
+                .push(Action::BreakOnUnknownOpcode(check.is_active()))
+        });
+        button_box.append(&break_on_unknown);
+        let status = Label::new(Some("Running"));
+        status.set_margin_start(10);
+        button_box.append(&status);
+        vbox.append(&button_box);
+
+        let columns = GtkBox::new(Orientation::Horizontal, 20);
+
+        // Machine state on the left
+        let state_box = GtkBox::new(Orientation::Vertical, 5);
+        let registers = monospace_label();
+        let stack = monospace_label();
+        let timers = monospace_label();
+        let keys = monospace_label();
+        for label in [&registers, &stack, &timers, &keys] {
+            state_box.append(label);
+        }
+        columns.append(&state_box);
+
+        // Disassembly in the middle, click the gutter to toggle a breakpoint
+        let disassembly_grid = Grid::new();
+        let mut disassembly = vec![];
+        for row in 0..(LINES_BEFORE + 1 + LINES_AFTER) as i32 {
+            let address = Rc::new(Cell::new(0));
+            let gutter = Button::with_label(" ");
+            gutter.add_css_class("flat");
+            let actions = actions.clone();
+            let address_clone = address.clone();
+            gutter.connect_clicked(move |_| {
+                actions
+                    .borrow_mut()
+                    .push(Action::ToggleBreakpoint(address_clone.get()))
+            });
+            let text = monospace_label();
+            disassembly_grid.attach(&gutter, 0, row, 1, 1);
+            disassembly_grid.attach(&text, 1, row, 1, 1);
+            disassembly.push(DisassemblyLine {
+                address,
+                gutter,
+                text,
+            });
+        }
+        columns.append(&disassembly_grid);
+
+        // Hex view on the right. Edit a byte and press Enter to write it.
+        let hex_box = GtkBox::new(Orientation::Vertical, 5);
+        let hex_start = SpinButton::with_range(0.0, 0xFF00 as f64, (HEX_COLUMNS * HEX_ROWS) as f64);
+        hex_start.set_value(0x200 as f64);
+        hex_box.append(&hex_start);
+        let hex_grid = Grid::new();
+        let mut hex_labels = vec![];
+        let mut hex_cells = vec![];
+        for row in 0..HEX_ROWS {
+            let label = monospace_label();
+            hex_grid.attach(&label, 0, row as i32, 1, 1);
+            hex_labels.push(label);
+            for column in 0..HEX_COLUMNS {
+                let cell = Entry::new();
+                cell.set_width_chars(2);
+                cell.set_max_width_chars(2);
+                cell.set_max_length(2);
+                cell.add_css_class("monospace");
+                let actions = actions.clone();
+                let start = hex_start.clone();
+                let offset = row * HEX_COLUMNS + column;
+                cell.connect_activate(move |cell| {
+                    if let Ok(value) = u8::from_str_radix(cell.text().trim(), 16) {
+                        let address = start.value_as_int() as usize + offset;
+                        actions.borrow_mut().push(Action::Poke(address, value));
+                    }
+                });
+                hex_grid.attach(&cell, column as i32 + 1, row as i32, 1, 1);
+                hex_cells.push(cell);
+            }
+        }
+        hex_box.append(&hex_grid);
+        columns.append(&hex_box);
+
+        vbox.append(&columns);
+
+        let source = monospace_label();
+        source.set_visible(listing.is_some());
+        vbox.append(&source);
+
+        window.set_child(Some(&vbox));
+        window.present();
+
+        DebugWindow {
+            window,
+            actions,
+            closed,
+            status,
+            break_on_unknown,
+            registers,
+            stack,
+            timers,
+            keys,
+            disassembly,
+            listing,
+            source,
+            hex_start,
+            hex_labels,
+            hex_cells,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.get()
+    }
+
+    pub fn close(&self) {
+        self.window.close();
+    }
+
+    pub fn take_actions(&self) -> Vec<Action> {
+        std::mem::take(&mut self.actions.borrow_mut())
+    }
+
+    pub fn show_stop(&self, stop: &Stop) {
+        self.status.set_text(&stop.to_string());
+    }
+
+    // Back to plain "Paused" once whatever we stopped on has been dealt with.
+    pub fn clear_stop(&self) {
+        self.status.set_text("Paused");
+    }
+
+    pub fn update(&self, cpu: &Chip8, debugger: &Debugger, keypad: &dyn Keypad) {
+        // Keep the reason we stopped on screen until we run again
+        if !debugger.is_paused() {
+            self.status.set_text("Running");
+        } else if self.status.text() == "Running" {
+            self.status.set_text("Paused");
+        }
+
+        let mut registers = String::new();
+        for (x, value) in cpu.registers().iter().enumerate() {
+            registers.push_str(&format!("V{:X}={:02X}", x, value));
+            registers.push(if x % 4 == 3 { '\n' } else { ' ' });
+        }
+        registers.push_str(&format!(
+            "I={:04X} PC={:04X} SP={:X}",
+            cpu.index(),
+            cpu.pc(),
+            cpu.sp()
+        ));
+        self.registers.set_text(&registers);
+
+        let stack: Vec<String> = cpu
+            .stack()
+            .iter()
+            .enumerate()
+            .map(|(j, address)| {
+                let marker = if j < cpu.sp() as usize { '*' } else { ' ' };
+                format!("{}{:X}: {:04X}", marker, j, address)
+            })
+            .collect();
+        self.stack.set_text(&format!("Stack\n{}", stack.join("\n")));
+
+        self.timers.set_text(&format!(
+            "DT={:02X} ST={:02X}",
+            cpu.delay_timer(),
+            cpu.sound_timer()
+        ));
+
+        // Laid out like the COSMAC VIP keypad
+        let mut keys = String::from("Keys\n");
+        for row in [
+            [1, 2, 3, 0xC],
+            [4, 5, 6, 0xD],
+            [7, 8, 9, 0xE],
+            [0xA, 0, 0xB, 0xF],
+        ] {
+            for key in row {
+                if keypad.is_pressed(key) {
+                    keys.push_str(&format!("[{:X}]", key));
+                } else {
+                    keys.push_str(&format!(" {:X} ", key));
+                }
+            }
+            keys.push('\n');
+        }
+        self.keys.set_text(&keys);
+
+        // Something else (the REPL, say) may have changed it. Only touch it when it differs, setting it
+        // fires the toggled handler.
+        if self.break_on_unknown.is_active() != debugger.break_on_unknown_opcode() {
+            self.break_on_unknown
+                .set_active(debugger.break_on_unknown_opcode());
+        }
+
+        let memory = cpu.memory();
+        let pc = cpu.pc();
+        // F000 NNNN takes up two lines' worth of bytes, so walk forward by instruction size. Going
+        // backwards from PC there's no telling where instructions start, so an F000 that would step
+        // over PC stops at it instead.
+        let mut address = pc.wrapping_sub(LINES_BEFORE * 2);
+        for line in &self.disassembly {
+            line.address.set(address);
+            line.gutter.set_label(if debugger.has_breakpoint(address) {
+                "●"
+            } else {
+                " "
+            });
+            let Some((text, size)) =
+                disasm::disassemble_at(memory, address as usize, Syntax::default())
+            else {
+                line.text.set_text("");
+                address = address.wrapping_add(2);
+                continue;
+            };
+            let marker = if address == pc { ">" } else { " " };
+            line.text.set_text(&format!(
+                "{} {:04X}  {:02X}{:02X}  {}",
+                marker,
+                address,
+                memory[address as usize],
+                memory[address as usize + 1],
+                text
+            ));
+            let next = address.wrapping_add(size);
+            address = if address < pc && next > pc { pc } else { next };
+        }
+
+        if let Some(listing) = &self.listing {
+            self.source.set_text(&source_around(listing, pc));
+        }
+
+        let start = self.hex_start.value_as_int() as usize;
+        for (row, label) in self.hex_labels.iter().enumerate() {
+            label.set_text(&format!("{:04X}", start + row * HEX_COLUMNS));
+        }
+        for (offset, cell) in self.hex_cells.iter().enumerate() {
+            // Don't overwrite what someone is typing
+            if cell.has_focus() {
+                continue;
+            }
+            match memory.get(start + offset) {
+                Some(byte) => cell.set_text(&format!("{:02X}", byte)),
+                None => cell.set_text(""),
+            }
+        }
+    }
+}
+
+fn source_around(listing: &Listing, pc: u16) -> String {
+    let Some(current) = listing.map.line(pc) else {
+        return format!("{:04X} isn't on a source line", pc);
+    };
+    let first = current.saturating_sub(SOURCE_CONTEXT).max(1);
+    let last = (current + SOURCE_CONTEXT).min(listing.lines.len());
+    let mut text = String::new();
+    for line in first..=last {
+        let marker = if line == current { '>' } else { ' ' };
+        text.push_str(&format!(
+            "{}{:>5} | {}\n",
+            marker,
+            line,
+            listing.lines[line - 1]
+        ));
+    }
+    text
+}
+
+fn monospace_label() -> Label {
+    let label = Label::new(None);
+    label.add_css_class("monospace");
+    label.set_xalign(0.0);
+    label
+}
@@ -0,0 +1,415 @@
+// Debugger. Wraps the usual run_loop calls with PC breakpoints, memory watchpoints, register conditions
+// and break-on-unknown-opcode, and can single step by instruction or by frame. It owns no machine state
+// itself, so the same Chip8 can be run with or without it.
+
+use crate::bus::{Access, MemoryAccess};
+use crate::cpu::Chip8;
+use crate::error::{EmulatorError, Fault, UnknownOpcodePolicy};
+use crate::peripherals::{Audio, Keypad};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Breaks when `register compare value` becomes true, e.g. V3 == 5.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub compare: Compare,
+    pub value: u16,
+}
+
+// Breaks after an instruction reads and/or writes `address`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: usize,
+    pub read: bool,
+    pub write: bool,
+}
+
+// Why the debugger stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    // pc is the instruction that made the access
+    Watchpoint { pc: u16, access: MemoryAccess },
+    // index into conditions()
+    Condition(usize),
+    UnknownOpcode { pc: u16, opcode: u16 },
+    Halted,
+    Error(EmulatorError),
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // Each with whether it held after the last instruction, so we only stop when it becomes true.
+    conditions: Vec<(Condition, bool)>,
+    break_on_unknown_opcode: bool,
+    paused: bool,
+    // Set when resuming or stepping so we can get off the breakpoint we stopped on.
+    skip_breakpoint: bool,
+}
+
+impl Register {
+    pub fn read(&self, cpu: &Chip8) -> u16 {
+        match self {
+            Register::V(x) => cpu.registers()[*x] as u16,
+            Register::I => cpu.index(),
+            Register::Pc => cpu.pc(),
+            Register::Sp => cpu.sp() as u16,
+            Register::DelayTimer => cpu.delay_timer() as u16,
+            Register::SoundTimer => cpu.sound_timer() as u16,
+        }
+    }
+
+    // v0-vf, i, pc, sp, dt, st
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "i" => Some(Register::I),
+            "pc" => Some(Register::Pc),
+            "sp" => Some(Register::Sp),
+            "dt" => Some(Register::DelayTimer),
+            "st" => Some(Register::SoundTimer),
+            _ => {
+                let x = name.strip_prefix('v').filter(|x| x.len() == 1)?;
+                usize::from_str_radix(x, 16).ok().map(Register::V)
+            }
+        }
+    }
+}
+
+impl Compare {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "==" => Some(Compare::Eq),
+            "!=" => Some(Compare::Ne),
+            "<" => Some(Compare::Lt),
+            "<=" => Some(Compare::Le),
+            ">" => Some(Compare::Gt),
+            ">=" => Some(Compare::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Compare::Eq => "==",
+            Compare::Ne => "!=",
+            Compare::Lt => "<",
+            Compare::Le => "<=",
+            Compare::Gt => ">",
+            Compare::Ge => ">=",
+        }
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &Chip8) -> bool {
+        let value = self.register.read(cpu);
+        match self.compare {
+            Compare::Eq => value == self.value,
+            Compare::Ne => value != self.value,
+            Compare::Lt => value < self.value,
+            Compare::Le => value <= self.value,
+            Compare::Gt => value > self.value,
+            Compare::Ge => value >= self.value,
+        }
+    }
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        access.address == self.address
+            && match access.access {
+                Access::Read => self.read,
+                Access::Write => self.write,
+            }
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.skip_breakpoint = true;
+    }
+
+    // Returns false if it was already set.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn toggle_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.remove(&address) {
+            self.breakpoints.insert(address);
+        }
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push((condition, false));
+    }
+
+    pub fn remove_condition(&mut self, index: usize) -> Option<Condition> {
+        (index < self.conditions.len()).then(|| self.conditions.remove(index).0)
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    pub fn break_on_unknown_opcode(&self) -> bool {
+        self.break_on_unknown_opcode
+    }
+
+    pub fn set_break_on_unknown_opcode(&mut self, enabled: bool) {
+        self.break_on_unknown_opcode = enabled;
+    }
+
+    // Gets a machine that stopped on an unknown opcode going again by stepping over it, since clearing
+    // the trap alone would just fetch the same opcode again. Returns false if there was no trap.
+    pub fn skip_unknown_opcode(&mut self, cpu: &mut Chip8) -> bool {
+        if cpu.trap().is_none() {
+            return false;
+        }
+        cpu.set_pc(cpu.pc().wrapping_add(2));
+        cpu.clear_trap();
+        true
+    }
+
+    // Exactly one instruction, paused or not. Leaves the debugger paused.
+    pub fn step_instruction(
+        &mut self,
+        cpu: &mut Chip8,
+        keypad: &dyn Keypad,
+        audio: &mut dyn Audio,
+    ) -> Option<Stop> {
+        self.paused = true;
+        self.skip_breakpoint = true;
+        self.execute(cpu, keypad, audio)
+    }
+
+    // One 60Hz frame, paused or not: tick the timers, then up to `instructions` instructions. Leaves the
+    // debugger paused.
+    pub fn step_frame(
+        &mut self,
+        cpu: &mut Chip8,
+        keypad: &dyn Keypad,
+        audio: &mut dyn Audio,
+        instructions: u32,
+    ) -> Option<Stop> {
+        self.paused = true;
+        self.skip_breakpoint = true;
+        self.frame(cpu, keypad, audio, instructions)
+    }
+
+    // What the main loop calls instead of ticking the timers and calling run_loop itself. Does nothing
+    // while paused, and pauses when something is hit.
+    pub fn run_frame(
+        &mut self,
+        cpu: &mut Chip8,
+        keypad: &dyn Keypad,
+        audio: &mut dyn Audio,
+        instructions: u32,
+    ) -> Option<Stop> {
+        if self.paused {
+            return None;
+        }
+        let stop = self.frame(cpu, keypad, audio, instructions);
+        if stop.is_some() {
+            self.paused = true;
+        }
+        stop
+    }
+
+    fn frame(
+        &mut self,
+        cpu: &mut Chip8,
+        keypad: &dyn Keypad,
+        audio: &mut dyn Audio,
+        instructions: u32,
+    ) -> Option<Stop> {
+        cpu.tick_timers();
+        for _ in 0..instructions {
+            if let Some(stop) = self.execute(cpu, keypad, audio) {
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    fn execute(
+        &mut self,
+        cpu: &mut Chip8,
+        keypad: &dyn Keypad,
+        audio: &mut dyn Audio,
+    ) -> Option<Stop> {
+        let pc = cpu.pc();
+        if !std::mem::take(&mut self.skip_breakpoint) && self.breakpoints.contains(&pc) {
+            return Some(Stop::Breakpoint(pc));
+        }
+        if cpu.is_halted() {
+            return Some(Stop::Halted);
+        }
+
+        // Borrow the trap policy for this one instruction so the machine's own setting is left alone.
+        let policy = cpu.unknown_opcode_policy();
+        if self.break_on_unknown_opcode {
+            cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Trap);
+        }
+        cpu.set_memory_watch(!self.watchpoints.is_empty());
+        let result = cpu.run_loop(keypad, audio);
+        cpu.set_unknown_opcode_policy(policy);
+        if let Err(e) = result {
+            return Some(Stop::Error(e));
+        }
+
+        if let Some(EmulatorError::Cpu {
+            pc,
+            opcode,
+            fault: Fault::UnknownOpcode,
+        }) = cpu.trap()
+        {
+            return Some(Stop::UnknownOpcode {
+                pc: *pc,
+                opcode: *opcode,
+            });
+        }
+
+        let accesses = cpu.take_memory_accesses();
+        if let Some(access) = accesses
+            .iter()
+            .find(|access| self.watchpoints.iter().any(|w| w.matches(access)))
+        {
+            return Some(Stop::Watchpoint {
+                pc,
+                access: *access,
+            });
+        }
+
+        let mut hit = None;
+        for (j, (condition, held)) in self.conditions.iter_mut().enumerate() {
+            let holds = condition.holds(cpu);
+            if holds && !*held && hit.is_none() {
+                hit = Some(Stop::Condition(j));
+            }
+            *held = holds;
+        }
+        hit
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:X}",
+            self.register,
+            self.compare.symbol(),
+            self.value
+        )
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{:04X} ({})", self.address, kind)
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(pc) => write!(f, "Breakpoint at {:04X}", pc),
+            Stop::Watchpoint { pc, access } => write!(
+                f,
+                "Watchpoint: {:04X} {} {:04X}",
+                pc,
+                match access.access {
+                    Access::Read => "read",
+                    Access::Write => "wrote",
+                },
+                access.address
+            ),
+            Stop::Condition(j) => write!(f, "Condition {} is true", j),
+            Stop::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:04X}", opcode, pc)
+            }
+            Stop::Halted => write!(f, "Halted"),
+            Stop::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,410 @@
+// Disassembler. Follows the code from the entry point the way the CPU would (jumps, calls, both sides of
+// every skip) so whatever it can't reach is treated as data. Jump, call and I targets get labels, and
+// data that I points at is laid out one byte per line with a picture of the bits since it's usually a
+// sprite.
+//
+// Two syntaxes: Octo, which the assembler can read back in, and the classic Cowgod style mnemonics.
+
+use crate::cpu::{Mode, PROGRAM_START};
+use crate::instruction::{Instruction, decode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// Octo unless someone asks otherwise, everywhere: chip8-disasm, the REPL and the debugger panel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Octo,
+    Classic,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub syntax: Syntax,
+    pub mode: Mode,
+    // Address the ROM is loaded at
+    pub base: u16,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            syntax: Syntax::default(),
+            mode: Mode::XoChip,
+            base: PROGRAM_START,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    // In order of preference when one address is several things
+    Data,
+    Jump,
+    Call,
+}
+
+// One instruction on its own, addresses as plain numbers. F000 NNNN needs the word after it, see
+// disassemble_at().
+pub fn mnemonic(instruction: &Instruction, syntax: Syntax) -> String {
+    render(instruction, None, syntax, &BTreeMap::new())
+}
+
+// The instruction at `address` and its size in bytes, None past the end of memory.
+pub fn disassemble_at(memory: &[u8], address: usize, syntax: Syntax) -> Option<(String, u16)> {
+    let opcode = word(memory, address)?;
+    let instruction = decode(opcode);
+    let long = match instruction {
+        Instruction::SetIndexLong => word(memory, address + 2),
+        _ => None,
+    };
+    Some((
+        render(&instruction, long, syntax, &BTreeMap::new()),
+        instruction.size(),
+    ))
+}
+
+// An annotated listing of a whole ROM.
+pub fn disassemble(rom: &[u8], options: &Options) -> String {
+    let base = options.base as usize;
+    let end = base + rom.len();
+    let byte = |address: usize| rom[address - base];
+    let word_at = |address: usize| {
+        (address >= base && address + 1 < end)
+            .then(|| ((byte(address) as u16) << 8) | byte(address + 1) as u16)
+    };
+
+    // Walk the code
+    let mut starts = BTreeSet::new();
+    let mut covered = vec![false; rom.len()];
+    let mut targets: BTreeMap<u16, LabelKind> = BTreeMap::new();
+    let mut pending = vec![base];
+    while let Some(address) = pending.pop() {
+        if starts.contains(&address) {
+            continue;
+        }
+        let Some(opcode) = word_at(address) else {
+            continue;
+        };
+        let instruction = decode(opcode);
+        if !options.mode.supports(&instruction)
+            || matches!(
+                instruction,
+                Instruction::Unknown(_) | Instruction::MachineCode(_)
+            )
+        {
+            continue;
+        }
+        let size = instruction.size() as usize;
+        let long = match instruction {
+            Instruction::SetIndexLong => match word_at(address + 2) {
+                Some(long) => Some(long),
+                None => continue,
+            },
+            _ => None,
+        };
+        starts.insert(address);
+        for offset in 0..size {
+            covered[address + offset - base] = true;
+        }
+
+        let next = address + size;
+        let mut label = |target: u16, kind: LabelKind| {
+            let entry = targets.entry(target).or_insert(kind);
+            *entry = (*entry).max(kind);
+        };
+        match instruction {
+            Instruction::Jump(target) => {
+                label(target, LabelKind::Jump);
+                pending.push(target as usize);
+            }
+            Instruction::Call(target) => {
+                label(target, LabelKind::Call);
+                pending.push(target as usize);
+                pending.push(next);
+            }
+            // Usually a jump table, so the base is at least code
+            Instruction::JumpOffset { nnn, .. } => {
+                label(nnn, LabelKind::Jump);
+                pending.push(nnn as usize);
+            }
+            Instruction::Return | Instruction::Exit => {}
+            _ if instruction.is_skip() => {
+                pending.push(next);
+                let skipped = word_at(next).map_or(2, |opcode| decode(opcode).size() as usize);
+                pending.push(next + skipped);
+            }
+            Instruction::SetIndex(target) => {
+                label(target, LabelKind::Data);
+                pending.push(next);
+            }
+            _ => {
+                if let Some(target) = long {
+                    label(target, LabelKind::Data);
+                }
+                pending.push(next);
+            }
+        }
+    }
+
+    // Only keep labels we can actually put somewhere: at an instruction, or in the data between them
+    let labels: BTreeMap<u16, String> = targets
+        .iter()
+        .filter(|(address, _)| {
+            let address = **address as usize;
+            starts.contains(&address)
+                || (address >= base && address < end && !covered[address - base])
+        })
+        .map(|(address, kind)| {
+            let name = match kind {
+                _ if *address as usize == base && options.syntax == Syntax::Octo => {
+                    "main".to_string()
+                }
+                LabelKind::Call => format!("sub_{:04X}", address),
+                LabelKind::Jump => format!("label_{:04X}", address),
+                LabelKind::Data => format!("data_{:04X}", address),
+            };
+            (*address, name)
+        })
+        .collect();
+
+    let comment = match options.syntax {
+        Syntax::Octo => '#',
+        Syntax::Classic => ';',
+    };
+    let mut out = String::new();
+    if options.syntax == Syntax::Octo && !labels.contains_key(&options.base) {
+        let _ = writeln!(out, ": main");
+    }
+    let mut address = base;
+    let mut sprite = false;
+    while address < end {
+        if let Some(name) = labels.get(&(address as u16)) {
+            match options.syntax {
+                Syntax::Octo => {
+                    let _ = writeln!(out, ": {}", name);
+                }
+                Syntax::Classic => {
+                    let _ = writeln!(out, "{}:", name);
+                }
+            }
+            sprite = targets.get(&(address as u16)) == Some(&LabelKind::Data);
+        }
+
+        if starts.contains(&address) {
+            let opcode = word_at(address).unwrap_or_default();
+            let instruction = decode(opcode);
+            let long = match instruction {
+                Instruction::SetIndexLong => word_at(address + 2),
+                _ => None,
+            };
+            let text = render(&instruction, long, options.syntax, &labels);
+            let _ = writeln!(
+                out,
+                "\t{:<28}{} {:04X}: {:04X}",
+                text, comment, address, opcode
+            );
+            address += instruction.size() as usize;
+            sprite = false;
+            continue;
+        }
+
+        // Data up to the next instruction or label
+        let mut run = vec![byte(address)];
+        let limit = if sprite { 1 } else { 8 };
+        while run.len() < limit
+            && address + run.len() < end
+            && !covered[address + run.len() - base]
+            && !labels.contains_key(&((address + run.len()) as u16))
+        {
+            run.push(byte(address + run.len()));
+        }
+        let text = match options.syntax {
+            Syntax::Octo => run
+                .iter()
+                .map(|b| format!("0x{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Syntax::Classic => format!(
+                "DB {}",
+                run.iter()
+                    .map(|b| format!("#{:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let picture = if sprite {
+            format!(
+                " {}",
+                (0..8)
+                    .map(|bit| if run[0] & (0x80 >> bit) != 0 {
+                        '#'
+                    } else {
+                        '.'
+                    })
+                    .collect::<String>()
+            )
+        } else {
+            String::new()
+        };
+        let _ = writeln!(out, "\t{:<28}{} {:04X}{}", text, comment, address, picture);
+        address += run.len();
+    }
+    out
+}
+
+fn word(memory: &[u8], address: usize) -> Option<u16> {
+    let bytes = memory.get(address..address + 2)?;
+    Some(((bytes[0] as u16) << 8) | bytes[1] as u16)
+}
+
+fn render(
+    instruction: &Instruction,
+    long: Option<u16>,
+    syntax: Syntax,
+    labels: &BTreeMap<u16, String>,
+) -> String {
+    match syntax {
+        Syntax::Octo => octo(instruction, long, labels),
+        Syntax::Classic => classic(instruction, long, labels),
+    }
+}
+
+fn octo(instruction: &Instruction, long: Option<u16>, labels: &BTreeMap<u16, String>) -> String {
+    let target = |address: u16| {
+        labels
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| format!("0x{:X}", address))
+    };
+    match *instruction {
+        Instruction::Clear => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Lores => "lores".to_string(),
+        Instruction::Hires => "hires".to_string(),
+        Instruction::MachineCode(nnn) => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+        Instruction::Jump(nnn) => format!("jump {}", target(nnn)),
+        Instruction::Call(nnn) => match labels.get(&nnn) {
+            Some(name) => name.clone(),
+            None => format!(":call 0x{:X}", nnn),
+        },
+        // Octo writes skips as the condition for running the next instruction, the opposite of
+        // the condition for skipping it
+        Instruction::SkipIfEqual { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+        Instruction::SkipIfNotEqual { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+        Instruction::SkipIfRegistersEqual { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Instruction::Set { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+        Instruction::Add { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+        Instruction::Copy { x, y } => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddRegisters { x, y } => format!("v{:x} += v{:x}", x, y),
+        Instruction::Subtract { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubtractReversed { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SkipIfRegistersNotEqual { x, y } => {
+            format!("if v{:x} == v{:x} then", x, y)
+        }
+        Instruction::SetIndex(nnn) => format!("i := {}", target(nnn)),
+        Instruction::JumpOffset { nnn, .. } => format!("jump0 {}", target(nnn)),
+        Instruction::Random { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+        Instruction::Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::SkipIfKey(x) => format!("if v{:x} -key then", x),
+        Instruction::SkipIfNotKey(x) => format!("if v{:x} key then", x),
+        Instruction::SetIndexLong => match long {
+            Some(nnnn) => format!("i := long {}", target(nnnn)),
+            None => "i := long ?".to_string(),
+        },
+        Instruction::Plane(n) => format!("plane {}", n),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::GetDelay(x) => format!("v{:x} := delay", x),
+        Instruction::WaitKey(x) => format!("v{:x} := key", x),
+        Instruction::SetDelay(x) => format!("delay := v{:x}", x),
+        Instruction::SetSound(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddIndex(x) => format!("i += v{:x}", x),
+        Instruction::Font(x) => format!("i := hex v{:x}", x),
+        Instruction::BigFont(x) => format!("i := bighex v{:x}", x),
+        Instruction::Bcd(x) => format!("bcd v{:x}", x),
+        Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+        Instruction::Store(x) => format!("save v{:x}", x),
+        Instruction::Load(x) => format!("load v{:x}", x),
+        Instruction::SaveFlags(x) => format!("saveflags v{:x}", x),
+        Instruction::LoadFlags(x) => format!("loadflags v{:x}", x),
+        Instruction::Unknown(opcode) => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+    }
+}
+
+fn classic(instruction: &Instruction, long: Option<u16>, labels: &BTreeMap<u16, String>) -> String {
+    let target = |address: u16| {
+        labels
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| format!("{:03X}", address))
+    };
+    match *instruction {
+        Instruction::Clear => "CLS".to_string(),
+        Instruction::Return => "RET".to_string(),
+        Instruction::ScrollDown(n) => format!("SCD {}", n),
+        Instruction::ScrollUp(n) => format!("SCU {}", n),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Lores => "LOW".to_string(),
+        Instruction::Hires => "HIGH".to_string(),
+        Instruction::MachineCode(nnn) => format!("SYS {:03X}", nnn),
+        Instruction::Jump(nnn) => format!("JP {}", target(nnn)),
+        Instruction::Call(nnn) => format!("CALL {}", target(nnn)),
+        Instruction::SkipIfEqual { x, nn } => format!("SE V{:X}, {:02X}", x, nn),
+        Instruction::SkipIfNotEqual { x, nn } => format!("SNE V{:X}, {:02X}", x, nn),
+        Instruction::SkipIfRegistersEqual { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::SaveRange { x, y } => format!("SAVE V{:X}-V{:X}", x, y),
+        Instruction::LoadRange { x, y } => format!("LOAD V{:X}-V{:X}", x, y),
+        Instruction::Set { x, nn } => format!("LD V{:X}, {:02X}", x, nn),
+        Instruction::Add { x, nn } => format!("ADD V{:X}, {:02X}", x, nn),
+        Instruction::Copy { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddRegisters { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Subtract { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubtractReversed { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SkipIfRegistersNotEqual { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::SetIndex(nnn) => format!("LD I, {}", target(nnn)),
+        Instruction::JumpOffset { nnn, .. } => format!("JP V0, {}", target(nnn)),
+        Instruction::Random { x, nn } => format!("RND V{:X}, {:02X}", x, nn),
+        Instruction::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkipIfKey(x) => format!("SKP V{:X}", x),
+        Instruction::SkipIfNotKey(x) => format!("SKNP V{:X}", x),
+        Instruction::SetIndexLong => match long {
+            Some(nnnn) => format!("LD I, LONG {}", target(nnnn)),
+            None => "LD I, LONG".to_string(),
+        },
+        Instruction::Plane(n) => format!("PLANE {}", n),
+        Instruction::Audio => "AUDIO".to_string(),
+        Instruction::GetDelay(x) => format!("LD V{:X}, DT", x),
+        Instruction::WaitKey(x) => format!("LD V{:X}, K", x),
+        Instruction::SetDelay(x) => format!("LD DT, V{:X}", x),
+        Instruction::SetSound(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddIndex(x) => format!("ADD I, V{:X}", x),
+        Instruction::Font(x) => format!("LD F, V{:X}", x),
+        Instruction::BigFont(x) => format!("LD HF, V{:X}", x),
+        Instruction::Bcd(x) => format!("LD B, V{:X}", x),
+        Instruction::Pitch(x) => format!("PITCH V{:X}", x),
+        Instruction::Store(x) => format!("LD [I], V{:X}", x),
+        Instruction::Load(x) => format!("LD V{:X}, [I]", x),
+        Instruction::SaveFlags(x) => format!("LD R, V{:X}", x),
+        Instruction::LoadFlags(x) => format!("LD V{:X}, R", x),
+        Instruction::Unknown(opcode) => format!("DW {:04X}", opcode),
+    }
+}
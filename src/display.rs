@@ -0,0 +1,227 @@
+// The CHIP-8 screen as plain data. This used to live inside the SDL Window, now the frontend only gets
+// to look at it when it is time to present a frame.
+
+use crate::util::is_bit_set;
+use serde::{Deserialize, Serialize};
+
+// Classic 64x32. SUPER-CHIP can switch to hi-res 128x64.
+// Each pixel is a colour index 0-3: bit 0 is plane 1, bit 1 is plane 2 (XO-CHIP). Plain CHIP-8 only ever
+// touches plane 1, so for it a pixel is simply on (1) or off (0).
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// 0xRRGGBB for each pixel value: off, plane 1 (plain CHIP-8 "on"), plane 2 only and both planes.
+pub const PALETTE: [u32; 4] = [0x81c784, 0x29302a, 0x4b8a50, 0xd7f0d8];
+
+// (name, label, colours). The first one is PALETTE, the green LCD look this emulator always had. All
+// four colours matter for XO-CHIP, plain CHIP-8 and SUPER-CHIP only use the first two.
+pub const PALETTES: [(&str, &str, [u32; 4]); 5] = [
+    ("lcd", "Green LCD", PALETTE),
+    (
+        "classic",
+        "Classic (White on Black)",
+        [0x000000, 0xffffff, 0x808080, 0xc0c0c0],
+    ),
+    ("amber", "Amber", [0x1c1000, 0xffb000, 0x8a5a00, 0xffe0a0]),
+    (
+        "phosphor",
+        "Green Phosphor",
+        [0x001400, 0x33ff66, 0x128c32, 0xb0ffc8],
+    ),
+    (
+        "gameboy",
+        "Game Boy",
+        [0x9bbc0f, 0x0f380f, 0x306230, 0x8bac0f],
+    ),
+];
+
+pub fn palette(name: &str) -> Option<[u32; 4]> {
+    PALETTES
+        .iter()
+        .find(|(preset, _, _)| preset.eq_ignore_ascii_case(name))
+        .map(|(_, _, colours)| *colours)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    #[serde(with = "crate::savestate::hex_bytes")]
+    pixels: Vec<u8>,
+    // Planes selected by XO-CHIP FN01. Drawing, clearing and scrolling only touch these.
+    planes: u8,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: vec![0; WIDTH * HEIGHT],
+            planes: 1,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    // Switching resolution wipes the screen, same as Octo and most modern SUPER-CHIP interpreters.
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (WIDTH, HEIGHT)
+        };
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3;
+    }
+
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        for p in self.pixels.iter_mut() {
+            *p &= keep;
+        }
+    }
+
+    // Number of bytes DXYN reads for a sprite: XO-CHIP stores one copy of the sprite per selected plane.
+    pub fn sprite_bytes(&self, rows: usize, sprite_width: usize) -> usize {
+        rows * sprite_width / 8 * self.planes.count_ones() as usize
+    }
+
+    // XORs the sprite onto the screen and returns 1 if any lit pixel got switched off.
+    // sprite_width is 8 for normal sprites (one byte per row) or 16 for SUPER-CHIP DXY0 (two bytes per row).
+    // With both XO-CHIP planes selected the bytes hold the plane 1 sprite followed by the plane 2 one.
+    // The starting position always wraps, with clip set the rest of the sprite is cut off at the edges.
+    pub fn draw(
+        &mut self,
+        bytes: &[u8],
+        sprite_width: usize,
+        init_x: u8,
+        init_y: u8,
+        clip: bool,
+    ) -> u8 {
+        let mut collision: u8 = 0;
+        let init_x = init_x as usize % self.width;
+        let init_y = init_y as usize % self.height;
+        let bytes_per_row = sprite_width / 8;
+        let plane_count = self.planes.count_ones() as usize;
+        if plane_count == 0 {
+            return 0;
+        }
+        let bytes_per_plane = bytes.len() / plane_count;
+        let mut sprites = bytes.chunks(bytes_per_plane.max(1));
+        for plane in [1u8, 2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            let Some(sprite) = sprites.next() else {
+                break;
+            };
+            for (k, row) in sprite.chunks(bytes_per_row).enumerate() {
+                for (byte_idx, b) in row.iter().enumerate() {
+                    for bit in 0..8 {
+                        if !is_bit_set(b, (8 - bit - 1) as u8) {
+                            continue;
+                        }
+                        let j = byte_idx * 8 + bit;
+                        if clip && (init_x + j >= self.width || init_y + k >= self.height) {
+                            continue;
+                        }
+                        let x = (init_x + j) % self.width;
+                        let y = (init_y + k) % self.height;
+                        let coord = (y * self.width) + x;
+                        if self.pixels[coord] & plane != 0 {
+                            collision = 1;
+                        }
+                        self.pixels[coord] ^= plane;
+                    }
+                }
+            }
+        }
+        collision
+    }
+
+    // SUPER-CHIP/XO-CHIP scrolling of the selected planes. Pixels scrolled off the edge are lost, new
+    // rows/columns come in blank.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.shift(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.shift(0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.shift(n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.shift(-(n as isize), 0);
+    }
+
+    fn shift(&mut self, dx: isize, dy: isize) {
+        let mask = self.planes;
+        let old = self.pixels.clone();
+        let (w, h) = (self.width as isize, self.height as isize);
+        for y in 0..h {
+            for x in 0..w {
+                let (sx, sy) = (x - dx, y - dy);
+                let src = if sx >= 0 && sx < w && sy >= 0 && sy < h {
+                    old[(sy * w + sx) as usize]
+                } else {
+                    0
+                };
+                let idx = (y * w + x) as usize;
+                self.pixels[idx] = (old[idx] & !mask) | (src & mask);
+            }
+        }
+    }
+
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x] != 0
+    }
+
+    // Colour index 0-3, see the comment at the top.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // For the rewind buffer, which stores the pixels as a delta against the next frame.
+    pub(crate) fn take_pixels(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pixels)
+    }
+
+    pub(crate) fn set_pixels(&mut self, pixels: Vec<u8>) {
+        self.pixels = pixels;
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
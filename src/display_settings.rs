@@ -0,0 +1,97 @@
+// How the emulated screen looks, kept in display.json next to keymap.json:
+//
+//   { "palette": "amber", "custom": ["#000000", "#ffffff", "#808080", "#c0c0c0"],
+//     "mode": "phosphor", "phosphor_decay": 0.6 }
+//
+// "palette" is one of the presets in chip_8::display::PALETTES or "custom", which uses the four colours
+// the launcher's colour pickers were last set to: off, plane 1, plane 2 and both planes. "mode" is
+// "vblank", the framebuffer exactly as it is at each 60Hz vblank, or "phosphor", which blends frames
+// together against flicker (see chip_8::phosphor).
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use chip_8::display::{self, PALETTE, PALETTES};
+use chip_8::phosphor;
+use chip_8::romdb::parse_colour;
+
+const DISPLAY_FILE: &str = "display.json";
+
+pub const CUSTOM: &str = "custom";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayMode {
+    #[default]
+    Vblank,
+    Phosphor,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    pub palette: String,
+    pub custom: [String; 4],
+    pub mode: DisplayMode,
+    pub phosphor_decay: f32,
+}
+
+impl DisplaySettings {
+    // display.json, or the defaults. Like the keymap, a broken file only gets a warning.
+    pub fn load() -> DisplaySettings {
+        let Ok(data) = fs::read_to_string(DISPLAY_FILE) else {
+            return DisplaySettings::default();
+        };
+        serde_json::from_str(&data).unwrap_or_else(|e| {
+            eprintln!("Ignoring {}: {}", DISPLAY_FILE, e);
+            DisplaySettings::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(DISPLAY_FILE, data)
+            .map_err(|e| format!("Could not write {}: {}", DISPLAY_FILE, e))
+    }
+
+    // The colours for a palette name, with "custom" meaning the saved custom colours.
+    pub fn colours_for(&self, name: &str) -> Option<[u32; 4]> {
+        if name.eq_ignore_ascii_case(CUSTOM) {
+            Some(self.custom_colours())
+        } else {
+            display::palette(name)
+        }
+    }
+
+    pub fn colours(&self) -> [u32; 4] {
+        self.colours_for(&self.palette).unwrap_or_else(|| {
+            eprintln!("Unknown palette {:?} in {}", self.palette, DISPLAY_FILE);
+            PALETTE
+        })
+    }
+
+    pub fn custom_colours(&self) -> [u32; 4] {
+        std::array::from_fn(|i| parse_colour(&self.custom[i]).unwrap_or(PALETTE[i]))
+    }
+
+    // How much glow the window keeps per frame, None for plain frames.
+    pub fn phosphor_decay(&self) -> Option<f32> {
+        (self.mode == DisplayMode::Phosphor).then_some(self.phosphor_decay)
+    }
+
+    pub fn set_custom_colours(&mut self, colours: [u32; 4]) {
+        self.custom = colours.map(|colour| format!("#{:06x}", colour));
+    }
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        let mut settings = DisplaySettings {
+            palette: PALETTES[0].0.to_string(),
+            custom: Default::default(),
+            mode: DisplayMode::default(),
+            phosphor_decay: phosphor::DEFAULT_DECAY,
+        };
+        settings.set_custom_colours(PALETTE);
+        settings
+    }
+}
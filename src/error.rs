@@ -0,0 +1,73 @@
+// Everything that can go wrong inside the core. Callers can match on these instead of parsing strings.
+
+use std::fmt;
+
+// What went wrong while executing an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    UnknownOpcode,
+    MemoryOutOfBounds { address: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EmulatorError {
+    // pc is the address of the offending instruction, not the one after it.
+    Cpu { pc: u16, opcode: u16, fault: Fault },
+    RomTooLarge { size: usize, max: usize },
+    Io(String),
+    // A save state file that can't be used: corrupt, or from a different version.
+    SaveState(String),
+    // Whatever the Display/Audio implementation reported.
+    Frontend(String),
+}
+
+// What to do when we fetch something that isn't an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum UnknownOpcodePolicy {
+    // Stop with EmulatorError::Cpu.
+    #[default]
+    Halt,
+    // Treat it as a no-op and carry on.
+    Skip,
+    // Freeze on the instruction so a debugger can look at it, see Chip8::trap().
+    Trap,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "Stack overflow"),
+            Fault::StackUnderflow => write!(f, "Stack underflow"),
+            Fault::UnknownOpcode => write!(f, "Unknown opcode"),
+            Fault::MemoryOutOfBounds { address } => {
+                write!(f, "Memory access out of bounds at {:04X}", address)
+            }
+        }
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::Cpu { pc, opcode, fault } => {
+                write!(f, "{} (opcode {:04X} at PC={:04X})", fault, opcode, pc)
+            }
+            EmulatorError::RomTooLarge { size, max } => {
+                write!(f, "ROM is too big ({} bytes, at most {} fit)", size, max)
+            }
+            EmulatorError::Io(e) => write!(f, "I/O error: {}", e),
+            EmulatorError::SaveState(e) => write!(f, "Bad save state: {}", e),
+            EmulatorError::Frontend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+impl From<std::io::Error> for EmulatorError {
+    fn from(e: std::io::Error) -> Self {
+        EmulatorError::Io(e.to_string())
+    }
+}
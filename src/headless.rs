@@ -0,0 +1,224 @@
+// Running a ROM with no window, speakers or keyboard, for `chip_8 run --headless`, CI and the tests.
+// Frames go the same way as in the SDL frontend: timers tick once, then instructions_per_frame
+// instructions run. Keys come from an InputScript instead of a keyboard.
+
+use crate::cpu::Chip8;
+use crate::display::Framebuffer;
+use crate::error::EmulatorError;
+use crate::peripherals::NullAudio;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Which keys go down and up on which frame.
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    events: Vec<KeyEvent>,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        InputScript::default()
+    }
+
+    pub fn push(&mut self, event: KeyEvent) {
+        // Kept sorted by frame, events on the same frame stay in the order they were added
+        let at = self.events.partition_point(|e| e.frame <= event.frame);
+        self.events.insert(at, event);
+    }
+
+    // Holds `key` down for `frames` frames starting at `frame`. Returns false, and adds nothing, if the
+    // key would have to be let go after the last frame a u64 can count.
+    pub fn press(&mut self, frame: u64, key: u8, frames: u64) -> bool {
+        let Some(release) = frame.checked_add(frames.max(1)) else {
+            return false;
+        };
+        self.push(KeyEvent {
+            frame,
+            key,
+            pressed: true,
+        });
+        self.push(KeyEvent {
+            frame: release,
+            key,
+            pressed: false,
+        });
+        true
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    // One event per line, # starts a comment. Keys are hex, frames decimal:
+    //   120 5 down
+    //   130 5 up
+    //   200 A 10      (hold A for 10 frames)
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut script = InputScript::new();
+        for (j, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let bad_line = || {
+                format!(
+                    "Input script line {}: expected FRAME KEY down|up|FRAMES",
+                    j + 1
+                )
+            };
+            let [frame, key, action] = words[..] else {
+                return Err(bad_line());
+            };
+            let frame = frame.parse::<u64>().map_err(|_| bad_line())?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|k| *k < 16)
+                .ok_or_else(bad_line)?;
+            match action {
+                "down" | "up" => script.push(KeyEvent {
+                    frame,
+                    key,
+                    pressed: action == "down",
+                }),
+                _ => {
+                    let frames = action.parse().map_err(|_| bad_line())?;
+                    if !script.press(frame, key, frames) {
+                        return Err(bad_line());
+                    }
+                }
+            }
+        }
+        Ok(script)
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<InputScript, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        InputScript::parse(&text)
+    }
+
+    // Applies everything that happens on `frame`.
+    pub fn apply(&self, frame: u64, keys: &mut [bool; 16]) {
+        let start = self.events.partition_point(|e| e.frame < frame);
+        for event in self.events[start..].iter().take_while(|e| e.frame == frame) {
+            keys[event.key as usize] = event.pressed;
+        }
+    }
+}
+
+// A run that failed part way, with how many frames had finished before the frame that failed.
+#[derive(Debug)]
+pub struct Stopped {
+    pub frames: u64,
+    pub error: EmulatorError,
+}
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (after {} frames)", self.error, self.frames)
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+// Runs up to `frames` frames and returns how many actually ran, which is fewer if the ROM exits (00FD).
+// On an error the machine is left as it was when it happened, so it can still be dumped.
+pub fn run(
+    cpu: &mut Chip8,
+    frames: u64,
+    instructions_per_frame: u32,
+    input: &InputScript,
+) -> Result<u64, Stopped> {
+    let mut keys = [false; 16];
+    let mut audio = NullAudio;
+    for frame in 0..frames {
+        if cpu.is_halted() {
+            return Ok(frame);
+        }
+        input.apply(frame, &mut keys);
+        cpu.tick_timers();
+        for _ in 0..instructions_per_frame {
+            cpu.run_loop(&keys, &mut audio).map_err(|error| Stopped {
+                frames: frame,
+                error,
+            })?;
+        }
+    }
+    Ok(frames)
+}
+
+// The screen as text, one character per pixel: ' ' off, '#' plane 1, '+' plane 2, '@' both.
+pub fn screen_text(framebuffer: &Framebuffer) -> String {
+    let mut text = String::new();
+    for y in 0..framebuffer.height() {
+        text.push('|');
+        for x in 0..framebuffer.width() {
+            text.push(match framebuffer.pixel(x, y) {
+                0 => ' ',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            });
+        }
+        text.push_str("|\n");
+    }
+    text
+}
+
+pub fn registers_text(cpu: &Chip8) -> String {
+    let mut text = String::new();
+    for (x, value) in cpu.registers().iter().enumerate() {
+        text.push_str(&format!("V{:X}={:02X}\n", x, value));
+    }
+    let stack: Vec<String> = cpu.stack()[..cpu.sp() as usize]
+        .iter()
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    text.push_str(&format!(
+        "I={:04X}\nPC={:04X}\nSP={:X}\nDT={:02X}\nST={:02X}\nstack={}\n",
+        cpu.index(),
+        cpu.pc(),
+        cpu.sp(),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        stack.join(" ")
+    ));
+    text
+}
+
+// Writes the screen as a PNG in `palette`'s colours, each pixel blown up to scale x scale.
+pub fn save_screenshot(
+    framebuffer: &Framebuffer,
+    path: &Path,
+    scale: u32,
+    palette: &[u32; 4],
+) -> Result<(), String> {
+    let scale = scale.max(1) as usize;
+    let width = framebuffer.width() * scale;
+    let height = framebuffer.height() * scale;
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let colour = palette[framebuffer.pixel(x / scale, y / scale) as usize & 0x3];
+            data.extend_from_slice(&[(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]);
+        }
+    }
+
+    let file =
+        File::create(path).map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&data).map_err(|e| e.to_string())
+}
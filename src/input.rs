@@ -1,115 +1,165 @@
 // This was too long so it got its own module.
-use sdl3::EventPump;
-use sdl3::Sdl;
+// Keyboard and gamepads both feed the same 16 keys, see keymap.rs for what presses what. Gamepads can
+// come and go while a game runs: poll_events() opens them as SDL announces them and drops them again
+// when they're unplugged.
+use sdl3::event::Event;
+use sdl3::gamepad::{Axis, Button, Gamepad};
 use sdl3::keyboard::Scancode;
+use sdl3::{EventPump, GamepadSubsystem, Sdl};
 use std::sync::{Arc, Mutex};
 
+use crate::keymap::Keymap;
+use chip_8::trace;
+
 pub struct InputHandler {
     event_pump: EventPump,
+    // Keyboard key -> CHIP-8 key, from the keymap
+    bindings: Vec<(Scancode, u8)>,
+    // None if SDL couldn't start its gamepad support, the keyboard still works
+    gamepad_subsystem: Option<GamepadSubsystem>,
+    gamepads: Vec<Gamepad>,
+    gamepad_bindings: Vec<(Button, u8)>,
+    stick_deadzone: f32,
     keys: [bool; 16],
     running: Arc<Mutex<bool>>,
 }
 
 impl InputHandler {
-    pub fn new(sdl: &Sdl, running: Arc<Mutex<bool>>) -> Result<InputHandler, String> {
+    pub fn new(
+        sdl: &Sdl,
+        running: Arc<Mutex<bool>>,
+        keymap: &Keymap,
+    ) -> Result<InputHandler, String> {
         let event_pump = sdl.event_pump().map_err(|e| e.to_string())?;
-        println!("InputHandler initialized");
-        Ok(InputHandler {
+        let gamepad_subsystem = sdl
+            .gamepad()
+            .inspect_err(|e| eprintln!("No gamepad support: {}", e))
+            .ok();
+        let mut input = InputHandler {
             event_pump,
+            bindings: keymap.bindings(),
+            gamepad_subsystem,
+            gamepads: vec![],
+            gamepad_bindings: keymap.gamepad_bindings(),
+            stick_deadzone: keymap.stick_deadzone(),
             keys: [false; 16],
             running,
-        })
+        };
+        // Whatever is already plugged in. SDL may announce these again, open_gamepad() skips repeats.
+        let connected = input
+            .gamepad_subsystem
+            .as_ref()
+            .and_then(|subsystem| subsystem.gamepads().ok())
+            .unwrap_or_default();
+        for id in connected {
+            input.open_gamepad(id);
+        }
+        trace!(Input, Info, "InputHandler initialized");
+        Ok(input)
     }
 
-    pub fn poll_events(&mut self) -> impl Iterator<Item = sdl3::event::Event> {
+    pub fn poll_events(&mut self) -> Vec<Event> {
         self.event_pump.pump_events();
-        self.event_pump.poll_iter()
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in &events {
+            match event {
+                Event::ControllerDeviceAdded { which, .. } => self.open_gamepad(*which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.gamepads.retain(|pad| pad.id().ok() != Some(*which));
+                    trace!(Input, Info, "Gamepad {} removed", which);
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn open_gamepad(&mut self, id: u32) {
+        let Some(subsystem) = &self.gamepad_subsystem else {
+            return;
+        };
+        if self.gamepads.iter().any(|pad| pad.id().ok() == Some(id)) {
+            return;
+        }
+        match subsystem.open(id) {
+            Ok(pad) => {
+                trace!(
+                    Input,
+                    Info,
+                    "Gamepad {} connected: {}",
+                    id,
+                    pad.name().unwrap_or_default()
+                );
+                self.gamepads.push(pad);
+            }
+            Err(e) => eprintln!("Could not open gamepad {}: {}", id, e),
+        }
     }
 
     pub fn update(&mut self) {
         let keyboard_state = self.event_pump.keyboard_state();
         self.keys = [false; 16];
 
-        if keyboard_state.is_scancode_pressed(Scancode::Kp1) {
-            self.keys[0x1] = true;
-            println!("Key 1 (Kp1) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::Kp2) {
-            self.keys[0x2] = true;
-            println!("Key 2 (Kp2) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::Kp3) {
-            self.keys[0x3] = true;
-            println!("Key 3 (Kp3) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::Kp4) {
-            self.keys[0xc] = true;
-            println!("Key C (Kp4) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::Q) {
-            self.keys[0x4] = true;
-            println!("Key 4 (Q) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::W) {
-            self.keys[0x5] = true;
-            println!("Key 5 (W) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::E) {
-            self.keys[0x6] = true;
-            println!("Key 6 (E) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::R) {
-            self.keys[0xd] = true;
-            println!("Key D (R) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::A) {
-            self.keys[0x7] = true;
-            println!("Key 7 (A) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::S) {
-            self.keys[0x8] = true;
-            println!("Key 8 (S) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::D) {
-            self.keys[0x9] = true;
-            println!("Key 9 (D) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::F) {
-            self.keys[0xe] = true;
-            println!("Key E (F) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::Z) {
-            self.keys[0xa] = true;
-            println!("Key A (Z) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::X) {
-            self.keys[0x0] = true;
-            println!("Key 0 (X) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::C) {
-            self.keys[0xb] = true;
-            println!("Key B (C) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::V) {
-            self.keys[0xf] = true;
-            println!("Key F (V) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::Slash) {
-            self.keys[0xb] = true;
-            println!("Key B (Slash) pressed");
-        }
-        if keyboard_state.is_scancode_pressed(Scancode::KpMultiply) {
-            self.keys[0xf] = true;
-            println!("Key F (KpMultiply) pressed");
+        for (scancode, key) in &self.bindings {
+            if keyboard_state.is_scancode_pressed(*scancode) {
+                self.keys[*key as usize] = true;
+                trace!(Input, Trace, "Key {:X} ({}) pressed", key, scancode.name());
+            }
+        }
+        for pad in &self.gamepads {
+            let stick = stick_direction(pad, self.stick_deadzone);
+            for (button, key) in &self.gamepad_bindings {
+                if pad.button(*button) || stick.contains(button) {
+                    self.keys[*key as usize] = true;
+                    trace!(Input, Trace, "Key {:X} ({:?}) pressed", key, button);
+                }
+            }
         }
+
         if keyboard_state.is_scancode_pressed(Scancode::Escape) {
-            println!("Escape pressed, stopping game");
+            trace!(Input, Info, "Escape pressed, stopping game");
             *self.running.lock().unwrap() = false;
         }
     }
+}
+
+// The D-pad buttons the left stick is pushing, so it presses whatever they're bound to. Nothing inside
+// the deadzone, past it the stick gives eight directions: an axis counts once it is at least 3/8 of
+// the way there, which splits the circle into roughly equal slices.
+fn stick_direction(pad: &Gamepad, deadzone: f32) -> Vec<Button> {
+    let x = pad.axis(Axis::LeftX) as f32 / i16::MAX as f32;
+    let y = pad.axis(Axis::LeftY) as f32 / i16::MAX as f32;
+    let length = x.hypot(y);
+    if length <= deadzone {
+        return vec![];
+    }
+    let threshold = length * 0.375;
+    let mut buttons = vec![];
+    if x < -threshold {
+        buttons.push(Button::DPadLeft);
+    }
+    if x > threshold {
+        buttons.push(Button::DPadRight);
+    }
+    // SDL's Y axis points down
+    if y < -threshold {
+        buttons.push(Button::DPadUp);
+    }
+    if y > threshold {
+        buttons.push(Button::DPadDown);
+    }
+    buttons
+}
+
+impl InputHandler {
+    // The keypad as of the last update(), for recording.
+    pub fn keys(&self) -> [bool; 16] {
+        self.keys
+    }
+}
 
-    pub fn get_keys(&self) -> &[bool; 16] {
-        println!("Keys state: {:?}", self.keys);
-        &self.keys
+impl chip_8::Keypad for InputHandler {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.keys.get(key as usize).copied().unwrap_or(false)
     }
 }
@@ -0,0 +1,249 @@
+// Opcode decoding, shared by the CPU, the debugger and the disassembler. decode() only looks at the bits:
+// it happily decodes SUPER-CHIP and XO-CHIP instructions whatever the machine, and mode() says which
+// platform an instruction needs so the caller can decide what to do with it.
+
+use crate::cpu::Mode;
+
+// Registers are indexes into V, 0-F.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    // 00E0
+    Clear,
+    // 00EE
+    Return,
+    // 00CN, SUPER-CHIP
+    ScrollDown(u8),
+    // 00DN, XO-CHIP
+    ScrollUp(u8),
+    // 00FB, SUPER-CHIP
+    ScrollRight,
+    // 00FC, SUPER-CHIP
+    ScrollLeft,
+    // 00FD, SUPER-CHIP
+    Exit,
+    // 00FE, SUPER-CHIP
+    Lores,
+    // 00FF, SUPER-CHIP
+    Hires,
+    // 0NNN, a machine code routine on the COSMAC VIP. We can't run those.
+    MachineCode(u16),
+    // 1NNN
+    Jump(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN
+    SkipIfEqual { x: usize, nn: u8 },
+    // 4XNN
+    SkipIfNotEqual { x: usize, nn: u8 },
+    // 5XY0
+    SkipIfRegistersEqual { x: usize, y: usize },
+    // 5XY2, XO-CHIP
+    SaveRange { x: usize, y: usize },
+    // 5XY3, XO-CHIP
+    LoadRange { x: usize, y: usize },
+    // 6XNN
+    Set { x: usize, nn: u8 },
+    // 7XNN
+    Add { x: usize, nn: u8 },
+    // 8XY0
+    Copy { x: usize, y: usize },
+    // 8XY1
+    Or { x: usize, y: usize },
+    // 8XY2
+    And { x: usize, y: usize },
+    // 8XY3
+    Xor { x: usize, y: usize },
+    // 8XY4
+    AddRegisters { x: usize, y: usize },
+    // 8XY5
+    Subtract { x: usize, y: usize },
+    // 8XY6
+    ShiftRight { x: usize, y: usize },
+    // 8XY7
+    SubtractReversed { x: usize, y: usize },
+    // 8XYE
+    ShiftLeft { x: usize, y: usize },
+    // 9XY0
+    SkipIfRegistersNotEqual { x: usize, y: usize },
+    // ANNN
+    SetIndex(u16),
+    // BNNN, or BXNN with the jump quirk. x is the X nibble either way.
+    JumpOffset { x: usize, nnn: u16 },
+    // CXNN
+    Random { x: usize, nn: u8 },
+    // DXYN
+    Draw { x: usize, y: usize, n: u8 },
+    // EX9E
+    SkipIfKey(usize),
+    // EXA1
+    SkipIfNotKey(usize),
+    // F000 NNNN, XO-CHIP. The address is the next word.
+    SetIndexLong,
+    // FN01, XO-CHIP
+    Plane(u8),
+    // F002, XO-CHIP
+    Audio,
+    // FX07
+    GetDelay(usize),
+    // FX0A
+    WaitKey(usize),
+    // FX15
+    SetDelay(usize),
+    // FX18
+    SetSound(usize),
+    // FX1E
+    AddIndex(usize),
+    // FX29
+    Font(usize),
+    // FX30, SUPER-CHIP
+    BigFont(usize),
+    // FX33
+    Bcd(usize),
+    // FX3A, XO-CHIP
+    Pitch(usize),
+    // FX55
+    Store(usize),
+    // FX65
+    Load(usize),
+    // FX75, SUPER-CHIP
+    SaveFlags(usize),
+    // FX85, SUPER-CHIP
+    LoadFlags(usize),
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+    let n = (opcode & 0x000F) as u8;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Clear,
+            0x00EE => Instruction::Return,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::Lores,
+            0x00FF => Instruction::Hires,
+            _ if opcode & 0xFFF0 == 0x00C0 => Instruction::ScrollDown(n),
+            _ if opcode & 0xFFF0 == 0x00D0 => Instruction::ScrollUp(n),
+            _ => Instruction::MachineCode(nnn),
+        },
+        0x1000 => Instruction::Jump(nnn),
+        0x2000 => Instruction::Call(nnn),
+        0x3000 => Instruction::SkipIfEqual { x, nn },
+        0x4000 => Instruction::SkipIfNotEqual { x, nn },
+        0x5000 => match n {
+            0x0 => Instruction::SkipIfRegistersEqual { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => Instruction::Unknown(opcode),
+        },
+        0x6000 => Instruction::Set { x, nn },
+        0x7000 => Instruction::Add { x, nn },
+        0x8000 => match n {
+            0x0 => Instruction::Copy { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddRegisters { x, y },
+            0x5 => Instruction::Subtract { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubtractReversed { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => Instruction::Unknown(opcode),
+        },
+        0x9000 => match n {
+            0x0 => Instruction::SkipIfRegistersNotEqual { x, y },
+            _ => Instruction::Unknown(opcode),
+        },
+        0xA000 => Instruction::SetIndex(nnn),
+        0xB000 => Instruction::JumpOffset { x, nnn },
+        0xC000 => Instruction::Random { x, nn },
+        0xD000 => Instruction::Draw { x, y, n },
+        0xE000 => match nn {
+            0x9E => Instruction::SkipIfKey(x),
+            0xA1 => Instruction::SkipIfNotKey(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        _ => match nn {
+            0x00 if x == 0 => Instruction::SetIndexLong,
+            0x01 => Instruction::Plane(x as u8),
+            0x02 if x == 0 => Instruction::Audio,
+            0x07 => Instruction::GetDelay(x),
+            0x0A => Instruction::WaitKey(x),
+            0x15 => Instruction::SetDelay(x),
+            0x18 => Instruction::SetSound(x),
+            0x1E => Instruction::AddIndex(x),
+            0x29 => Instruction::Font(x),
+            0x30 => Instruction::BigFont(x),
+            0x33 => Instruction::Bcd(x),
+            0x3A => Instruction::Pitch(x),
+            0x55 => Instruction::Store(x),
+            0x65 => Instruction::Load(x),
+            0x75 => Instruction::SaveFlags(x),
+            0x85 => Instruction::LoadFlags(x),
+            _ => Instruction::Unknown(opcode),
+        },
+    }
+}
+
+impl Instruction {
+    // The first platform that has this instruction.
+    pub fn mode(&self) -> Mode {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::BigFont(_)
+            | Instruction::SaveFlags(_)
+            | Instruction::LoadFlags(_) => Mode::SuperChip,
+            Instruction::ScrollUp(_)
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::SetIndexLong
+            | Instruction::Plane(_)
+            | Instruction::Audio
+            | Instruction::Pitch(_) => Mode::XoChip,
+            _ => Mode::Chip8,
+        }
+    }
+
+    // Bytes taken up in memory, 4 for F000 NNNN.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::SetIndexLong => 4,
+            _ => 2,
+        }
+    }
+
+    // Skips conditionally jump over the next instruction.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipIfEqual { .. }
+                | Instruction::SkipIfNotEqual { .. }
+                | Instruction::SkipIfRegistersEqual { .. }
+                | Instruction::SkipIfRegistersNotEqual { .. }
+                | Instruction::SkipIfKey(_)
+                | Instruction::SkipIfNotKey(_)
+        )
+    }
+}
+
+impl Mode {
+    // Whether a machine in this mode can run `instruction`.
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        match instruction.mode() {
+            Mode::Chip8 => true,
+            Mode::SuperChip => self.has_schip(),
+            Mode::XoChip => self.has_xochip(),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Which keyboard keys press which CHIP-8 keys. Kept in keymap.json next to recent_roms.json, as a map
+// from the CHIP-8 key (hex digit) to the SDL names of every keyboard key that presses it:
+//
+//   { "keys": { "5": ["W", "Up"], "8": ["S", "Down"], ... } }
+//
+// The default is the usual laptop layout, the left hand side of the keyboard laid out like the keypad:
+//
+//   1 2 3 C        1 2 3 4
+//   4 5 6 D   ->   Q W E R
+//   7 8 9 E        A S D F
+//   A 0 B F        Z X C V
+//
+// Gamepads live in the same file. "gamepad" maps SDL button names to CHIP-8 keys, and the left stick
+// works as the D-pad once it's pushed further than "stick_deadzone" (0 to 1):
+//
+//   "gamepad": { "dpup": "2", "dpdown": "8", "dpleft": "4", "dpright": "6", "a": "5", "b": "5" },
+//   "stick_deadzone": 0.35
+//
+// Changes for a single ROM go in its rom_db.json profile, see with_profile().
+use sdl3::gamepad::Button;
+use sdl3::keyboard::Scancode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+use chip_8::RomProfile;
+use chip_8::romdb::ROM_DB_FILE;
+use chip_8::trace;
+
+const KEYMAP_FILE: &str = "keymap.json";
+
+// The emulator uses these itself: quit, rewind and the save slots.
+pub const RESERVED: [&str; 6] = ["Escape", "Backspace", "F1", "F2", "F3", "F4"];
+
+// CHIP-8 keys in keypad order, with the default keyboard key for each
+pub const LAYOUT: [[(u8, &str); 4]; 4] = [
+    [(0x1, "1"), (0x2, "2"), (0x3, "3"), (0xC, "4")],
+    [(0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xD, "R")],
+    [(0x7, "A"), (0x8, "S"), (0x9, "D"), (0xE, "F")],
+    [(0xA, "Z"), (0x0, "X"), (0xB, "C"), (0xF, "V")],
+];
+
+// Most games move with 2/4/6/8 and fire with 5. Both bottom face buttons fire, so it doesn't matter
+// whether the pad puts A on the right or at the bottom.
+const DEFAULT_GAMEPAD: [(&str, &str); 6] = [
+    ("dpup", "2"),
+    ("dpdown", "8"),
+    ("dpleft", "4"),
+    ("dpright", "6"),
+    ("a", "5"),
+    ("b", "5"),
+];
+const DEFAULT_DEADZONE: f32 = 0.35;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keymap {
+    #[serde(default = "default_keys")]
+    keys: BTreeMap<String, Vec<String>>,
+    #[serde(default = "default_gamepad")]
+    gamepad: BTreeMap<String, String>,
+    #[serde(default = "default_deadzone")]
+    stick_deadzone: f32,
+}
+
+// A file with only gamepad settings in it still gets the usual keyboard layout
+fn default_keys() -> BTreeMap<String, Vec<String>> {
+    Keymap::default().keys
+}
+
+fn default_gamepad() -> BTreeMap<String, String> {
+    DEFAULT_GAMEPAD
+        .iter()
+        .map(|(button, key)| (button.to_string(), key.to_string()))
+        .collect()
+}
+
+fn default_deadzone() -> f32 {
+    DEFAULT_DEADZONE
+}
+
+impl Keymap {
+    // keymap.json, or the default if there isn't one. A broken file gets a warning rather than
+    // stopping anyone from playing.
+    pub fn load() -> Keymap {
+        let Ok(data) = fs::read_to_string(KEYMAP_FILE) else {
+            return Keymap::default();
+        };
+        match serde_json::from_str::<Keymap>(&data) {
+            Ok(keymap) => keymap.normalized(),
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", KEYMAP_FILE, e);
+                Keymap::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(KEYMAP_FILE, data).map_err(|e| format!("Could not write {}: {}", KEYMAP_FILE, e))
+    }
+
+    // Hand edited files might use a key that isn't a CHIP-8 key at all, or say "w" in one place and "W"
+    // in another. bind() turns both spellings into SDL's, so W still ends up on one CHIP-8 key.
+    fn normalized(self) -> Keymap {
+        let mut keymap = Keymap {
+            keys: BTreeMap::new(),
+            gamepad: self.gamepad,
+            stick_deadzone: self.stick_deadzone.clamp(0.0, 0.95),
+        };
+        for (key, names) in self.keys {
+            match u8::from_str_radix(&key, 16) {
+                Ok(key) if key < 16 => {
+                    for name in names {
+                        keymap.bind(key, &name);
+                    }
+                }
+                _ => eprintln!("Ignoring {} in {}, not a CHIP-8 key", key, KEYMAP_FILE),
+            }
+        }
+        keymap
+    }
+
+    pub fn keys_for(&self, key: u8) -> &[String] {
+        self.keys
+            .get(&format!("{:X}", key))
+            .map(|names| names.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Adds a keyboard key to a CHIP-8 key. A keyboard key only ever presses one CHIP-8 key, so it is
+    // taken away from wherever it was before. Names go in the way SDL spells them, and SDL doesn't care
+    // about case, so neither does the check for an earlier binding.
+    pub fn bind(&mut self, key: u8, name: &str) {
+        let name = Scancode::from_name(name).map_or(name, |scancode| scancode.name());
+        for names in self.keys.values_mut() {
+            names.retain(|n| !n.eq_ignore_ascii_case(name));
+        }
+        let names = self.keys.entry(format!("{:X}", key)).or_default();
+        names.push(name.to_string());
+    }
+
+    pub fn clear(&mut self, key: u8) {
+        self.keys.remove(&format!("{:X}", key));
+    }
+
+    // Back to the default keyboard layout, gamepad settings stay as they are.
+    pub fn reset_keys(&mut self) {
+        self.keys = Keymap::default().keys;
+    }
+
+    // What InputHandler checks every frame. Names SDL doesn't know are skipped with a warning.
+    pub fn bindings(&self) -> Vec<(Scancode, u8)> {
+        let mut bindings = vec![];
+        for key in 0..16u8 {
+            for name in self.keys_for(key) {
+                match Scancode::from_name(name) {
+                    Some(scancode) if !RESERVED.contains(&name.as_str()) => {
+                        bindings.push((scancode, key))
+                    }
+                    _ => eprintln!("Ignoring unknown or reserved key {:?} in the keymap", name),
+                }
+            }
+        }
+        trace!(Input, Info, "Keymap has {} bindings", bindings.len());
+        bindings
+    }
+
+    // What InputHandler checks for gamepads. A button mapped to "" is unbound, that's how a ROM profile
+    // takes a button away.
+    pub fn gamepad_bindings(&self) -> Vec<(Button, u8)> {
+        let mut bindings = vec![];
+        for (name, key) in &self.gamepad {
+            match (
+                Button::from_string(name),
+                u8::from_str_radix(key, 16).ok().filter(|k| *k < 16),
+            ) {
+                (Some(button), Some(key)) => bindings.push((button, key)),
+                (Some(_), None) if key.is_empty() => {}
+                _ => eprintln!(
+                    "Ignoring gamepad button {:?} -> {:?} in the keymap",
+                    name, key
+                ),
+            }
+        }
+        bindings
+    }
+
+    // A ROM profile's keys replace the bindings of the CHIP-8 keys it mentions, its gamepad buttons go on
+    // top of the general ones.
+    pub fn with_profile(mut self, profile: &RomProfile) -> Keymap {
+        for (key, names) in &profile.keys {
+            match u8::from_str_radix(key, 16) {
+                Ok(key) if key < 16 => {
+                    self.clear(key);
+                    for name in names {
+                        self.bind(key, name);
+                    }
+                }
+                _ => eprintln!("Ignoring {} in {}, not a CHIP-8 key", key, ROM_DB_FILE),
+            }
+        }
+        self.gamepad.extend(profile.gamepad.clone());
+        self
+    }
+
+    pub fn stick_deadzone(&self) -> f32 {
+        self.stick_deadzone
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap {
+            keys: BTreeMap::new(),
+            gamepad: default_gamepad(),
+            stick_deadzone: DEFAULT_DEADZONE,
+        };
+        for (key, name) in LAYOUT.iter().flatten() {
+            keymap.bind(*key, name);
+        }
+        keymap
+    }
+}
@@ -0,0 +1,183 @@
+// The launcher's keyboard mapping dialog. The CHIP-8 keypad is laid out as buttons: click one and every
+// key pressed after that gets added to it, Backspace clears it and Escape stops listening. Nothing is
+// written to keymap.json until Save.
+use gtk::gdk;
+use gtk::prelude::*;
+use gtk::{Box as GtkBox, Button, EventControllerKey, Grid, Label, Orientation, PropagationPhase};
+use gtk4 as gtk;
+use sdl3::keyboard::Scancode;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::keymap::{Keymap, LAYOUT, RESERVED};
+
+pub fn show(parent: &impl IsA<gtk::Window>) {
+    let keymap = Rc::new(RefCell::new(Keymap::load()));
+    let listening = Rc::new(Cell::new(None::<u8>));
+
+    let window = gtk::Window::builder()
+        .title("Keyboard Mapping")
+        .transient_for(parent)
+        .modal(true)
+        .build();
+
+    let vbox = GtkBox::new(Orientation::Vertical, 10);
+    vbox.set_margin_top(10);
+    vbox.set_margin_bottom(10);
+    vbox.set_margin_start(10);
+    vbox.set_margin_end(10);
+
+    let status = Label::new(Some("Click a CHIP-8 key, then press the keys for it"));
+    vbox.append(&status);
+
+    // The keypad, same layout as the COSMAC VIP
+    let grid = Grid::new();
+    grid.set_row_spacing(5);
+    grid.set_column_spacing(5);
+    let mut buttons = vec![];
+    for (row, keys) in LAYOUT.iter().enumerate() {
+        for (column, (key, _)) in keys.iter().enumerate() {
+            let button = Button::new();
+            button.set_size_request(100, 60);
+            grid.attach(&button, column as i32, row as i32, 1, 1);
+            buttons.push((*key, button));
+        }
+    }
+    let buttons = Rc::new(buttons);
+    label_buttons(&buttons, &keymap.borrow(), None);
+    vbox.append(&grid);
+
+    for (key, button) in buttons.iter() {
+        let key = *key;
+        let buttons = buttons.clone();
+        let keymap = keymap.clone();
+        let listening = listening.clone();
+        let status = status.clone();
+        button.connect_clicked(move |_| {
+            listening.set(Some(key));
+            status.set_text(&format!(
+                "Press keys for {:X}. Backspace clears it, Escape when done",
+                key
+            ));
+            label_buttons(&buttons, &keymap.borrow(), Some(key));
+        });
+    }
+
+    // Capture phase, so Space and Return get bound instead of clicking the focused button
+    let controller = EventControllerKey::new();
+    controller.set_propagation_phase(PropagationPhase::Capture);
+    {
+        let buttons = buttons.clone();
+        let keymap = keymap.clone();
+        let listening = listening.clone();
+        let status = status.clone();
+        controller.connect_key_pressed(move |_, keyval, _, _| {
+            let Some(key) = listening.get() else {
+                return gtk::glib::Propagation::Proceed;
+            };
+            match keyval {
+                gdk::Key::Escape => {
+                    listening.set(None);
+                    status.set_text("Click a CHIP-8 key, then press the keys for it");
+                }
+                gdk::Key::BackSpace => keymap.borrow_mut().clear(key),
+                _ => match sdl_name(keyval) {
+                    Some(name) if RESERVED.contains(&name.as_str()) => {
+                        status.set_text(&format!("{} is taken by the emulator", name))
+                    }
+                    Some(name) => keymap.borrow_mut().bind(key, &name),
+                    None => status.set_text("That key can't be used, try another"),
+                },
+            }
+            label_buttons(&buttons, &keymap.borrow(), listening.get());
+            gtk::glib::Propagation::Stop
+        });
+    }
+    window.add_controller(controller);
+
+    let button_box = GtkBox::new(Orientation::Horizontal, 5);
+    button_box.set_halign(gtk::Align::End);
+    let reset_button = Button::with_label("Reset to Default");
+    let cancel_button = Button::with_label("Cancel");
+    let save_button = Button::with_label("Save");
+    save_button.add_css_class("suggested-action");
+    {
+        let buttons = buttons.clone();
+        let keymap = keymap.clone();
+        let listening = listening.clone();
+        reset_button.connect_clicked(move |_| {
+            keymap.borrow_mut().reset_keys();
+            label_buttons(&buttons, &keymap.borrow(), listening.get());
+        });
+    }
+    {
+        let window = window.clone();
+        cancel_button.connect_clicked(move |_| window.close());
+    }
+    {
+        let window = window.clone();
+        let status = status.clone();
+        save_button.connect_clicked(move |_| match keymap.borrow().save() {
+            Ok(()) => window.close(),
+            Err(e) => status.set_text(&e),
+        });
+    }
+    button_box.append(&reset_button);
+    button_box.append(&cancel_button);
+    button_box.append(&save_button);
+    vbox.append(&button_box);
+
+    window.set_child(Some(&vbox));
+    window.present();
+}
+
+fn label_buttons(buttons: &[(u8, Button)], keymap: &Keymap, listening: Option<u8>) {
+    for (key, button) in buttons {
+        let names = keymap.keys_for(*key);
+        let bound = if names.is_empty() {
+            "-".to_string()
+        } else {
+            names.join(", ")
+        };
+        let marker = if listening == Some(*key) { " ..." } else { "" };
+        button.set_label(&format!("{:X}{}\n{}", key, marker, bound));
+    }
+}
+
+// GDK names keys by what they type, SDL by where they are. Letters, digits and punctuation line up on a
+// US layout. On other layouts the name may point at a different physical key, keymap.json can be
+// edited by hand for those.
+fn sdl_name(keyval: gdk::Key) -> Option<String> {
+    let gdk_name = keyval.name()?;
+    let name = match gdk_name.as_str() {
+        "space" => "Space".to_string(),
+        "Return" | "Tab" | "Up" | "Down" | "Left" | "Right" | "Home" | "End" | "Insert"
+        | "Delete" => gdk_name.to_string(),
+        "Page_Up" => "PageUp".to_string(),
+        "Page_Down" => "PageDown".to_string(),
+        "Shift_L" => "Left Shift".to_string(),
+        "Shift_R" => "Right Shift".to_string(),
+        "Control_L" => "Left Ctrl".to_string(),
+        "Control_R" => "Right Ctrl".to_string(),
+        "Alt_L" => "Left Alt".to_string(),
+        "Alt_R" => "Right Alt".to_string(),
+        "KP_Divide" => "Keypad /".to_string(),
+        "KP_Multiply" => "Keypad *".to_string(),
+        "KP_Subtract" => "Keypad -".to_string(),
+        "KP_Add" => "Keypad +".to_string(),
+        "KP_Enter" => "Keypad Enter".to_string(),
+        "KP_Decimal" => "Keypad .".to_string(),
+        other => match other.strip_prefix("KP_") {
+            Some(digit) if digit.len() == 1 => format!("Keypad {}", digit),
+            // F1-F12 are called the same thing in both
+            _ if other.starts_with('F') && other[1..].parse::<u8>().is_ok() => other.to_string(),
+            _ => keyval
+                .to_lower()
+                .to_unicode()
+                .filter(|c| c.is_ascii_graphic())?
+                .to_ascii_uppercase()
+                .to_string(),
+        },
+    };
+    Scancode::from_name(&name).map(|_| name)
+}
@@ -0,0 +1,39 @@
+// The emulator core. Nothing in here knows about SDL, GTK or rodio - the machine state is plain data
+// and frontends plug in through the Display, Audio and Keypad traits. The SDL/GTK binary in main.rs is
+// just one frontend over this, tests and batch tools can drive a Chip8 without opening any windows.
+
+pub mod asm;
+pub mod bus;
+pub mod cli;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod font;
+pub mod headless;
+pub mod instruction;
+pub mod movie;
+pub mod peripherals;
+pub mod phosphor;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod romdb;
+pub mod savestate;
+pub mod trace;
+pub mod util;
+
+pub use cpu::{Chip8, Mode};
+pub use debugger::Debugger;
+pub use display::Framebuffer;
+pub use error::{EmulatorError, Fault, UnknownOpcodePolicy};
+pub use instruction::{Instruction, decode};
+pub use movie::Movie;
+pub use peripherals::{Audio, Display, Keypad, NullAudio, NullDisplay};
+pub use phosphor::Phosphor;
+pub use quirks::Quirks;
+pub use random::{Random, RngKind};
+pub use rewind::Rewind;
+pub use romdb::{RomDatabase, RomProfile};
+pub use savestate::Snapshot;
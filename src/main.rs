@@ -8,19 +8,35 @@
 
 use gtk::prelude::*;
 use gtk::{
-    Application, ApplicationWindow, Box as GtkBox, Button, CheckButton, DropDown,
-    FileChooserAction, FileChooserDialog, HeaderBar, Label, Orientation, ResponseType,
+    Application, ApplicationWindow, Box as GtkBox, Button, CheckButton, ColorButton, DropDown,
+    Entry, FileChooserAction, FileChooserDialog, HeaderBar, Label, Orientation, ResponseType,
+    SpinButton, gdk,
 };
 use gtk4 as gtk;
 use sdl3::event::Event;
+use sdl3::keyboard::{Mod, Scancode};
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-mod cpu;
-use cpu::CPU;
+use chip_8::Audio as _;
+use chip_8::Display as _;
+use chip_8::asm;
+use chip_8::bus::MemoryOverflow;
+use chip_8::cli::{self, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use chip_8::debugger::Stop;
+use chip_8::display::PALETTES;
+use chip_8::quirks::{self, Quirks};
+use chip_8::rewind::{self, Rewind};
+use chip_8::romdb::ROM_DB_FILE;
+use chip_8::trace::{self, Level};
+use chip_8::{
+    Chip8, Debugger, Mode, Movie, Phosphor, Random, RngKind, RomDatabase, Snapshot,
+    UnknownOpcodePolicy, savestate,
+};
+use std::collections::HashMap;
 
 mod audio;
 use audio::Audio;
@@ -29,16 +45,25 @@ mod window;
 use window::Window;
 
 mod input;
-use input::InputHandler;
+mod keymap;
+mod keymap_dialog;
+mod repl;
 
-mod util;
+mod debug_window;
+mod display_settings;
+use debug_window::{Action, DebugWindow};
+use display_settings::{CUSTOM, DisplayMode, DisplaySettings};
+use input::InputHandler;
+use keymap::Keymap;
 
 // For my benefit, I used GTK4 because I got to use GTK3 after following a tutorial from Rust Programming by Example by Packt.
 // It is more modernt than GKT3
 
 const WINDOW_WIDTH: i32 = 400;
 const WINDOW_HEIGHT: i32 = 500;
-const CPU_CYCLES_PER_FRAME: u32 = 8; // ~500Hz at 60Hz frame rate
+const SAVE_DIR: &str = "saves";
+const REWIND_KEY: Scancode = Scancode::Backspace;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 // Structure to store recent ROMs
 #[derive(Serialize, Deserialize)]
@@ -46,13 +71,239 @@ struct RecentRoms {
     roms: Vec<String>,
 }
 
+// Everything the launcher hands over to the emulator
+struct EmulatorOptions {
+    scale: u32,
+    // 0xRRGGBB for off, plane 1, plane 2 and both planes
+    palette: [u32; 4],
+    // Phosphor persistence, None presents plain frames on vblank
+    phosphor_decay: Option<f32>,
+    enable_audio: bool,
+    mode: Mode,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    // Whether a ROM profile's quirks and speed replace the ones above
+    use_rom_profile: bool,
+    random: Random,
+    unknown_opcode_policy: UnknownOpcodePolicy,
+    memory_overflow: MemoryOverflow,
+    trace_levels: [Option<Level>; 4],
+    trace_file: Option<PathBuf>,
+    // 0 seconds turns rewind off
+    rewind_seconds: u32,
+    rewind_granularity: u32,
+    rewind_budget: usize,
+    open_debugger: bool,
+    // Movies, only from the command line for now
+    start_state: Option<PathBuf>,
+    play_movie: Option<PathBuf>,
+    record_movie: Option<PathBuf>,
+}
+
+// The launcher widgets that make up EmulatorOptions, cloned into each callback that starts a ROM
+#[derive(Clone)]
+struct LauncherSettings {
+    scale_dropdown: DropDown,
+    palette_dropdown: DropDown,
+    colour_buttons: [ColorButton; 4],
+    display_mode_dropdown: DropDown,
+    phosphor_decay_spin: SpinButton,
+    quirks_dropdown: DropDown,
+    key_press_toggle: CheckButton,
+    speed_spin: SpinButton,
+    rom_profile_toggle: CheckButton,
+    seed_entry: Entry,
+    vip_random_toggle: CheckButton,
+    audio_toggle: CheckButton,
+    unknown_opcode_dropdown: DropDown,
+    memory_fault_toggle: CheckButton,
+    trace_toggles: [CheckButton; 4],
+    trace_level_dropdown: DropDown,
+    // What the dropdown started on, to tell whether it was touched
+    trace_initial_level: u32,
+    trace_file_toggle: CheckButton,
+    trace_file: PathBuf,
+    rewind_seconds_spin: SpinButton,
+    rewind_granularity_spin: SpinButton,
+    rewind_budget_spin: SpinButton,
+    debugger_toggle: CheckButton,
+}
+
+impl LauncherSettings {
+    // A category keeps the level it has, which --trace may have set differently per category, unless
+    // its toggle or the level dropdown was changed. Otherwise one dropdown would flatten them all.
+    fn trace_levels(&self) -> [Option<Level>; 4] {
+        let selected = self.trace_level_dropdown.selected();
+        let level = trace::LEVELS
+            .get(selected as usize)
+            .copied()
+            .unwrap_or(Level::Trace);
+        std::array::from_fn(|j| {
+            let current = trace::level(trace::CATEGORIES[j]);
+            let active = self.trace_toggles[j].is_active();
+            if active == current.is_some() && selected == self.trace_initial_level {
+                current
+            } else {
+                active.then_some(level)
+            }
+        })
+    }
+
+    fn options(&self) -> EmulatorOptions {
+        let scale = match self.scale_dropdown.selected() {
+            0 => 8,
+            1 => 10,
+            2 => 12,
+            _ => 8,
+        };
+
+        // Whatever the palette ends up as is kept for next time
+        let mut display_settings = DisplaySettings::load();
+        display_settings.palette = PALETTES
+            .get(self.palette_dropdown.selected() as usize)
+            .map(|(name, _, _)| name.to_string())
+            .unwrap_or(CUSTOM.to_string());
+        display_settings.set_custom_colours(
+            self.colour_buttons
+                .each_ref()
+                .map(|button| rgba_to_colour(&button.rgba())),
+        );
+        display_settings.mode = match self.display_mode_dropdown.selected() {
+            1 => DisplayMode::Phosphor,
+            _ => DisplayMode::Vblank,
+        };
+        display_settings.phosphor_decay = self.phosphor_decay_spin.value() as f32 / 100.0;
+        if let Err(e) = display_settings.save() {
+            eprintln!("{}", e);
+        }
+
+        // The preset picks both the instruction set and the quirks
+        let name = quirks::PRESETS
+            .get(self.quirks_dropdown.selected() as usize)
+            .map(|(name, _)| *name)
+            .unwrap_or("chip8");
+        let mut quirks = Quirks::preset(name).unwrap_or_default();
+        quirks.key_wait_on_press = self.key_press_toggle.is_active();
+
+        // An empty seed box means a new seed every run
+        let rng = if self.vip_random_toggle.is_active() {
+            RngKind::CosmacVip
+        } else {
+            RngKind::SplitMix
+        };
+        let seed = self.seed_entry.text();
+        let random = match cli::parse_seed(seed.trim()) {
+            Ok(seed) => Random::new(rng, seed),
+            Err(e) => {
+                if !seed.trim().is_empty() {
+                    eprintln!("Ignoring the seed: {}", e);
+                }
+                Random::from_entropy(rng)
+            }
+        };
+
+        EmulatorOptions {
+            scale,
+            palette: display_settings.colours(),
+            phosphor_decay: display_settings.phosphor_decay(),
+            enable_audio: self.audio_toggle.is_active(),
+            mode: Mode::from_preset(name).unwrap_or(Mode::Chip8),
+            quirks,
+            instructions_per_frame: self.speed_spin.value_as_int().max(1) as u32,
+            use_rom_profile: self.rom_profile_toggle.is_active(),
+            random,
+            unknown_opcode_policy: match self.unknown_opcode_dropdown.selected() {
+                1 => UnknownOpcodePolicy::Skip,
+                2 => UnknownOpcodePolicy::Trap,
+                _ => UnknownOpcodePolicy::Halt,
+            },
+            memory_overflow: if self.memory_fault_toggle.is_active() {
+                MemoryOverflow::Fault
+            } else {
+                MemoryOverflow::Wrap
+            },
+            trace_levels: self.trace_levels(),
+            trace_file: self
+                .trace_file_toggle
+                .is_active()
+                .then(|| self.trace_file.clone()),
+            rewind_seconds: self.rewind_seconds_spin.value_as_int().max(0) as u32,
+            rewind_granularity: self.rewind_granularity_spin.value_as_int().max(1) as u32,
+            rewind_budget: self.rewind_budget_spin.value_as_int().max(1) as usize * 1024 * 1024,
+            open_debugger: self.debugger_toggle.is_active(),
+            start_state: None,
+            play_movie: None,
+            record_movie: None,
+        }
+    }
+}
+
+// SUPER-CHIP RPL flags survive between runs, keyed by ROM file name
+#[derive(Serialize, Deserialize, Default)]
+struct RplFlags {
+    flags: HashMap<String, [u8; 16]>,
+}
+
 fn main() {
+    // `chip_8 run ROM ...` skips the launcher (and with --headless, any window at all)
+    if std::env::args().nth(1).as_deref() == Some("run") {
+        std::process::exit(run_from_command_line(std::env::args().skip(2)));
+    }
+    if std::env::args().nth(1).as_deref() == Some("import-db") {
+        let Some(path) = std::env::args().nth(2) else {
+            eprintln!("Usage: chip_8 import-db PATH/TO/chip-8-database/database/programs.json");
+            std::process::exit(2);
+        };
+        match import_rom_database(Path::new(&path)) {
+            Ok(message) => println!("{}", message),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Our own flags come first, whatever is left goes to GTK
+    let mut gtk_args = vec![];
+    let mut trace_file = None;
+    let mut debug_rom = None;
+    let mut preset = "chip8".to_string();
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => {
+                if let Err(e) = trace::configure(&args.next().unwrap_or_default()) {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                }
+            }
+            "--trace-file" => trace_file = args.next().map(PathBuf::from),
+            "--debug" => debug_rom = args.next().map(PathBuf::from),
+            "--quirks" => preset = args.next().unwrap_or_default(),
+            _ => gtk_args.push(arg),
+        }
+    }
+    if let Err(e) = trace::set_output_file(trace_file.as_deref()) {
+        eprintln!("Could not open trace file: {}", e);
+        std::process::exit(2);
+    }
+
+    // --debug skips the launcher and goes straight into the terminal debugger
+    if let Some(rom) = debug_rom {
+        if let Err(e) = debug_rom_in_terminal(&rom, &preset) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Initialize GTK
     let app = Application::builder()
         .application_id("com.euclidae.chip8_emulator")
         .build();
 
-    app.connect_activate(|app| {
+    app.connect_activate(move |app| {
         // Create the main window
         let window = ApplicationWindow::builder()
             .application(app)
@@ -96,21 +347,172 @@ fn main() {
             DropDown::from_strings(&["8x (512x256)", "10x (640x320)", "12x (768x384)"]);
         scale_dropdown.set_selected(0);
 
+        // Screen colours: the presets plus Custom, which is whatever the four colour pickers say. Picking a
+        // preset shows its colours in the pickers, changing a picker switches to Custom.
+        let display_settings = DisplaySettings::load();
+        let mut palette_labels: Vec<_> = PALETTES.iter().map(|(_, label, _)| *label).collect();
+        palette_labels.push("Custom");
+        let palette_dropdown = DropDown::from_strings(&palette_labels);
+        let custom_index = PALETTES.len() as u32;
+        palette_dropdown.set_selected(
+            PALETTES
+                .iter()
+                .position(|(name, _, _)| name.eq_ignore_ascii_case(&display_settings.palette))
+                .map(|index| index as u32)
+                .unwrap_or(custom_index),
+        );
+        let initial_colours = display_settings.colours();
+        let colour_buttons: [ColorButton; 4] = std::array::from_fn(|i| {
+            let button = ColorButton::with_rgba(&colour_to_rgba(initial_colours[i]));
+            button.set_tooltip_text(Some(["Off", "On (Plane 1)", "Plane 2", "Both Planes"][i]));
+            button
+        });
+        {
+            let colour_buttons = colour_buttons.clone();
+            palette_dropdown.connect_selected_notify(move |dropdown| {
+                let colours = match PALETTES.get(dropdown.selected() as usize) {
+                    Some((_, _, colours)) => *colours,
+                    None => DisplaySettings::load().custom_colours(),
+                };
+                for (button, colour) in colour_buttons.iter().zip(colours) {
+                    button.set_rgba(&colour_to_rgba(colour));
+                }
+            });
+        }
+        for button in &colour_buttons {
+            let palette_dropdown = palette_dropdown.clone();
+            button.connect_color_set(move |_| palette_dropdown.set_selected(custom_index));
+        }
+
+        // Plain frames or phosphor blending, and how much glow is left after each frame (in %)
+        let display_mode_dropdown =
+            DropDown::from_strings(&["Plain (Present on Vblank)", "Phosphor (Anti-Flicker)"]);
+        display_mode_dropdown.set_selected(match display_settings.mode {
+            DisplayMode::Vblank => 0,
+            DisplayMode::Phosphor => 1,
+        });
+        let phosphor_decay_spin = SpinButton::with_range(0.0, 95.0, 5.0);
+        phosphor_decay_spin.set_value((display_settings.phosphor_decay * 100.0).round() as f64);
+        phosphor_decay_spin.set_sensitive(display_settings.mode == DisplayMode::Phosphor);
+        {
+            let phosphor_decay_spin = phosphor_decay_spin.clone();
+            display_mode_dropdown.connect_selected_notify(move |dropdown| {
+                phosphor_decay_spin.set_sensitive(dropdown.selected() == 1);
+            });
+        }
+
+        // Quirk preset dropdown, same order as quirks::PRESETS
+        let quirks_dropdown = DropDown::from_strings(
+            &quirks::PRESETS
+                .iter()
+                .map(|(_, label)| *label)
+                .collect::<Vec<_>>(),
+        );
+        quirks_dropdown.set_selected(0);
+
+        // FX0A normally waits for the key to be released, like the COSMAC VIP
+        let key_press_toggle = CheckButton::with_label("FX0A Triggers on Key Press");
+        key_press_toggle.set_active(false);
+
+        // Instructions per frame. Timers always run at 60Hz regardless.
+        let speed_spin = SpinButton::with_range(1.0, 1000.0, 1.0);
+        speed_spin.set_value(DEFAULT_INSTRUCTIONS_PER_FRAME as f64);
+
+        // ROMs with an entry in rom_db.json bring their own quirks and speed
+        let rom_profile_toggle =
+            CheckButton::with_label("Use Quirks and Speed from the ROM Database");
+        rom_profile_toggle.set_active(true);
+
+        // Random numbers for CXNN. A fixed seed makes runs repeatable.
+        let seed_entry = Entry::builder().placeholder_text("New every run").build();
+        let vip_random_toggle = CheckButton::with_label("COSMAC VIP Random Numbers");
+        vip_random_toggle.set_active(false);
+
+        // Audio toggle
+        let audio_toggle = CheckButton::with_label("Enable Audio");
+        audio_toggle.set_active(true);
+
+        // What to do with opcodes the selected platform doesn't have
+        let unknown_opcode_dropdown =
+            DropDown::from_strings(&["Stop with an error", "Skip them", "Pause in the debugger"]);
+        unknown_opcode_dropdown.set_selected(0);
+
+        // Addresses past the end of memory wrap around unless this is on
+        let memory_fault_toggle = CheckButton::with_label("Stop on Out-of-Bounds Memory Access");
+        memory_fault_toggle.set_active(false);
+
+        // Tracing, starting from whatever --trace set up
+        let trace_toggles = trace::CATEGORIES.map(|category| {
+            let toggle = CheckButton::with_label(category.name());
+            toggle.set_active(trace::level(category).is_some());
+            toggle
+        });
+        let trace_level_dropdown = DropDown::from_strings(
+            &trace::LEVELS
+                .iter()
+                .map(|level| level.name())
+                .collect::<Vec<_>>(),
+        );
+        let initial_level = trace::CATEGORIES
+            .iter()
+            .filter_map(|category| trace::level(*category))
+            .max()
+            .unwrap_or(Level::Trace);
+        let trace_initial_level = initial_level as u32 - 1;
+        trace_level_dropdown.set_selected(trace_initial_level);
+        let trace_file_toggle = CheckButton::with_label("Write Trace to File");
+        trace_file_toggle.set_active(trace_file.is_some());
+
+        // Rewind history: how far back, how often to snapshot (in frames) and a memory cap in MiB
+        let rewind_seconds_spin = SpinButton::with_range(0.0, 600.0, 1.0);
+        rewind_seconds_spin.set_value(rewind::DEFAULT_SECONDS as f64);
+        let rewind_granularity_spin = SpinButton::with_range(1.0, 60.0, 1.0);
+        rewind_granularity_spin.set_value(rewind::DEFAULT_GRANULARITY as f64);
+        let rewind_budget_spin = SpinButton::with_range(1.0, 1024.0, 1.0);
+        rewind_budget_spin.set_value((rewind::DEFAULT_BUDGET / (1024 * 1024)) as f64);
+
+        // Debugger panel next to the game window
+        let debugger_toggle = CheckButton::with_label("Open Debugger");
+        debugger_toggle.set_active(false);
+
+        let settings = LauncherSettings {
+            scale_dropdown: scale_dropdown.clone(),
+            palette_dropdown: palette_dropdown.clone(),
+            colour_buttons: colour_buttons.clone(),
+            display_mode_dropdown: display_mode_dropdown.clone(),
+            phosphor_decay_spin: phosphor_decay_spin.clone(),
+            quirks_dropdown: quirks_dropdown.clone(),
+            key_press_toggle: key_press_toggle.clone(),
+            speed_spin: speed_spin.clone(),
+            rom_profile_toggle: rom_profile_toggle.clone(),
+            seed_entry: seed_entry.clone(),
+            vip_random_toggle: vip_random_toggle.clone(),
+            audio_toggle: audio_toggle.clone(),
+            unknown_opcode_dropdown: unknown_opcode_dropdown.clone(),
+            memory_fault_toggle: memory_fault_toggle.clone(),
+            trace_toggles: trace_toggles.clone(),
+            trace_level_dropdown: trace_level_dropdown.clone(),
+            trace_initial_level,
+            trace_file_toggle: trace_file_toggle.clone(),
+            trace_file: trace_file
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("trace.log")),
+            rewind_seconds_spin: rewind_seconds_spin.clone(),
+            rewind_granularity_spin: rewind_granularity_spin.clone(),
+            rewind_budget_spin: rewind_budget_spin.clone(),
+            debugger_toggle: debugger_toggle.clone(),
+        };
+
         // Clone widgets for closures
         let status_label_clone1 = status_label.clone();
         let window_clone1 = window.clone();
-        let scale_dropdown_clone1 = scale_dropdown.clone();
+        let settings_clone1 = settings.clone();
         recent_dropdown.connect_selected_item_notify(move |dropdown| {
             if let Some(item) = dropdown.selected_item() {
                 let path = item.downcast_ref::<gtk::StringObject>().unwrap().string();
-                let scale = match scale_dropdown_clone1.selected() {
-                    0 => 8,
-                    1 => 10,
-                    2 => 12,
-                    _ => 8,
-                };
+                let options = settings_clone1.options();
                 window_clone1.hide();
-                match start_emulator(PathBuf::from(path.as_str()), scale, true) {
+                match start_emulator(PathBuf::from(path.as_str()), &options) {
                     Ok(_) => {
                         status_label_clone1.set_text("Emulator closed successfully");
                         window_clone1.show();
@@ -133,7 +535,7 @@ fn main() {
         let status_label_clone2 = status_label.clone();
         let recent_dropdown_clone2 = recent_dropdown.clone();
         let window_clone2 = window.clone();
-        let scale_dropdown_clone2 = scale_dropdown.clone();
+        let settings_clone2 = settings.clone();
         select_button.connect_clicked(move |_| {
             let dialog = FileChooserDialog::new(
                 Some("Select a CHIP-8 ROM"),
@@ -147,38 +549,35 @@ fn main() {
 
             let filter = gtk::FileFilter::new();
             filter.add_pattern("*.ch8");
-            filter.set_name(Some("CHIP-8 ROM files (*.ch8)"));
+            filter.add_pattern("*.sc8");
+            filter.add_pattern("*.xo8");
+            filter.add_pattern("*.8o");
+            filter.set_name(Some("CHIP-8 ROM files (*.ch8, *.sc8, *.xo8, *.8o)"));
             dialog.add_filter(&filter);
 
             let status_label_clone3 = status_label_clone2.clone();
             let recent_dropdown_clone3 = recent_dropdown_clone2.clone();
             let window_clone3 = window_clone2.clone();
-            let scale_dropdown_clone3 = scale_dropdown_clone2.clone();
+            let settings_clone3 = settings_clone2.clone();
             dialog.connect_response(move |dialog, response| {
-                if response == ResponseType::Accept {
-                    if let Some(file) = dialog.file() {
-                        if let Some(path) = file.path() {
-                            let scale = match scale_dropdown_clone3.selected() {
-                                0 => 8,
-                                1 => 10,
-                                2 => 12,
-                                _ => 8,
-                            };
-                            window_clone3.hide();
-                            match start_emulator(path.clone(), scale, true) {
-                                Ok(_) => {
-                                    status_label_clone3.set_text("Emulator closed successfully");
-                                    add_recent_rom(
-                                        path.to_string_lossy().to_string(),
-                                        &recent_dropdown_clone3,
-                                    );
-                                    window_clone3.show();
-                                }
-                                Err(e) => {
-                                    status_label_clone3.set_text(&format!("Emulator error: {}", e));
-                                    window_clone3.show();
-                                }
-                            }
+                if response == ResponseType::Accept
+                    && let Some(file) = dialog.file()
+                    && let Some(path) = file.path()
+                {
+                    let options = settings_clone3.options();
+                    window_clone3.hide();
+                    match start_emulator(path.clone(), &options) {
+                        Ok(_) => {
+                            status_label_clone3.set_text("Emulator closed successfully");
+                            add_recent_rom(
+                                path.to_string_lossy().to_string(),
+                                &recent_dropdown_clone3,
+                            );
+                            window_clone3.show();
+                        }
+                        Err(e) => {
+                            status_label_clone3.set_text(&format!("Emulator error: {}", e));
+                            window_clone3.show();
                         }
                     }
                 }
@@ -195,12 +594,136 @@ fn main() {
         vbox.append(&scale_label);
         vbox.append(&scale_dropdown);
 
+        // Palette
+        let palette_label = Label::new(Some("Colours:"));
+        palette_label.set_margin_top(10);
+        vbox.append(&palette_label);
+        vbox.append(&palette_dropdown);
+        let colour_box = GtkBox::new(Orientation::Horizontal, 5);
+        colour_box.set_halign(gtk::Align::Center);
+        for button in &colour_buttons {
+            colour_box.append(button);
+        }
+        vbox.append(&colour_box);
+
+        // Flicker
+        let display_mode_label =
+            Label::new(Some("Display Mode (phosphor glow kept per frame, %):"));
+        display_mode_label.set_margin_top(10);
+        vbox.append(&display_mode_label);
+        let display_mode_box = GtkBox::new(Orientation::Horizontal, 5);
+        display_mode_box.append(&display_mode_dropdown);
+        display_mode_box.append(&phosphor_decay_spin);
+        vbox.append(&display_mode_box);
+
+        // Quirk preset dropdown
+        let quirks_label = Label::new(Some("Quirks:"));
+        quirks_label.set_margin_top(10);
+        vbox.append(&quirks_label);
+        vbox.append(&quirks_dropdown);
+        key_press_toggle.set_margin_top(10);
+        vbox.append(&key_press_toggle);
+
+        // Speed
+        let speed_label = Label::new(Some("Instructions per Frame:"));
+        speed_label.set_margin_top(10);
+        vbox.append(&speed_label);
+        vbox.append(&speed_spin);
+        vbox.append(&rom_profile_toggle);
+
+        // Random seed
+        let seed_label = Label::new(Some("Random Seed:"));
+        seed_label.set_margin_top(10);
+        vbox.append(&seed_label);
+        vbox.append(&seed_entry);
+        vbox.append(&vip_random_toggle);
+
         // Audio toggle
-        let audio_toggle = CheckButton::with_label("Enable Audio");
-        audio_toggle.set_active(true);
         audio_toggle.set_margin_top(10);
         vbox.append(&audio_toggle);
 
+        // Unknown opcode policy
+        let unknown_opcode_label = Label::new(Some("Unknown Opcodes:"));
+        unknown_opcode_label.set_margin_top(10);
+        vbox.append(&unknown_opcode_label);
+        vbox.append(&unknown_opcode_dropdown);
+        memory_fault_toggle.set_margin_top(10);
+        vbox.append(&memory_fault_toggle);
+
+        // Trace categories and level
+        let trace_label = Label::new(Some("Trace:"));
+        trace_label.set_margin_top(10);
+        vbox.append(&trace_label);
+        let trace_box = GtkBox::new(Orientation::Horizontal, 5);
+        for toggle in &trace_toggles {
+            trace_box.append(toggle);
+        }
+        vbox.append(&trace_box);
+        vbox.append(&trace_level_dropdown);
+        vbox.append(&trace_file_toggle);
+
+        // Rewind
+        let rewind_label = Label::new(Some("Rewind (seconds, every N frames, MiB):"));
+        rewind_label.set_margin_top(10);
+        vbox.append(&rewind_label);
+        let rewind_box = GtkBox::new(Orientation::Horizontal, 5);
+        rewind_box.append(&rewind_seconds_spin);
+        rewind_box.append(&rewind_granularity_spin);
+        rewind_box.append(&rewind_budget_spin);
+        vbox.append(&rewind_box);
+        debugger_toggle.set_margin_top(10);
+        vbox.append(&debugger_toggle);
+
+        // Keyboard mapping, saved to keymap.json and picked up the next time a ROM starts
+        let keymap_button = Button::builder()
+            .label("Keyboard Mapping...")
+            .halign(gtk::Align::Center)
+            .build();
+        keymap_button.set_margin_top(10);
+        let window_clone = window.clone();
+        keymap_button.connect_clicked(move |_| keymap_dialog::show(&window_clone));
+        vbox.append(&keymap_button);
+
+        // ROM database import, from a local copy of chip-8-database
+        let import_button = Button::builder()
+            .label("Import chip-8-database...")
+            .halign(gtk::Align::Center)
+            .build();
+        let window_clone = window.clone();
+        let status_label_clone = status_label.clone();
+        import_button.connect_clicked(move |_| {
+            let dialog = FileChooserDialog::new(
+                Some("Select chip-8-database's programs.json"),
+                Some(&window_clone),
+                FileChooserAction::Open,
+                &[
+                    ("Open", ResponseType::Accept),
+                    ("Cancel", ResponseType::Cancel),
+                ],
+            );
+            let filter = gtk::FileFilter::new();
+            filter.add_pattern("*.json");
+            filter.set_name(Some("JSON files (*.json)"));
+            dialog.add_filter(&filter);
+
+            let status_label_clone = status_label_clone.clone();
+            dialog.connect_response(move |dialog, response| {
+                if response == ResponseType::Accept
+                    && let Some(file) = dialog.file()
+                    && let Some(path) = file.path()
+                {
+                    match import_rom_database(&path) {
+                        Ok(message) => status_label_clone.set_text(&message),
+                        Err(e) => status_label_clone.set_text(&e),
+                    }
+                }
+                dialog.close();
+            });
+
+            dialog.show();
+        });
+        vbox.append(&import_button);
+
         // Theme switcher
         let theme_button = Button::builder()
             .label("Toggle Dark/Light Theme")
@@ -242,63 +765,495 @@ fn main() {
         window.show();
     });
 
-    app.run();
+    app.run_with_args(&gtk_args);
+}
+
+// Returns the exit code: 0 fine, 1 the ROM or emulator failed, 2 bad arguments.
+fn run_from_command_line(args: impl Iterator<Item = String>) -> i32 {
+    let run = match cli::RunArgs::parse(args, DEFAULT_INSTRUCTIONS_PER_FRAME) {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            return 2;
+        }
+    };
+    if let Err(e) = run.start_tracing() {
+        eprintln!("{}\n{}", e, cli::USAGE);
+        return 2;
+    }
+    let result = if run.headless {
+        cli::run_headless(&run)
+    } else {
+        run_in_window(&run)
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
 }
 
-fn start_emulator(rom_path: PathBuf, scale: u32, enable_audio: bool) -> Result<(), String> {
+// The launcher's defaults, with the quirks, speed, seed and movies from the command line
+fn run_in_window(run: &cli::RunArgs) -> Result<(), String> {
+    let (mode, quirks) = run.quirks()?;
+    let display_settings = DisplaySettings::load();
+    let palette = match &run.palette {
+        Some(name) => display_settings
+            .colours_for(name)
+            .ok_or(format!("Unknown palette: {}", name))?,
+        None => display_settings.colours(),
+    };
+    let options = EmulatorOptions {
+        scale: 8,
+        palette,
+        phosphor_decay: display_settings.phosphor_decay(),
+        enable_audio: true,
+        mode,
+        quirks,
+        instructions_per_frame: run.instructions_per_frame,
+        use_rom_profile: run.rom_profile,
+        random: run.random(),
+        unknown_opcode_policy: UnknownOpcodePolicy::default(),
+        memory_overflow: MemoryOverflow::default(),
+        // Whatever --trace set up
+        trace_levels: trace::CATEGORIES.map(trace::level),
+        trace_file: run.trace_file.clone(),
+        rewind_seconds: rewind::DEFAULT_SECONDS,
+        rewind_granularity: rewind::DEFAULT_GRANULARITY,
+        rewind_budget: rewind::DEFAULT_BUDGET,
+        open_debugger: false,
+        start_state: run.from_state.clone(),
+        play_movie: run.play.clone(),
+        record_movie: run.record.clone(),
+    };
+    start_emulator(run.rom.clone(), &options)
+}
+
+fn debug_rom_in_terminal(rom: &Path, preset: &str) -> Result<(), String> {
+    let quirks = Quirks::preset(preset).ok_or(format!("Unknown quirks preset: {}", preset))?;
+    let mode = Mode::from_preset(preset).unwrap_or(Mode::Chip8);
+    let mut cpu = Chip8::with_mode(mode, quirks);
+    let (bytes, listing) = asm::load_program(rom)?;
+    cpu.load_rom(&bytes).map_err(|e| e.to_string())?;
+    repl::run(cpu, DEFAULT_INSTRUCTIONS_PER_FRAME, listing)
+}
+
+fn start_emulator(rom_path: PathBuf, options: &EmulatorOptions) -> Result<(), String> {
+    for (category, level) in trace::CATEGORIES.iter().zip(options.trace_levels) {
+        trace::set_level(*category, level);
+    }
+    trace::set_output_file(options.trace_file.as_deref())?;
+
     // Initialize SDL
     let sdl = sdl3::init().map_err(|e| e.to_string())?;
 
-    // Create SDL window
+    // Octo source is assembled on the fly, the listing feeds the debugger panel
+    let (bytes, listing) = asm::load_program(&rom_path)?;
     let rom_name = rom_path.file_name().unwrap_or_default().to_string_lossy();
-    let win = Window::new(&format!("Euclidae's CHIP-8: {}", rom_name), scale)?;
+
+    // The ROM's profile from rom_db.json, if it has one. Its title, colours and keys always count, the
+    // quirks and speed only when the launcher or command line didn't pick their own.
+    let profile = match RomDatabase::load_from_file(Path::new(ROM_DB_FILE)) {
+        Ok(database) => database.profile_for(&bytes).cloned(),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+    .unwrap_or_default();
+    let mut mode = options.mode;
+    let mut quirks = options.quirks;
+    let mut instructions_per_frame = options.instructions_per_frame;
+    if options.use_rom_profile {
+        if let Some(profile_quirks) = profile.machine_quirks() {
+            quirks = profile_quirks;
+            mode = profile.mode().unwrap_or(mode);
+        }
+        instructions_per_frame = profile
+            .instructions_per_frame
+            .unwrap_or(instructions_per_frame);
+    }
+    if let Some(title) = &profile.title {
+        match &profile.description {
+            Some(description) => println!("{}: {}", title, description),
+            None => println!("{}", title),
+        }
+    }
+    chip_8::trace!(
+        Cpu,
+        Info,
+        "{:?} with {} instructions per frame",
+        mode,
+        instructions_per_frame
+    );
+
+    // Create SDL window
+    let title = profile.title.as_deref().unwrap_or(&rom_name);
+    let mut win = Window::new(
+        &format!("Euclidae's CHIP-8: {}", title),
+        options.scale,
+        profile.apply_palette(options.palette),
+        options.phosphor_decay.map(Phosphor::new),
+    )?;
 
     // Initialize audio
-    let audio = if enable_audio {
+    let mut audio = if options.enable_audio {
         Audio::new()?
     } else {
         Audio::new_silent()
     };
 
-    // Read ROM file
-    let rom = fs::read(&rom_path).map_err(|e| e.to_string())?;
+    // Initialize CPU and load the ROM. A movie being played brings its own machine.
+    let rpl_key = rom_name.to_string();
+    let mut playback = options
+        .play_movie
+        .as_deref()
+        .map(Movie::load_from_file)
+        .transpose()?;
+    let mut cpu = match &playback {
+        Some(movie) => {
+            instructions_per_frame = movie.instructions_per_frame;
+            movie.setup(&bytes)?
+        }
+        None => {
+            let mut cpu = Chip8::with_mode(mode, quirks);
+            cpu.set_random(options.random);
+            cpu.load_rom(&bytes).map_err(|e| e.to_string())?;
+            if let Some(flags) = load_rpl_flags().flags.get(&rpl_key) {
+                cpu.set_rpl_flags(*flags);
+            }
+            if let Some(path) = &options.start_state {
+                let state = Snapshot::load_from_file(path).map_err(|e| e.to_string())?;
+                cpu.restore(&state).map_err(|e| e.to_string())?;
+            }
+            cpu
+        }
+    };
+    cpu.set_unknown_opcode_policy(options.unknown_opcode_policy);
+    cpu.set_memory_overflow(options.memory_overflow);
 
-    // Initialize CPU
-    let mut cpu = CPU::new(win, audio);
-    cpu.load_rom(&rom)?;
+    // Playing on into a recording keeps the movie's start, see cli.rs
+    let mut recording = options.record_movie.as_ref().map(|_| match &playback {
+        Some(movie) => movie.restart(),
+        None => Movie::new(
+            &cpu,
+            &bytes,
+            instructions_per_frame,
+            options.start_state.is_some(),
+        ),
+    });
+    // Rewinding, loading a slot or stopping in the debugger would leave the movie out of step with
+    // the machine, so they're off while one plays or records
+    let open_debugger = options.open_debugger && playback.is_none() && recording.is_none();
+    if options.open_debugger && !open_debugger {
+        eprintln!("The debugger is off while a movie plays or records");
+    }
+    let mut movie_frame: u64 = 0;
 
     // Initialize input handler with shared running flag
     let running = Arc::new(Mutex::new(true));
-    let mut input_handler = InputHandler::new(&sdl, Arc::clone(&running))?;
+    let mut input_handler = InputHandler::new(
+        &sdl,
+        Arc::clone(&running),
+        &Keymap::load().with_profile(&profile),
+    )?;
 
-    // Main loop
-    let mut last_frame_time = std::time::Instant::now();
+    let mut rewind = Rewind::new(
+        options.rewind_seconds,
+        options.rewind_granularity,
+        options.rewind_budget,
+    );
+    let mut rewinding = false;
+
+    let mut debugger = Debugger::new();
+    let mut debug_window = open_debugger.then(|| DebugWindow::new(&rom_name, listing.clone()));
+    let mut frame: u64 = 0;
+
+    // Main loop. One iteration per 60Hz frame: tick the timers once, then run however many instructions
+    // the speed setting asks for.
+    let mut next_frame = Instant::now();
     while *running.lock().unwrap() {
+        let movie_active = playback.is_some() || recording.is_some();
         for event in input_handler.poll_events() {
             match event {
                 Event::Quit { .. } => {
                     *running.lock().unwrap() = false;
-                    cpu.audio.pause();
+                    audio.pause();
+                }
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(slot) = save_slot(scancode) {
+                        let save = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                        if !save && movie_active {
+                            eprintln!("Can't load a save state while a movie plays or records");
+                        } else if save_state_hotkey(&mut cpu, &rom_name, slot, save) {
+                            // The history leads up to where we were, not to the state we just loaded
+                            rewind.clear();
+                        }
+                    }
+                    if scancode == REWIND_KEY && !movie_active {
+                        rewinding = true;
+                        audio.pause();
+                    }
                 }
+                Event::KeyUp {
+                    scancode: Some(REWIND_KEY),
+                    ..
+                } => rewinding = false,
                 _ => {}
             }
         }
 
         input_handler.update();
-        for _ in 0..CPU_CYCLES_PER_FRAME {
-            cpu.run_loop(&input_handler)?;
+        // The movie has the keys until it runs out, then the keyboard takes over
+        let keys = playback
+            .as_ref()
+            .and_then(|movie| movie.keys(movie_frame))
+            .unwrap_or_else(|| input_handler.keys());
+
+        // The debugger panel lives in GTK, so give GTK a turn and do whatever was clicked
+        if let Some(panel) = &debug_window {
+            let context = gtk::glib::MainContext::default();
+            while context.pending() {
+                context.iteration(false);
+            }
+            for action in panel.take_actions() {
+                let stop = match action {
+                    Action::Pause => {
+                        debugger.pause();
+                        None
+                    }
+                    Action::Continue => {
+                        debugger.resume();
+                        None
+                    }
+                    Action::Step => debugger.step_instruction(&mut cpu, &input_handler, &mut audio),
+                    Action::StepFrame => debugger.step_frame(
+                        &mut cpu,
+                        &input_handler,
+                        &mut audio,
+                        instructions_per_frame,
+                    ),
+                    Action::SkipOpcode => {
+                        if debugger.skip_unknown_opcode(&mut cpu) {
+                            panel.clear_stop();
+                        }
+                        None
+                    }
+                    Action::BreakOnUnknownOpcode(enabled) => {
+                        debugger.set_break_on_unknown_opcode(enabled);
+                        None
+                    }
+                    Action::ToggleBreakpoint(address) => {
+                        debugger.toggle_breakpoint(address);
+                        None
+                    }
+                    Action::Poke(address, value) => {
+                        if let Err(e) = cpu.poke(address, value) {
+                            eprintln!("{}", e);
+                        }
+                        None
+                    }
+                };
+                if let Some(stop) = stop {
+                    panel.show_stop(&stop);
+                }
+            }
+            // Closing the panel lets the game carry on as normal
+            if panel.is_closed() {
+                debugger.resume();
+                debug_window = None;
+            }
         }
 
-        let frame_time = last_frame_time.elapsed();
-        if frame_time < std::time::Duration::from_millis(16) {
-            std::thread::sleep(std::time::Duration::from_millis(16) - frame_time);
+        if rewinding {
+            // One recorded frame per real frame, so the game plays backwards at (granularity x) speed
+            rewind.step_back(&mut cpu).map_err(|e| e.to_string())?;
+        } else if let Some(panel) = &debug_window {
+            if let Some(stop) =
+                debugger.run_frame(&mut cpu, &input_handler, &mut audio, instructions_per_frame)
+            {
+                panel.show_stop(&stop);
+            }
+            if debugger.is_paused() {
+                audio.pause();
+            } else if options.rewind_seconds > 0 {
+                rewind.record(&cpu);
+            }
+            // A few times a second is plenty for people to read
+            if debugger.is_paused() || frame.is_multiple_of(4) {
+                panel.update(&cpu, &debugger, &input_handler);
+            }
+        } else {
+            cpu.tick_timers();
+            let mut crashed = None;
+            for _ in 0..instructions_per_frame {
+                if let Err(e) = cpu.run_loop(&keys, &mut audio) {
+                    crashed = Some(e.to_string());
+                    break;
+                }
+            }
+            // The Trap policy freezes the machine on an unknown opcode, bring up the debugger on it. A movie
+            // can't go through the debugger, so there it's a crash like any other.
+            if crashed.is_none()
+                && let Some(trap) = cpu.trap()
+            {
+                if movie_active || !gtk::is_initialized() {
+                    crashed = Some(trap.to_string());
+                } else {
+                    let panel = DebugWindow::new(&rom_name, listing.clone());
+                    panel.show_stop(&Stop::Error(trap.clone()));
+                    debugger.pause();
+                    panel.update(&cpu, &debugger, &input_handler);
+                    debug_window = Some(panel);
+                    audio.pause();
+                }
+            }
+            if let Some(movie) = &mut recording {
+                movie.push(&keys);
+            }
+            // A crash is exactly what a recording is for, so save it before bailing out
+            if let Some(e) = crashed {
+                if let (Some(movie), Some(path)) = (&mut recording, &options.record_movie) {
+                    save_movie(movie, path, &cpu);
+                }
+                return Err(e);
+            }
+            movie_frame += 1;
+            if let Some(movie) = &playback
+                && movie_frame >= movie.len()
+            {
+                match movie.verify(cpu.framebuffer()) {
+                    Ok(()) => println!("Movie finished, the screen matches the recording"),
+                    Err(e) => eprintln!("{}", e),
+                }
+                playback = None;
+            }
+            if options.rewind_seconds > 0 && !movie_active {
+                rewind.record(&cpu);
+            }
         }
-        last_frame_time = std::time::Instant::now();
+        frame += 1;
+
+        // Whatever the frame ended up as gets drawn once, however many instructions it took
+        win.refresh(cpu.framebuffer())?;
+
+        // Sleep until the next frame is due. Deadlines are absolute so rounding errors don't add up,
+        // and if we fell badly behind (debugger, window drag) we just start counting again from now.
+        next_frame += FRAME_DURATION;
+        let now = Instant::now();
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+        } else if now - next_frame > FRAME_DURATION * 4 {
+            next_frame = now;
+        }
+    }
+
+    if let Some(panel) = debug_window {
+        panel.close();
+    }
+    if let (Some(movie), Some(path)) = (&mut recording, &options.record_movie) {
+        save_movie(movie, path, &cpu);
     }
+    save_rpl_flags(rpl_key, *cpu.rpl_flags());
 
     Ok(())
 }
 
+// F1-F4 load a slot, Shift+F1-F4 save to it.
+fn save_slot(scancode: Scancode) -> Option<u8> {
+    match scancode {
+        Scancode::F1 => Some(1),
+        Scancode::F2 => Some(2),
+        Scancode::F3 => Some(3),
+        Scancode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+// Returns true if a slot was loaded, so the machine is now somewhere else.
+fn save_state_hotkey(cpu: &mut Chip8, rom_name: &str, slot: u8, save: bool) -> bool {
+    let path = savestate::slot_path(Path::new(SAVE_DIR), rom_name, slot);
+    let result = if save {
+        cpu.snapshot().save_to_file(&path)
+    } else {
+        Snapshot::load_from_file(&path).and_then(|snapshot| cpu.restore(&snapshot))
+    };
+    match result {
+        Ok(()) if save => {
+            chip_8::trace!(Input, Info, "Saved slot {} to {}", slot, path.display());
+            false
+        }
+        Ok(()) => {
+            chip_8::trace!(Input, Info, "Loaded slot {} from {}", slot, path.display());
+            true
+        }
+        // A missing or bad save shouldn't take the game down with it
+        Err(e) => {
+            eprintln!("Save slot {}: {}", slot, e);
+            false
+        }
+    }
+}
+
+// Merges chip-8-database's programs.json into rom_db.json, hand edits win. Returns a message for the user.
+fn import_rom_database(path: &Path) -> Result<String, String> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut database = RomDatabase::load_from_file(Path::new(ROM_DB_FILE))?;
+    let count = database.import_chip8_database(&data)?;
+    database.save_to_file(Path::new(ROM_DB_FILE))?;
+    Ok(format!(
+        "Imported {} ROMs, {} now knows {}",
+        count,
+        ROM_DB_FILE,
+        database.len()
+    ))
+}
+
+fn colour_to_rgba(colour: u32) -> gdk::RGBA {
+    let channel = |shift: u32| ((colour >> shift) & 0xff) as f32 / 255.0;
+    gdk::RGBA::new(channel(16), channel(8), channel(0), 1.0)
+}
+
+fn rgba_to_colour(rgba: &gdk::RGBA) -> u32 {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+    channel(rgba.red()) << 16 | channel(rgba.green()) << 8 | channel(rgba.blue())
+}
+
+fn save_movie(movie: &mut Movie, path: &Path, cpu: &Chip8) {
+    movie.finish(cpu.framebuffer());
+    match movie.save_to_file(path) {
+        Ok(()) => println!("Recorded {} frames to {}", movie.len(), path.display()),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+fn load_rpl_flags() -> RplFlags {
+    fs::read_to_string("rpl_flags.json")
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_rpl_flags(rom: String, flags: [u8; 16]) {
+    let mut rpl = load_rpl_flags();
+    // Don't litter the file with every ROM that never touched FX75
+    if flags == [0; 16] && !rpl.flags.contains_key(&rom) {
+        return;
+    }
+    rpl.flags.insert(rom, flags);
+    if let Ok(data) = serde_json::to_string(&rpl) {
+        let _ = fs::write("rpl_flags.json", data);
+    }
+}
+
 fn load_recent_roms() -> Result<RecentRoms, String> {
     let data = fs::read_to_string("recent_roms.json").map_err(|e| e.to_string())?;
     let roms: RecentRoms = serde_json::from_str(&data).map_err(|e| e.to_string())?;
@@ -0,0 +1,217 @@
+// Input movies. A movie is everything needed to replay a session exactly: the platform and quirks, the
+// random number generator, where the machine started (a fresh ROM or a save state) and the keypad on
+// every frame. Play it back with the same ROM and you get the same game, down to the last pixel, which
+// the hash of the final screen is there to prove. Good for bug reports, demos and regression runs.
+//
+// Movies are JSON like save states. The keypad goes in as runs of (keys, frames) since most of the time
+// nobody is pressing anything.
+
+use crate::cpu::{Chip8, Mode};
+use crate::display::Framebuffer;
+use crate::headless::{InputScript, KeyEvent};
+use crate::quirks::Quirks;
+use crate::random::Random;
+use crate::romdb::rom_sha1;
+use crate::savestate::Snapshot;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// 2: rom_hash is the ROM's SHA-1, the same key the ROM database uses
+pub const MOVIE_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Movie {
+    pub version: u32,
+    // SHA-1 of the ROM it was recorded with, playing it over another ROM is pointless
+    pub rom_hash: String,
+    pub mode: Mode,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub random: Random,
+    pub rpl: [u8; 16],
+    // Set when recording started from a save state instead of a freshly loaded ROM
+    pub start: Option<Snapshot>,
+    // One bit per key, bit N is key N
+    #[serde(with = "run_length")]
+    frames: Vec<u16>,
+    // Hash of the screen after the last frame, filled in by finish()
+    pub final_hash: Option<String>,
+}
+
+impl Movie {
+    // A new, empty movie starting from wherever `cpu` is now. Call it after the ROM is loaded and any
+    // save state restored, before the first frame runs.
+    pub fn new(cpu: &Chip8, rom: &[u8], instructions_per_frame: u32, from_state: bool) -> Movie {
+        Movie {
+            version: MOVIE_VERSION,
+            rom_hash: rom_sha1(rom),
+            mode: cpu.mode(),
+            quirks: *cpu.quirks(),
+            instructions_per_frame,
+            random: *cpu.random(),
+            rpl: *cpu.rpl_flags(),
+            start: from_state.then(|| cpu.snapshot()),
+            frames: vec![],
+            final_hash: None,
+        }
+    }
+
+    // Same start as this one with no frames yet, for re-recording over it.
+    pub fn restart(&self) -> Movie {
+        Movie {
+            frames: vec![],
+            final_hash: None,
+            ..self.clone()
+        }
+    }
+
+    // A machine set up exactly like the one the movie was recorded on, ROM loaded.
+    pub fn setup(&self, rom: &[u8]) -> Result<Chip8, String> {
+        if rom_sha1(rom) != self.rom_hash {
+            return Err("This movie was recorded with a different ROM".to_string());
+        }
+        let mut cpu = Chip8::with_mode(self.mode, self.quirks);
+        cpu.load_rom(rom).map_err(|e| e.to_string())?;
+        cpu.set_rpl_flags(self.rpl);
+        cpu.set_random(self.random);
+        if let Some(start) = &self.start {
+            cpu.restore(start).map_err(|e| e.to_string())?;
+        }
+        Ok(cpu)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Adds the keypad for the next frame.
+    pub fn push(&mut self, keys: &[bool; 16]) {
+        let bits = keys
+            .iter()
+            .enumerate()
+            .fold(0u16, |bits, (key, pressed)| bits | (*pressed as u16) << key);
+        self.frames.push(bits);
+    }
+
+    // The keypad on `frame`, None past the end.
+    pub fn keys(&self, frame: u64) -> Option<[bool; 16]> {
+        let bits = *self.frames.get(frame as usize)?;
+        Some(std::array::from_fn(|key| bits & (1 << key) != 0))
+    }
+
+    // The same key presses as a script for the headless runner.
+    pub fn input_script(&self) -> InputScript {
+        let mut script = InputScript::new();
+        let mut previous = 0u16;
+        for (frame, bits) in self.frames.iter().enumerate() {
+            let changed = previous ^ bits;
+            for key in 0..16u8 {
+                if changed & (1 << key) != 0 {
+                    script.push(KeyEvent {
+                        frame: frame as u64,
+                        key,
+                        pressed: bits & (1 << key) != 0,
+                    });
+                }
+            }
+            previous = *bits;
+        }
+        script
+    }
+
+    // Call once the last frame has run.
+    pub fn finish(&mut self, framebuffer: &Framebuffer) {
+        self.final_hash = Some(screen_hash(framebuffer));
+    }
+
+    // Whether the screen after playback is the one that was recorded.
+    pub fn verify(&self, framebuffer: &Framebuffer) -> Result<(), String> {
+        let Some(expected) = &self.final_hash else {
+            return Err("The movie has no final screen hash to check against".to_string());
+        };
+        let actual = screen_hash(framebuffer);
+        if actual != *expected {
+            return Err(format!(
+                "Playback desynced: the final screen hash is {}, the movie expects {}",
+                actual, expected
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Movie, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let movie: Movie = serde_json::from_str(&data)
+            .map_err(|e| format!("{} is not a movie: {}", path.display(), e))?;
+        if movie.version != MOVIE_VERSION {
+            return Err(format!(
+                "Movie version {} is not supported (expected {})",
+                movie.version, MOVIE_VERSION
+            ));
+        }
+        Ok(movie)
+    }
+}
+
+// The screen size and every pixel, as 16 hex digits.
+pub fn screen_hash(framebuffer: &Framebuffer) -> String {
+    let mut bytes = Vec::with_capacity(framebuffer.pixels().len() + 4);
+    bytes.extend_from_slice(&(framebuffer.width() as u16).to_be_bytes());
+    bytes.extend_from_slice(&(framebuffer.height() as u16).to_be_bytes());
+    bytes.extend_from_slice(framebuffer.pixels());
+    format!("{:016x}", fnv1a(&bytes))
+}
+
+// FNV-1a, 64 bit. Not cryptographic, just a cheap way to tell two buffers apart.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// The frames as [keys, count] pairs.
+mod run_length {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // A day of play at 60 frames a second. The counts come from a file, and without a limit one bad
+    // number would have us allocate gigabytes.
+    const MAX_FRAMES: u64 = 60 * 60 * 60 * 24;
+
+    pub fn serialize<S: Serializer>(frames: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut runs: Vec<(u16, u32)> = vec![];
+        for bits in frames {
+            match runs.last_mut() {
+                Some((keys, count)) if keys == bits => *count += 1,
+                _ => runs.push((*bits, 1)),
+            }
+        }
+        runs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+        let runs = Vec::<(u16, u32)>::deserialize(deserializer)?;
+        let total: u64 = runs.iter().map(|(_, count)| *count as u64).sum();
+        if total > MAX_FRAMES {
+            return Err(D::Error::custom(format!(
+                "{} frames is more than the {} a movie can have",
+                total, MAX_FRAMES
+            )));
+        }
+        Ok(runs
+            .into_iter()
+            .flat_map(|(keys, count)| std::iter::repeat_n(keys, count as usize))
+            .collect())
+    }
+}
@@ -0,0 +1,44 @@
+// The bits of hardware the core talks to. A frontend implements these, the core never cares whether it
+// is an SDL window, a rodio sink or a plain array in a test.
+
+use crate::display::Framebuffer;
+
+// Shows a finished frame. Called once per 60Hz frame by whatever runs the frames, not by the core.
+pub trait Display {
+    fn refresh(&mut self, framebuffer: &Framebuffer) -> Result<(), String>;
+}
+
+pub trait Audio {
+    fn play(&mut self);
+    fn pause(&mut self);
+
+    // XO-CHIP only: loop this 128 bit waveform at `rate` bits per second instead of the usual beep.
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _rate: f32) {}
+}
+
+pub trait Keypad {
+    fn is_pressed(&self, key: u8) -> bool;
+}
+
+// Handy for tests and headless runs - a keypad is just 16 booleans.
+impl Keypad for [bool; 16] {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.get(key as usize).copied().unwrap_or(false)
+    }
+}
+
+// Does nothing. For running without a screen or speakers.
+pub struct NullDisplay;
+
+impl Display for NullDisplay {
+    fn refresh(&mut self, _framebuffer: &Framebuffer) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn play(&mut self) {}
+    fn pause(&mut self) {}
+}
@@ -0,0 +1,66 @@
+// Phosphor persistence. CHIP-8 games move things by XOR-erasing the sprite and drawing it again, and
+// whenever a frame ends between the two the sprite is simply gone, so moving objects flicker. Old CRTs
+// hid that because the phosphor kept glowing for a moment after the beam left. This does the same: a lit
+// pixel shows up straight away, a pixel that goes dark fades out over a few frames instead of vanishing.
+//
+// `decay` is how much of the glow is left after each 60Hz frame, 0 being no persistence at all and 0.9 a
+// long smeary trail.
+
+use crate::display::Framebuffer;
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+pub struct Phosphor {
+    decay: f32,
+    width: usize,
+    height: usize,
+    // What each pixel shows right now, RGB from 0 to 255
+    glow: Vec<[f32; 3]>,
+}
+
+impl Phosphor {
+    pub fn new(decay: f32) -> Self {
+        Phosphor {
+            decay: decay.clamp(0.0, 0.95),
+            width: 0,
+            height: 0,
+            glow: vec![],
+        }
+    }
+
+    pub fn decay(&self) -> f32 {
+        self.decay
+    }
+
+    // Blends the next frame in and writes the result to `rgb` as 3 bytes per pixel. Lit pixels (any
+    // plane) take their colour at once, dark ones fade towards the off colour.
+    pub fn blend(&mut self, framebuffer: &Framebuffer, palette: &[u32; 4], rgb: &mut Vec<u8>) {
+        let colours = palette.map(|colour| {
+            [
+                ((colour >> 16) & 0xff) as f32,
+                ((colour >> 8) & 0xff) as f32,
+                (colour & 0xff) as f32,
+            ]
+        });
+        // A new resolution has nothing to fade from
+        if (self.width, self.height) != (framebuffer.width(), framebuffer.height()) {
+            self.width = framebuffer.width();
+            self.height = framebuffer.height();
+            self.glow = vec![colours[0]; self.width * self.height];
+        }
+
+        rgb.clear();
+        let off = colours[0];
+        for (glow, pixel) in self.glow.iter_mut().zip(framebuffer.pixels()) {
+            let pixel = *pixel as usize & 0x3;
+            if pixel != 0 {
+                *glow = colours[pixel];
+            } else {
+                for (channel, off) in glow.iter_mut().zip(off) {
+                    *channel = off + (*channel - off) * self.decay;
+                }
+            }
+            rgb.extend(glow.iter().map(|channel| channel.round() as u8));
+        }
+    }
+}
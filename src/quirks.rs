@@ -0,0 +1,104 @@
+// The ambiguous opcodes. Every CHIP-8 interpreter since the COSMAC VIP has disagreed on a few of these,
+// and ROMs written for one tend to break on another, so they are switches rather than hard-coded.
+// Reference: https://github.com/Timendus/chip8-test-suite#quirks-test
+
+use serde::{Deserialize, Serialize};
+
+// What FX55/FX65 do to I afterwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryIncrement {
+    None,     // I is left alone (SUPER-CHIP)
+    X,        // I += X (CHIP-48)
+    XPlusOne, // I += X + 1 (COSMAC VIP, XO-CHIP)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    pub memory_increment: MemoryIncrement,
+    // BNNN becomes BXNN and jumps to XNN + VX instead of NNN + V0.
+    pub jump: bool,
+    // 8XY1/8XY2/8XY3 clear VF.
+    pub vf_reset: bool,
+    // Sprites are cut off at the screen edge instead of wrapping around.
+    pub clipping: bool,
+    // DXYN waits for the next vertical blank, so at most one sprite per frame.
+    pub display_wait: bool,
+    // FX0A finishes as soon as a key is pressed instead of waiting for it to be released.
+    pub key_wait_on_press: bool,
+}
+
+// (name, label) pairs. The name is what the API and command line take, the label is for the launcher.
+pub const PRESETS: [(&str, &str); 4] = [
+    ("chip8", "CHIP-8 (COSMAC VIP)"),
+    ("chip48", "CHIP-48"),
+    ("schip", "SUPER-CHIP"),
+    ("xochip", "XO-CHIP"),
+];
+
+impl Quirks {
+    pub fn chip8() -> Self {
+        Quirks {
+            shift: false,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump: false,
+            vf_reset: true,
+            clipping: true,
+            display_wait: true,
+            key_wait_on_press: false,
+        }
+    }
+
+    pub fn chip48() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment: MemoryIncrement::X,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+            key_wait_on_press: false,
+        }
+    }
+
+    pub fn schip() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment: MemoryIncrement::None,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+            key_wait_on_press: false,
+        }
+    }
+
+    pub fn xochip() -> Self {
+        Quirks {
+            shift: false,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+            key_wait_on_press: false,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" | "vip" => Some(Self::chip8()),
+            "chip48" | "chip-48" => Some(Self::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Self::schip()),
+            "xochip" | "xo-chip" => Some(Self::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::chip8()
+    }
+}
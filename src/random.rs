@@ -0,0 +1,111 @@
+// The random numbers behind CXNN. They used to come straight from thread_rng(), which made every run
+// different: input movies desynced, traces couldn't be compared and tests couldn't use CXNN at all. Now
+// the generator is part of the machine, so the same seed and the same inputs always give the same game,
+// and it goes into save states and the rewind buffer with everything else.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RngKind {
+    // SplitMix64, good numbers from any seed.
+    #[default]
+    SplitMix,
+    // Works like the COSMAC VIP interpreter: a two byte seed, the low byte counts up on every call and
+    // picks a byte out of a table which gets added into the high byte. The VIP used its own interpreter
+    // code as the table and we don't have that, so the numbers themselves differ. The shape is the same
+    // though: 8 bit arithmetic, fairly streaky, and the whole sequence repeats after 64K calls.
+    CosmacVip,
+}
+
+impl RngKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "splitmix" | "default" => Some(RngKind::SplitMix),
+            "vip" | "cosmac" | "cosmac-vip" => Some(RngKind::CosmacVip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Random {
+    kind: RngKind,
+    // What the generator started from, kept so it can be shown and reused.
+    seed: u64,
+    state: u64,
+}
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(GOLDEN_GAMMA);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Stand-in for the VIP interpreter page. The bytes add up to an odd number, so the high byte keeps
+// moving and the seed visits all 64K values before repeating.
+const VIP_TABLE: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut state: u64 = 0x0c05_1802;
+    let mut j = 0;
+    while j < 256 {
+        state = state.wrapping_add(GOLDEN_GAMMA);
+        table[j] = (state >> 56) as u8;
+        j += 1;
+    }
+    let mut sum = 0u32;
+    j = 0;
+    while j < 256 {
+        sum += table[j] as u32;
+        j += 1;
+    }
+    if sum.is_multiple_of(2) {
+        table[0] ^= 1;
+    }
+    table
+};
+
+impl Random {
+    pub fn new(kind: RngKind, seed: u64) -> Self {
+        let state = match kind {
+            RngKind::SplitMix => seed,
+            // Only 16 bits of it matter to the VIP
+            RngKind::CosmacVip => seed & 0xffff,
+        };
+        Random { kind, seed, state }
+    }
+
+    // A fresh seed for when nobody asked for one.
+    pub fn from_entropy(kind: RngKind) -> Self {
+        Self::new(kind, rand::random())
+    }
+
+    pub fn kind(&self) -> RngKind {
+        self.kind
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self.kind {
+            RngKind::SplitMix => (splitmix(&mut self.state) >> 56) as u8,
+            RngKind::CosmacVip => {
+                let low = (self.state as u8).wrapping_add(1);
+                let high = ((self.state >> 8) as u8).wrapping_add(VIP_TABLE[low as usize]);
+                self.state = (high as u64) << 8 | low as u64;
+                high
+            }
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::from_entropy(RngKind::default())
+    }
+}
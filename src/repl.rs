@@ -0,0 +1,304 @@
+// Terminal debugger, started with --debug ROM. Runs the machine headless with the core's Debugger, so
+// everything here can also be done from code. Type "help" for the commands. With a Listing (an Octo
+// file, or a ROM with a source map next to it) the source line at PC is shown too.
+
+use chip_8::asm::Listing;
+use chip_8::debugger::{Compare, Condition, Register, Watchpoint};
+use chip_8::disasm::{self, Syntax};
+use chip_8::headless;
+use chip_8::{Chip8, Debugger, NullAudio};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+s, step [N]          run N instructions (default 1)
+f, frame [N]         run N frames (default 1)
+c, continue          run until something breaks
+b, break ADDR        set a breakpoint
+d, delete ADDR       remove a breakpoint
+w, watch ADDR [r|w|rw]  stop after ADDR is read and/or written (default rw)
+unwatch N            remove watchpoint N
+cond REG OP VALUE    stop when e.g. V3 == 5, I >= 300 (OP is == != < <= > >=, values in hex)
+uncond N             remove condition N
+unknown on|off       stop on unknown opcodes
+skip                 step over a trapped unknown opcode
+info                 list breakpoints, watchpoints and conditions
+r, regs              show registers
+m, mem ADDR [LEN]    dump memory
+set REG VALUE        change V0-VF, I or PC
+poke ADDR VALUE      change a byte of memory
+key K down|up        press or release keypad key K
+screen               print the framebuffer
+l, list [N]          show N source lines either side of PC (default 5)
+q, quit              exit";
+
+// The most a single continue runs before giving back the prompt, so a ROM that never hits anything
+// doesn't lock up the terminal. Ten minutes of game time.
+const CONTINUE_FRAMES: u32 = 60 * 60 * 10;
+
+pub fn run(
+    mut cpu: Chip8,
+    instructions_per_frame: u32,
+    listing: Option<Listing>,
+) -> Result<(), String> {
+    let listing = listing.as_ref();
+    let mut debugger = Debugger::new();
+    let mut keys = [false; 16];
+    let mut audio = NullAudio;
+
+    println!("CHIP-8 debugger, type \"help\" for commands");
+    print_registers(&cpu, listing);
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        line.clear();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            continue;
+        };
+
+        let mut stop = None;
+        match *command {
+            "s" | "step" => {
+                for _ in 0..count(args) {
+                    stop = debugger.step_instruction(&mut cpu, &keys, &mut audio);
+                    if stop.is_some() {
+                        break;
+                    }
+                }
+                print_registers(&cpu, listing);
+            }
+            "f" | "frame" => {
+                for _ in 0..count(args) {
+                    stop = debugger.step_frame(&mut cpu, &keys, &mut audio, instructions_per_frame);
+                    if stop.is_some() {
+                        break;
+                    }
+                }
+                print_registers(&cpu, listing);
+            }
+            "c" | "continue" => {
+                debugger.resume();
+                for _ in 0..CONTINUE_FRAMES {
+                    stop = debugger.run_frame(&mut cpu, &keys, &mut audio, instructions_per_frame);
+                    // Nobody is going to press a key while we're in here
+                    if stop.is_some() || cpu.is_waiting_for_key() {
+                        break;
+                    }
+                }
+                debugger.pause();
+                if cpu.is_waiting_for_key() {
+                    println!("Waiting for a key (FX0A), use \"key\" to press one");
+                }
+                print_registers(&cpu, listing);
+            }
+            "b" | "break" => match args.first().and_then(|a| parse_hex(a)) {
+                Some(address) => {
+                    debugger.add_breakpoint(address as u16);
+                }
+                None => println!("Usage: break ADDR"),
+            },
+            "d" | "delete" => match args.first().and_then(|a| parse_hex(a)) {
+                Some(address) if debugger.remove_breakpoint(address as u16) => {}
+                _ => println!("No such breakpoint"),
+            },
+            "w" | "watch" => {
+                let kind = args.get(1).copied().unwrap_or("rw");
+                match args.first().and_then(|a| parse_hex(a)) {
+                    Some(address) if matches!(kind, "r" | "w" | "rw") => {
+                        debugger.add_watchpoint(Watchpoint {
+                            address,
+                            read: kind.contains('r'),
+                            write: kind.contains('w'),
+                        })
+                    }
+                    _ => println!("Usage: watch ADDR [r|w|rw]"),
+                }
+            }
+            "unwatch" => match args.first().and_then(|a| a.parse().ok()) {
+                Some(j) if debugger.remove_watchpoint(j).is_some() => {}
+                _ => println!("No such watchpoint"),
+            },
+            "cond" => match parse_condition(args) {
+                Some(condition) => debugger.add_condition(condition),
+                None => println!("Usage: cond REG OP VALUE, e.g. cond V3 == 5"),
+            },
+            "uncond" => match args.first().and_then(|a| a.parse().ok()) {
+                Some(j) if debugger.remove_condition(j).is_some() => {}
+                _ => println!("No such condition"),
+            },
+            "unknown" => match args.first().copied() {
+                Some("on") => debugger.set_break_on_unknown_opcode(true),
+                Some("off") => debugger.set_break_on_unknown_opcode(false),
+                _ => println!(
+                    "Break on unknown opcode is {}",
+                    if debugger.break_on_unknown_opcode() {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                ),
+            },
+            "skip" => {
+                debugger.skip_unknown_opcode(&mut cpu);
+                print_registers(&cpu, listing);
+            }
+            "info" => print_info(&debugger),
+            "r" | "regs" => print_registers(&cpu, listing),
+            "m" | "mem" => match args.first().and_then(|a| parse_hex(a)) {
+                Some(address) => {
+                    let len = args.get(1).and_then(|a| parse_hex(a)).unwrap_or(0x40);
+                    print_memory(&cpu, address, len);
+                }
+                None => println!("Usage: mem ADDR [LEN]"),
+            },
+            "set" => {
+                let register = args.first().and_then(|a| Register::from_name(a));
+                match (register, args.get(1).and_then(|a| parse_hex(a))) {
+                    (Some(Register::V(x)), Some(value)) => cpu.set_register(x, value as u8),
+                    (Some(Register::I), Some(value)) => cpu.set_index(value as u16),
+                    (Some(Register::Pc), Some(value)) => cpu.set_pc(value as u16),
+                    _ => println!("Usage: set V0-VF|I|PC VALUE"),
+                }
+            }
+            "poke" => match (
+                args.first().and_then(|a| parse_hex(a)),
+                args.get(1).and_then(|a| parse_hex(a)),
+            ) {
+                (Some(address), Some(value)) => {
+                    if let Err(e) = cpu.poke(address, value as u8) {
+                        println!("{}", e);
+                    }
+                }
+                _ => println!("Usage: poke ADDR VALUE"),
+            },
+            "key" => match (args.first().and_then(|a| parse_hex(a)), args.get(1)) {
+                (Some(key), Some(&"down")) if key < 16 => keys[key] = true,
+                (Some(key), Some(&"up")) if key < 16 => keys[key] = false,
+                _ => println!("Usage: key 0-F down|up"),
+            },
+            "screen" => print_screen(&cpu),
+            "l" | "list" => match listing {
+                Some(listing) => {
+                    let context = args.first().and_then(|a| a.parse().ok()).unwrap_or(5);
+                    print_source(&cpu, listing, context);
+                }
+                None => println!("No source for this ROM"),
+            },
+            "help" | "h" | "?" => println!("{}", HELP),
+            "q" | "quit" | "exit" => return Ok(()),
+            _ => println!("Unknown command {}, try \"help\"", command),
+        }
+
+        if let Some(stop) = stop {
+            println!("{}", stop);
+        }
+    }
+}
+
+fn count(args: &[&str]) -> u32 {
+    args.first().and_then(|a| a.parse().ok()).unwrap_or(1)
+}
+
+// Addresses and values are hex, with or without 0x / #
+fn parse_hex(text: &str) -> Option<usize> {
+    let text = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('#');
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_condition(args: &[&str]) -> Option<Condition> {
+    let [register, compare, value] = args else {
+        return None;
+    };
+    Some(Condition {
+        register: Register::from_name(register)?,
+        compare: Compare::from_symbol(compare)?,
+        value: parse_hex(value)? as u16,
+    })
+}
+
+fn print_registers(cpu: &Chip8, listing: Option<&Listing>) {
+    let v: Vec<String> = cpu
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(x, value)| format!("V{:X}={:02X}", x, value))
+        .collect();
+    println!("{}", v.join(" "));
+    let opcode = disasm::disassemble_at(cpu.memory(), cpu.pc() as usize, Syntax::default())
+        .map(|(text, _)| text)
+        .unwrap_or_else(|| "????".to_string());
+    let stack: Vec<String> = cpu.stack()[..cpu.sp() as usize]
+        .iter()
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    println!(
+        "PC={:04X} [{}] I={:04X} SP={} DT={:02X} ST={:02X} stack=[{}]{}",
+        cpu.pc(),
+        opcode,
+        cpu.index(),
+        cpu.sp(),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        stack.join(" "),
+        if cpu.is_halted() { " halted" } else { "" }
+    );
+    if let Some((line, text)) = listing.and_then(|l| l.line_at(cpu.pc())) {
+        println!("{:>5} | {}", line, text.trim());
+    }
+}
+
+fn print_source(cpu: &Chip8, listing: &Listing, context: usize) {
+    let Some(current) = listing.map.line(cpu.pc()) else {
+        println!("PC isn't on a source line");
+        return;
+    };
+    let first = current.saturating_sub(context).max(1);
+    let last = (current + context).min(listing.lines.len());
+    for line in first..=last {
+        let marker = if line == current { '>' } else { ' ' };
+        println!("{}{:>5} | {}", marker, line, listing.lines[line - 1]);
+    }
+}
+
+fn print_info(debugger: &Debugger) {
+    let breakpoints: Vec<String> = debugger
+        .breakpoints()
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    println!("Breakpoints: {}", breakpoints.join(" "));
+    for (j, watchpoint) in debugger.watchpoints().iter().enumerate() {
+        println!("Watchpoint {}: {}", j, watchpoint);
+    }
+    for (j, condition) in debugger.conditions().enumerate() {
+        println!("Condition {}: {}", j, condition);
+    }
+}
+
+fn print_memory(cpu: &Chip8, address: usize, len: usize) {
+    let memory = cpu.memory();
+    let end = address.saturating_add(len).min(memory.len());
+    for row in (address..end).step_by(16) {
+        let bytes: Vec<String> = memory[row..(row + 16).min(end)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:04X}: {}", row, bytes.join(" "));
+    }
+}
+
+fn print_screen(cpu: &Chip8) {
+    print!("{}", headless::screen_text(cpu.framebuffer()));
+}
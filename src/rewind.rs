@@ -0,0 +1,199 @@
+// Rewind. Every few frames we take a Snapshot and keep it in a ring buffer. Only the newest one is kept
+// whole, every older frame is stored as the bytes that differ from the frame after it. Most frames only
+// touch a handful of memory bytes and some pixels, so that's a few hundred bytes instead of 4KiB (64KiB
+// for XO-CHIP) per frame.
+//
+// Stepping back undoes one delta at a time, and the oldest frames fall off the end once we run out of
+// the time or memory budget.
+
+use crate::cpu::Chip8;
+use crate::error::EmulatorError;
+use crate::savestate::Snapshot;
+use std::collections::VecDeque;
+
+pub const DEFAULT_SECONDS: u32 = 10;
+pub const DEFAULT_GRANULARITY: u32 = 1;
+pub const DEFAULT_BUDGET: usize = 16 * 1024 * 1024;
+
+// The bytes to change to get from one buffer to another.
+#[derive(Clone, Debug)]
+enum Patch {
+    // (offset, new bytes) for every run that changed.
+    Runs(Vec<(usize, Vec<u8>)>),
+    // The lengths differ (hi-res switch, a save state for another platform), just keep all of it.
+    Full(Vec<u8>),
+}
+
+impl Patch {
+    // Patch that turns `from` into `to`.
+    fn diff(from: &[u8], to: &[u8]) -> Patch {
+        if from.len() != to.len() {
+            return Patch::Full(to.to_vec());
+        }
+        let mut runs = vec![];
+        let mut j = 0;
+        while j < to.len() {
+            if from[j] == to[j] {
+                j += 1;
+                continue;
+            }
+            let start = j;
+            while j < to.len() && from[j] != to[j] {
+                j += 1;
+            }
+            runs.push((start, to[start..j].to_vec()));
+        }
+        Patch::Runs(runs)
+    }
+
+    fn apply(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Patch::Runs(runs) => {
+                for (offset, run) in runs {
+                    bytes[*offset..offset + run.len()].copy_from_slice(run);
+                }
+            }
+            Patch::Full(full) => *bytes = full.clone(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Patch::Runs(runs) => runs
+                .iter()
+                .map(|(_, run)| run.len() + std::mem::size_of::<(usize, Vec<u8>)>())
+                .sum(),
+            Patch::Full(full) => full.len(),
+        }
+    }
+}
+
+// One older frame: everything small copied whole, memory and pixels as patches against the next frame.
+#[derive(Clone, Debug)]
+struct Frame {
+    // memory and framebuffer pixels are left empty in here
+    state: Snapshot,
+    memory: Patch,
+    pixels: Patch,
+}
+
+impl Frame {
+    // What we need to store to get back to `older` from `newer`.
+    fn new(newer: &Snapshot, mut older: Snapshot) -> Frame {
+        let memory = Patch::diff(&newer.memory, &older.memory);
+        let pixels = Patch::diff(newer.framebuffer.pixels(), older.framebuffer.pixels());
+        older.memory = vec![];
+        older.framebuffer.take_pixels();
+        Frame {
+            state: older,
+            memory,
+            pixels,
+        }
+    }
+
+    fn undo(&self, newer: &Snapshot) -> Snapshot {
+        let mut older = self.state.clone();
+        older.memory = newer.memory.clone();
+        self.memory.apply(&mut older.memory);
+        let mut pixels = newer.framebuffer.pixels().to_vec();
+        self.pixels.apply(&mut pixels);
+        older.framebuffer.set_pixels(pixels);
+        older
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of::<Frame>() + self.memory.size() + self.pixels.size()
+    }
+}
+
+pub struct Rewind {
+    // Oldest first.
+    frames: VecDeque<Frame>,
+    newest: Option<Snapshot>,
+    bytes: usize,
+    max_frames: usize,
+    budget: usize,
+    granularity: u32,
+    counter: u32,
+}
+
+impl Rewind {
+    // Keep up to `seconds` of history in at most `budget` bytes, taking a snapshot every `granularity`
+    // frames. A coarser granularity goes back further for the same memory, but rewinds in bigger jumps.
+    pub fn new(seconds: u32, granularity: u32, budget: usize) -> Self {
+        let granularity = granularity.max(1);
+        Rewind {
+            frames: VecDeque::new(),
+            newest: None,
+            bytes: 0,
+            max_frames: (seconds.saturating_mul(60) / granularity) as usize,
+            budget,
+            granularity,
+            counter: 0,
+        }
+    }
+
+    // Call once per 60Hz frame while the game is running.
+    pub fn record(&mut self, cpu: &Chip8) {
+        self.counter += 1;
+        if self.counter < self.granularity {
+            return;
+        }
+        self.counter = 0;
+
+        let snapshot = cpu.snapshot();
+        if let Some(previous) = self.newest.take() {
+            let frame = Frame::new(&snapshot, previous);
+            self.bytes += frame.size();
+            self.frames.push_back(frame);
+        }
+        self.newest = Some(snapshot);
+
+        while self.frames.len() > self.max_frames || self.bytes > self.budget {
+            match self.frames.pop_front() {
+                Some(frame) => self.bytes -= frame.size(),
+                None => break,
+            }
+        }
+    }
+
+    // Goes back one recorded frame. Once there is nothing older left the machine stays on the oldest
+    // frame we have. Returns false only when nothing has been recorded, so the machine wasn't touched.
+    pub fn step_back(&mut self, cpu: &mut Chip8) -> Result<bool, EmulatorError> {
+        let Some(newest) = self.newest.take() else {
+            return Ok(false);
+        };
+        let older = match self.frames.pop_back() {
+            Some(frame) => {
+                self.bytes -= frame.size();
+                frame.undo(&newest)
+            }
+            None => newest,
+        };
+        cpu.restore(&older)?;
+        self.newest = Some(older);
+        self.counter = 0;
+        Ok(true)
+    }
+
+    // Roughly how much memory the history is using.
+    pub fn bytes_used(&self) -> usize {
+        self.bytes
+    }
+
+    // How many frames we could step back right now.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.newest = None;
+        self.bytes = 0;
+        self.counter = 0;
+    }
+}
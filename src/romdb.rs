@@ -0,0 +1,319 @@
+// Per-ROM profiles. Lots of ROMs only work with the right quirks and speed, and nobody remembers which
+// ones, so the settings that make a ROM work live in rom_db.json keyed by the SHA-1 of the ROM. The hash
+// rather than the file name, since the same game turns up as "Tetris.ch8", "TETRIS" and "tetris [Fran
+// Dachille, 1991].ch8". A profile only holds what it cares about, anything missing is left to the
+// launcher or command line:
+//
+//   { "roms": { "<sha1>": {
+//       "title": "Tetris", "description": "...",
+//       "platform": "chip8", "quirks": { ... }, "instructions_per_frame": 15,
+//       "keys": { "5": ["Up"] }, "gamepad": { "a": "4" },
+//       "palette": "amber", "colors": ["#000000", "#ffffff"] } } }
+//
+// The community database (https://github.com/chip-8/chip-8-database) knows the right settings for most
+// ROMs out there. Its programs.json can be imported from a local copy, see import_chip8_database.
+
+use crate::cpu::Mode;
+use crate::display;
+use crate::quirks::{MemoryIncrement, Quirks};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const ROM_DB_FILE: &str = "rom_db.json";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RomProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // A quirks preset name, picks the instruction set and the quirks if "quirks" isn't there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<Quirks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions_per_frame: Option<u32>,
+    // Same format as keymap.json, replacing the bindings of the CHIP-8 keys mentioned
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub gamepad: BTreeMap<String, String>,
+    // A palette preset name, replacing the one picked in the launcher
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    // "#rrggbb" for pixel values 0-3, on top of the palette
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub colors: Vec<String>,
+}
+
+impl RomProfile {
+    pub fn mode(&self) -> Option<Mode> {
+        self.platform.as_deref().and_then(Mode::from_preset)
+    }
+
+    pub fn machine_quirks(&self) -> Option<Quirks> {
+        self.quirks
+            .or_else(|| self.platform.as_deref().and_then(Quirks::preset))
+    }
+
+    // `palette` with this ROM's palette and colours on top.
+    pub fn apply_palette(&self, mut palette: [u32; 4]) -> [u32; 4] {
+        if let Some(name) = &self.palette {
+            match display::palette(name) {
+                Some(preset) => palette = preset,
+                None => eprintln!("Ignoring unknown palette {:?} in {}", name, ROM_DB_FILE),
+            }
+        }
+        for (slot, colour) in palette.iter_mut().zip(&self.colors) {
+            match parse_colour(colour) {
+                Some(rgb) => *slot = rgb,
+                None => eprintln!("Ignoring colour {:?} in {}", colour, ROM_DB_FILE),
+            }
+        }
+        palette
+    }
+
+    // Fills in whatever this profile doesn't say from `other`, so hand edits survive an import.
+    fn fill_from(&mut self, other: RomProfile) {
+        self.title = self.title.take().or(other.title);
+        self.description = self.description.take().or(other.description);
+        if self.platform.is_none() && self.quirks.is_none() {
+            self.platform = other.platform;
+            self.quirks = other.quirks;
+        }
+        self.instructions_per_frame = self.instructions_per_frame.or(other.instructions_per_frame);
+        if self.keys.is_empty() {
+            self.keys = other.keys;
+        }
+        if self.gamepad.is_empty() {
+            self.gamepad = other.gamepad;
+        }
+        if self.palette.is_none() && self.colors.is_empty() {
+            self.palette = other.palette;
+            self.colors = other.colors;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RomDatabase {
+    roms: BTreeMap<String, RomProfile>,
+}
+
+impl RomDatabase {
+    // An empty database when the file isn't there yet.
+    pub fn load_from_file(path: &Path) -> Result<RomDatabase, String> {
+        let Ok(data) = fs::read_to_string(path) else {
+            return Ok(RomDatabase::default());
+        };
+        serde_json::from_str(&data).map_err(|e| format!("{} is broken: {}", path.display(), e))
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&RomProfile> {
+        self.roms.get(&hash.to_ascii_lowercase())
+    }
+
+    pub fn profile_for(&self, rom: &[u8]) -> Option<&RomProfile> {
+        self.get(&rom_sha1(rom))
+    }
+
+    pub fn insert(&mut self, hash: &str, profile: RomProfile) {
+        self.roms.insert(hash.to_ascii_lowercase(), profile);
+    }
+
+    // Reads chip-8-database's programs.json. Every ROM it lists gets a profile, ROMs that are already
+    // in here only get the fields they don't have yet. Returns how many ROMs were imported.
+    pub fn import_chip8_database(&mut self, programs_json: &str) -> Result<usize, String> {
+        let programs: Vec<Program> = serde_json::from_str(programs_json)
+            .map_err(|e| format!("Not chip-8-database's programs.json: {}", e))?;
+        let mut count = 0;
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let imported = rom.profile(&program.title, &program.description);
+                self.roms
+                    .entry(hash.to_ascii_lowercase())
+                    .or_default()
+                    .fill_from(imported);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+// Lowercase hex, the way chip-8-database writes them.
+pub fn rom_sha1(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn parse_colour(text: &str) -> Option<u32> {
+    let hex = text
+        .trim()
+        .strip_prefix('#')
+        .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))?;
+    match hex.len() {
+        6 => u32::from_str_radix(hex, 16).ok(),
+        // #rgb
+        3 => u32::from_str_radix(hex, 16).ok().map(|rgb| {
+            let (r, g, b) = ((rgb >> 8) & 0xf, (rgb >> 4) & 0xf, rgb & 0xf);
+            (r * 0x11) << 16 | (g * 0x11) << 8 | (b * 0x11)
+        }),
+        _ => None,
+    }
+}
+
+// The bits of chip-8-database we use. It has a lot more (authors, release dates, touch input...), serde
+// skips that.
+#[derive(Deserialize)]
+struct Program {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    roms: BTreeMap<String, ProgramRom>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ProgramRom {
+    // Best fit first
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    // Quirks that differ from the platform's usual ones, per platform
+    quirky_platforms: BTreeMap<String, BTreeMap<String, Value>>,
+    colors: Option<ProgramColors>,
+    // "up", "a" etc. to a CHIP-8 key
+    keys: BTreeMap<String, Value>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ProgramColors {
+    pixels: Vec<String>,
+}
+
+impl ProgramRom {
+    fn profile(self, title: &Option<String>, description: &Option<String>) -> RomProfile {
+        // MegaChip and CHIP-8X aren't emulated, those ROMs just get their title
+        let platform = self
+            .platforms
+            .iter()
+            .find_map(|id| platform_quirks(id).map(|(name, quirks)| (id, name, quirks)));
+        let (platform, quirks) = match platform {
+            Some((id, name, mut quirks)) => {
+                if let Some(changes) = self.quirky_platforms.get(id) {
+                    apply_quirks(&mut quirks, changes);
+                }
+                (Some(name.to_string()), Some(quirks))
+            }
+            None => (None, None),
+        };
+        let gamepad = self
+            .keys
+            .iter()
+            .filter_map(|(name, key)| {
+                let button = match name.as_str() {
+                    "up" => "dpup",
+                    "down" => "dpdown",
+                    "left" => "dpleft",
+                    "right" => "dpright",
+                    "a" => "a",
+                    "b" => "b",
+                    _ => return None,
+                };
+                let key = key.as_u64().filter(|k| *k < 16)?;
+                Some((button.to_string(), format!("{:X}", key)))
+            })
+            .collect();
+        RomProfile {
+            title: title.clone(),
+            description: description.clone(),
+            platform,
+            quirks,
+            instructions_per_frame: self.tickrate.filter(|t| *t > 0),
+            keys: BTreeMap::new(),
+            gamepad,
+            palette: None,
+            colors: self
+                .colors
+                .map(|colors| {
+                    colors
+                        .pixels
+                        .into_iter()
+                        .filter(|c| parse_colour(c).is_some())
+                        .take(4)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// chip-8-database platform ids to our presets. Its "modern" CHIP-8 is the VIP instruction set with the
+// quirks most later interpreters settled on.
+fn platform_quirks(id: &str) -> Option<(&'static str, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some(("chip8", Quirks::chip8())),
+        "modernChip8" => Some((
+            "chip8",
+            Quirks {
+                vf_reset: false,
+                display_wait: false,
+                ..Quirks::chip8()
+            },
+        )),
+        "chip48" => Some(("chip48", Quirks::chip48())),
+        "superchip1" => Some((
+            "schip",
+            Quirks {
+                memory_increment: MemoryIncrement::X,
+                ..Quirks::schip()
+            },
+        )),
+        "superchip" => Some(("schip", Quirks::schip())),
+        "xochip" => Some(("xochip", Quirks::xochip())),
+        _ => None,
+    }
+}
+
+// Quirk names from chip-8-database's quirks.json. The ones we don't have a switch for are skipped.
+fn apply_quirks(quirks: &mut Quirks, changes: &BTreeMap<String, Value>) {
+    for (name, value) in changes {
+        let Some(on) = value.as_bool() else {
+            continue;
+        };
+        match name.as_str() {
+            "shift" => quirks.shift = on,
+            "memoryIncrementByX" if on => quirks.memory_increment = MemoryIncrement::X,
+            "memoryLeaveIUnchanged" if on => quirks.memory_increment = MemoryIncrement::None,
+            "wrap" => quirks.clipping = !on,
+            "jump" => quirks.jump = on,
+            "vblank" => quirks.display_wait = on,
+            "logic" => quirks.vf_reset = on,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Save states. A Snapshot is the complete machine state as plain data: keep it in memory for tooling, or
+// write it to disk as JSON. Files carry a version number so an old save doesn't get loaded into a core
+// that lays things out differently.
+
+use crate::cpu::{KeyWait, Mode};
+use crate::display::Framebuffer;
+use crate::error::EmulatorError;
+use crate::quirks::Quirks;
+use crate::random::Random;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SAVE_STATE_VERSION: u32 = 1;
+pub const SLOTS: u8 = 4;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub mode: Mode,
+    pub quirks: Quirks,
+    #[serde(with = "hex_bytes")]
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub framebuffer: Framebuffer,
+    pub vblank_ready: bool,
+    pub rpl: [u8; 16],
+    pub halted: bool,
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub key_wait: Option<KeyWait>,
+    // Saves from before the generator was part of the machine get a fresh one.
+    #[serde(default)]
+    pub random: Random,
+}
+
+impl Snapshot {
+    pub fn save_to_file(&self, path: &Path) -> Result<(), EmulatorError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data =
+            serde_json::to_string(self).map_err(|e| EmulatorError::SaveState(e.to_string()))?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Snapshot, EmulatorError> {
+        let data = fs::read_to_string(path)?;
+        Self::from_json(&data)
+    }
+
+    pub fn from_json(data: &str) -> Result<Snapshot, EmulatorError> {
+        // Check the version before anything else so the error says what is actually wrong.
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+        }
+        let header: Header =
+            serde_json::from_str(data).map_err(|e| EmulatorError::SaveState(e.to_string()))?;
+        if header.version != SAVE_STATE_VERSION {
+            return Err(EmulatorError::SaveState(format!(
+                "Save state version {} is not supported (expected {})",
+                header.version, SAVE_STATE_VERSION
+            )));
+        }
+        serde_json::from_str(data).map_err(|e| EmulatorError::SaveState(e.to_string()))
+    }
+}
+
+// Where slot N for a ROM lives, e.g. saves/Pong.ch8/slot1.json
+pub fn slot_path(dir: &Path, rom_name: &str, slot: u8) -> PathBuf {
+    dir.join(rom_name).join(format!("slot{}.json", slot))
+}
+
+// Memory as one hex string instead of a JSON array of numbers, a 64KiB XO-CHIP dump would be huge.
+pub(crate) mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if !hex.is_ascii() || hex.len() % 2 != 0 {
+            return Err(D::Error::custom("expected an even number of hex digits"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|j| u8::from_str_radix(&hex[j..j + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
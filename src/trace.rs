@@ -0,0 +1,161 @@
+// Levelled, per-category tracing. Everything is off by default and a disabled category costs one atomic
+// load, so the hot loop doesn't pay for formatting messages nobody reads (the old println! per opcode
+// ate most of the frame time).
+//
+// Enable with a spec like "cpu=trace,input=debug" or "all=info", from the command line (--trace) or the
+// launcher. Output goes to stderr unless a trace file is set.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Cpu,
+    Input,
+    Timers,
+    Display,
+}
+
+pub const CATEGORIES: [Category; 4] = [
+    Category::Cpu,
+    Category::Input,
+    Category::Timers,
+    Category::Display,
+];
+
+pub const LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+// Most verbose level enabled per category, 0 = off. Indexed by Category as usize.
+static ENABLED: [AtomicU8; 4] = [
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+];
+
+static OUTPUT: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LEVELS.into_iter().find(|l| l.name() == name)
+    }
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Cpu => "cpu",
+            Category::Input => "input",
+            Category::Timers => "timers",
+            Category::Display => "display",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CATEGORIES.into_iter().find(|c| c.name() == name)
+    }
+}
+
+pub fn enabled(category: Category, level: Level) -> bool {
+    ENABLED[category as usize].load(Ordering::Relaxed) >= level as u8
+}
+
+pub fn level(category: Category) -> Option<Level> {
+    let l = ENABLED[category as usize].load(Ordering::Relaxed);
+    LEVELS.into_iter().find(|level| *level as u8 == l)
+}
+
+// None turns the category off.
+pub fn set_level(category: Category, level: Option<Level>) {
+    ENABLED[category as usize].store(level.map_or(0, |l| l as u8), Ordering::Relaxed);
+}
+
+// Parses "cpu=trace,input" style specs. A category without a level means trace, "all" means every
+// category and "off" as a level turns it off again.
+pub fn configure(spec: &str) -> Result<(), String> {
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (name, level) = part.split_once('=').unwrap_or((part, "trace"));
+        let level = match level {
+            "off" => None,
+            l => Some(Level::from_name(l).ok_or(format!("Unknown trace level: {}", l))?),
+        };
+        if name == "all" {
+            for category in CATEGORIES {
+                set_level(category, level);
+            }
+        } else {
+            let category =
+                Category::from_name(name).ok_or(format!("Unknown trace category: {}", name))?;
+            set_level(category, level);
+        }
+    }
+    Ok(())
+}
+
+// Send trace output to a file instead of stderr. None goes back to stderr.
+pub fn set_output_file(path: Option<&Path>) -> Result<(), String> {
+    let file = match path {
+        Some(path) => Some(LineWriter::new(
+            File::create(path).map_err(|e| e.to_string())?,
+        )),
+        None => None,
+    };
+    *OUTPUT.lock().map_err(|e| e.to_string())? = file;
+    Ok(())
+}
+
+// Use the trace! macro rather than calling this, it skips the formatting when the category is off.
+pub fn log(category: Category, level: Level, args: fmt::Arguments) {
+    if let Ok(mut output) = OUTPUT.lock()
+        && let Some(file) = output.as_mut()
+    {
+        let _ = writeln!(file, "[{}:{}] {}", category.name(), level.name(), args);
+        return;
+    }
+    eprintln!("[{}:{}] {}", category.name(), level.name(), args);
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($category:ident, $level:ident, $($arg:tt)*) => {
+        if $crate::trace::enabled(
+            $crate::trace::Category::$category,
+            $crate::trace::Level::$level,
+        ) {
+            $crate::trace::log(
+                $crate::trace::Category::$category,
+                $crate::trace::Level::$level,
+                format_args!($($arg)*),
+            );
+        }
+    };
+}
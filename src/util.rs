@@ -4,7 +4,7 @@ pub fn get_hex_digits(n: &u16, d: u32, o: u32) -> usize {
 }
 
 pub fn is_bit_set(byte: &u8, n: u8) -> bool {
-    if byte & (1 << n) == 0 { false } else { true }
+    byte & (1 << n) != 0
 }
 
 pub fn get_bit(byte: &u8, n: u8) -> u8 {
@@ -1,112 +1,116 @@
 // Stole some of my own code from my SDL3 raycaster and Brick Breaker then modified it for Chip-8. Hopefully, I didn't forget to change anything.
 // I literally had brick breaker as the title of my raycaster :|
 
-use sdl3::pixels::Color;
-use sdl3::rect::Point;
-use sdl3::render::Canvas;
+use sdl3::pixels::{Color, PixelFormat, PixelFormatEnum};
+use sdl3::render::{Canvas, ScaleMode, Texture};
 use sdl3::video::Window as SDLWindow;
 
-use crate::util::is_bit_set;
-
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
-const PX_OFF: u32 = 0x81c784;
-const PX_ON: u32 = 0x29302a;
+use chip_8::display::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+use chip_8::{Framebuffer, Phosphor};
 
 pub struct Window {
     canvas: Canvas<SDLWindow>,
-    framebuffer: [u32; WIDTH * HEIGHT],
+    // The framebuffer gets uploaded into one of these and the GPU scales it up to the window. One per
+    // resolution so switching to SUPER-CHIP hi-res doesn't need a new texture.
+    lores: Texture,
+    hires: Texture,
+    // RGB bytes for the upload, kept around so a frame doesn't allocate
+    rgb: Vec<u8>,
+    // 0xRRGGBB per pixel value, the core's PALETTE unless a ROM profile has its own colours
+    palette: [u32; 4],
+    // Anti-flicker blending, None shows each vblank's framebuffer as it is
+    phosphor: Option<Phosphor>,
 }
 
 impl Window {
-    pub fn new(title: &str, scale: u32) -> Result<Window, String> {
+    pub fn new(
+        title: &str,
+        scale: u32,
+        palette: [u32; 4],
+        phosphor: Option<Phosphor>,
+    ) -> Result<Window, String> {
         let sdl = sdl3::init().map_err(|e| e.to_string())?;
         let video = sdl.video().map_err(|e| e.to_string())?;
 
         let window = video
-            .window(
-                title,
-                (WIDTH as u32 * scale) as u32,
-                (HEIGHT as u32 * scale) as u32,
-            )
+            .window(title, WIDTH as u32 * scale, HEIGHT as u32 * scale)
             .position_centered()
             .build()
             .map_err(|e| e.to_string())?;
 
         let mut canvas = window.into_canvas();
+        let lores = screen_texture(&canvas, WIDTH, HEIGHT)?;
+        let hires = screen_texture(&canvas, HIRES_WIDTH, HIRES_HEIGHT)?;
 
-        canvas.set_scale(scale as f32, scale as f32).unwrap();
-        canvas.set_draw_color(Color::RGB(
-            (PX_OFF >> 16) as u8,
-            (PX_OFF >> 8) as u8,
-            PX_OFF as u8,
-        ));
+        let off = palette[0];
+        canvas.set_draw_color(Color::RGB((off >> 16) as u8, (off >> 8) as u8, off as u8));
         canvas.clear();
         canvas.present();
 
         Ok(Window {
             canvas,
-            framebuffer: [PX_OFF; WIDTH * HEIGHT],
+            lores,
+            hires,
+            rgb: Vec::with_capacity(HIRES_WIDTH * HIRES_HEIGHT * 3),
+            palette,
+            phosphor,
         })
     }
+}
 
-    pub fn clear_screen(&mut self) {
-        for j in 0..self.framebuffer.len() {
-            self.framebuffer[j] = PX_OFF;
-        }
-    }
-
-    pub fn draw(&mut self, bytes: &Vec<u8>, init_x: u8, init_y: u8) -> u8 {
-        let mut collision: u8 = 0;
-        for (k, b) in bytes.iter().enumerate() {
-            for j in 0..8 {
-                let x = (init_x as usize + j) % WIDTH;
-                let y = (init_y as usize + k) % HEIGHT;
-                let coord = (y * WIDTH) + x;
-                let is_old_set = self.framebuffer[coord] == PX_ON;
-                self.framebuffer[coord] = if is_bit_set(b, (8 - j - 1) as u8) {
-                    if is_old_set {
-                        collision = 1;
-                        PX_OFF
-                    } else {
-                        PX_ON
-                    }
-                } else {
-                    self.framebuffer[coord]
-                };
-            }
-        }
-        collision
+// Streaming, since it's rewritten every frame, and nearest neighbour so pixels stay square.
+fn screen_texture(
+    canvas: &Canvas<SDLWindow>,
+    width: usize,
+    height: usize,
+) -> Result<Texture, String> {
+    let texture = canvas
+        .create_texture_streaming(
+            PixelFormat::from(PixelFormatEnum::RGB24),
+            width as u32,
+            height as u32,
+        )
+        .map_err(|e| e.to_string())?;
+    // sdl3-rs leaves set_scale_mode out of its unsafe_textures Texture, so straight to SDL
+    let nearest = unsafe {
+        sdl3::sys::render::SDL_SetTextureScaleMode(texture.raw(), ScaleMode::Nearest.into())
+    };
+    if !nearest {
+        return Err(sdl3::get_error().to_string());
     }
+    Ok(texture)
+}
 
-    pub fn refresh(&mut self) -> Result<(), String> {
-        self.canvas.set_draw_color(Color::RGB(
-            (PX_OFF >> 16) as u8,
-            (PX_OFF >> 8) as u8,
-            PX_OFF as u8,
-        ));
-        self.canvas.clear();
-
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let idx = y * WIDTH + x;
-                let pixel = self.framebuffer[idx];
-                self.canvas.set_draw_color(Color::RGB(
-                    (pixel >> 16) as u8,
-                    (pixel >> 8) as u8,
-                    pixel as u8,
-                ));
-                self.canvas
-                    .draw_point(Point::new(x as i32, y as i32))
-                    .map_err(|e| e.to_string())?;
+// The core hands us its framebuffer once a frame, we turn colour indices into RGB and let SDL blit it.
+impl chip_8::Display for Window {
+    fn refresh(&mut self, framebuffer: &Framebuffer) -> Result<(), String> {
+        match &mut self.phosphor {
+            Some(phosphor) => phosphor.blend(framebuffer, &self.palette, &mut self.rgb),
+            None => {
+                self.rgb.clear();
+                for pixel in framebuffer.pixels() {
+                    let colour = self.palette[*pixel as usize & 0x3];
+                    self.rgb.extend_from_slice(&[
+                        (colour >> 16) as u8,
+                        (colour >> 8) as u8,
+                        colour as u8,
+                    ]);
+                }
             }
         }
 
+        let texture = if framebuffer.is_hires() {
+            &mut self.hires
+        } else {
+            &mut self.lores
+        };
+        texture
+            .update(None, &self.rgb, framebuffer.width() * 3)
+            .map_err(|e| e.to_string())?;
+        self.canvas
+            .copy(texture, None, None)
+            .map_err(|e| e.to_string())?;
         self.canvas.present();
         Ok(())
     }
-
-    pub fn is_open(&self) -> bool {
-        true
-    }
 }
@@ -0,0 +1,157 @@
+// Assembler snippets and the exact bytes they should turn into.
+
+use chip_8::asm::{self, AssembleError};
+
+fn assemble(source: &str) -> Vec<u8> {
+    asm::assemble(source)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .rom
+}
+
+fn error(source: &str) -> AssembleError {
+    match asm::assemble(source) {
+        Ok(program) => panic!("assembled to {:02x?}", program.rom),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn forward_references_get_patched() {
+    let rom = assemble(
+        "
+        : main
+          sub
+          jump done
+          v0 := 1
+        : done
+          i := sprite
+          jump done
+        : sub
+          return
+        : sprite
+          0xF0
+        ",
+    );
+    assert_eq!(
+        rom,
+        [
+            0x22, 0x0A, 0x12, 0x06, 0x60, 0x01, 0xA2, 0x0C, 0x12, 0x06, 0x00, 0xEE, 0xF0
+        ]
+    );
+}
+
+#[test]
+fn main_further_down_gets_a_jump() {
+    let rom = assemble(": data 0x12 0x34 : main jump main");
+    assert_eq!(rom, [0x12, 0x04, 0x12, 0x34, 0x12, 0x04]);
+}
+
+#[test]
+fn nested_if_and_loop() {
+    let rom = assemble(
+        "
+        : main
+          loop
+            if v0 == 3 begin
+              v1 += 1
+            else
+              v2 += 1
+            end
+            v0 += 1
+            while v0 != 5
+          again
+        ",
+    );
+    #[rustfmt::skip]
+    let expected = [
+        0x30, 0x03, // 200: if v0 == 3 begin, skip the jump to else when it holds
+        0x12, 0x08, // 202: jump to else
+        0x71, 0x01, // 204: v1 += 1
+        0x12, 0x0A, // 206: else, jump past the else branch
+        0x72, 0x01, // 208: v2 += 1
+        0x70, 0x01, // 20A: end, v0 += 1
+        0x40, 0x05, // 20C: while v0 != 5, skip the exit while it holds
+        0x12, 0x12, // 20E: exit the loop
+        0x12, 0x00, // 210: again
+    ];
+    assert_eq!(rom, expected);
+}
+
+#[test]
+fn comparisons() {
+    let cases: [(&str, &[u16]); 10] = [
+        ("v1 == v2", &[0x9120]),
+        ("v1 != v2", &[0x5120]),
+        ("v1 == 7", &[0x4107]),
+        ("v1 != 7", &[0x3107]),
+        // The ordered ones go through VF: load the right hand side, subtract, test the borrow flag
+        ("v1 < v2", &[0x8F20, 0x8F17, 0x3F01]),
+        ("v1 >= v2", &[0x8F20, 0x8F17, 0x3F00]),
+        ("v1 > 7", &[0x6F07, 0x8F15, 0x3F01]),
+        ("v1 <= 7", &[0x6F07, 0x8F15, 0x3F00]),
+        ("v1 key", &[0xE1A1]),
+        ("v1 -key", &[0xE19E]),
+    ];
+    for (condition, skips) in cases {
+        let rom = assemble(&format!(": main if {} then v3 := 1", condition));
+        let mut expected: Vec<u8> = skips.iter().flat_map(|op| op.to_be_bytes()).collect();
+        expected.extend([0x63, 0x01]);
+        assert_eq!(rom, expected, "if {} then", condition);
+    }
+}
+
+#[test]
+fn calc_expressions() {
+    let rom = assemble(
+        ":calc a { ( 3 + 4 ) * 2 } :calc b { 1 << 4 | 0x0F % 4 } :calc c { -1 & 0xFF } \
+         : main :byte a :byte b :byte c",
+    );
+    assert_eq!(rom, [14, 0x13, 0xFF]);
+}
+
+#[test]
+fn undefined_label() {
+    let e = error(": main\n  v0 := 1\n  jump nowhere\n");
+    assert_eq!(e.line, 3);
+    assert!(e.message.contains("nowhere"), "{}", e);
+}
+
+#[test]
+fn values_out_of_range() {
+    assert!(error(": main v0 := 256").message.contains("byte"));
+    assert!(error(": main :byte -129").message.contains("byte"));
+    assert!(error(": main sprite v0 v1 16").message.contains("nibble"));
+    assert!(error(": main jump 0x1000").message.contains("out of range"));
+}
+
+#[test]
+fn bad_arithmetic() {
+    assert!(
+        error(":calc x { 1 / 0 }")
+            .message
+            .contains("Division by zero")
+    );
+    assert!(
+        error(":calc x { 1 % 0 }")
+            .message
+            .contains("Division by zero")
+    );
+    assert!(error(":calc x { 1 << 64 }").message.contains("Shift"));
+    assert!(error(":calc x { 1 >> -1 }").message.contains("Shift"));
+}
+
+#[test]
+fn blocks_past_0xfff_are_rejected() {
+    let sources = [
+        ": main jump far :org 0x1000 : far loop again",
+        ": main jump far :org 0x1000 : far if v0 == 1 begin v1 := 2 end",
+        ": main jump far :org 0x1000 : far if v0 == 1 begin v1 := 2 else v1 := 3 end",
+        ": main jump far :org 0xFF8 : far loop v0 += 1 while v0 != 5 again",
+    ];
+    for source in sources {
+        let e = error(source);
+        assert!(e.message.contains("past 0xFFF"), "{}: {}", source, e);
+    }
+    // Right up to the edge is fine
+    assemble(": main :org 0xFF6 loop v0 += 1 while v0 != 5 again");
+}
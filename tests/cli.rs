@@ -0,0 +1,32 @@
+// `chip_8 run` / chip8-run argument parsing.
+
+use chip_8::cli::RunArgs;
+
+fn parse(args: &[&str]) -> Result<RunArgs, String> {
+    RunArgs::parse(args.iter().map(|arg| arg.to_string()), 8)
+}
+
+#[test]
+fn tracing_works_with_and_without_a_window() {
+    for headless in [false, true] {
+        let mut args = vec!["rom.ch8", "--trace", "cpu=debug", "--trace-file", "t.log"];
+        if headless {
+            args.push("--headless");
+        }
+        let run = parse(&args).unwrap();
+        assert_eq!(run.headless, headless);
+        assert_eq!(run.trace_file.as_deref(), Some("t.log".as_ref()));
+    }
+}
+
+#[test]
+fn dumps_need_headless() {
+    let error = parse(&["rom.ch8", "--dump-screen", "-"]).err().unwrap();
+    assert!(error.contains("--headless"), "{}", error);
+}
+
+#[test]
+fn bad_trace_spec_fails_when_tracing_starts() {
+    let run = parse(&["rom.ch8", "--trace", "nonsense=trace"]).unwrap();
+    assert!(run.start_tracing().is_err());
+}
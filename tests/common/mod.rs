@@ -0,0 +1,56 @@
+// Shared by the integration tests: building machines for every quirk profile and feeding them code.
+
+#![allow(dead_code)]
+
+use chip_8::quirks::PRESETS;
+use chip_8::{Chip8, EmulatorError, Mode, Quirks};
+
+// Every preset the launcher offers, by name.
+pub fn profiles() -> impl Iterator<Item = &'static str> {
+    PRESETS.iter().map(|(name, _)| *name)
+}
+
+pub fn machine(profile: &str) -> Chip8 {
+    let quirks = Quirks::preset(profile).expect("known preset");
+    let mode = Mode::from_preset(profile).expect("known preset");
+    Chip8::with_mode(mode, quirks)
+}
+
+// A machine with `program` loaded at 0x200, one opcode per word.
+pub fn load(profile: &str, program: &[u16]) -> Chip8 {
+    let mut cpu = machine(profile);
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    cpu.load_rom(&rom).expect("ROM fits");
+    cpu
+}
+
+// Runs `count` instructions with nothing pressed.
+pub fn step(cpu: &mut Chip8, count: usize) -> Result<(), EmulatorError> {
+    step_with_keys(cpu, count, &[false; 16])
+}
+
+pub fn step_with_keys(
+    cpu: &mut Chip8,
+    count: usize,
+    keys: &[bool; 16],
+) -> Result<(), EmulatorError> {
+    for _ in 0..count {
+        cpu.step(keys)?;
+    }
+    Ok(())
+}
+
+// Loads `program` for `profile`, runs every instruction in it once and hands back the machine.
+pub fn run(profile: &str, program: &[u16]) -> Chip8 {
+    let mut cpu = load(profile, program);
+    step(&mut cpu, program.len()).unwrap_or_else(|e| panic!("{}: {}", profile, e));
+    cpu
+}
+
+pub fn has_schip(profile: &str) -> bool {
+    Mode::from_preset(profile).is_some_and(|mode| mode.has_schip())
+}
+
+pub fn has_xochip(profile: &str) -> bool {
+    Mode::from_preset(profile).is_some_and(|mode| mode.has_xochip())
+}
@@ -0,0 +1,174 @@
+// The debugger stops where it's told to and gets out of the way again on resume.
+
+mod common;
+
+use chip_8::bus::{Access, MemoryAccess};
+use chip_8::debugger::{Compare, Condition, Register, Stop, Watchpoint};
+use chip_8::{Chip8, Debugger, NullAudio, UnknownOpcodePolicy};
+
+fn frame(debugger: &mut Debugger, cpu: &mut Chip8) -> Option<Stop> {
+    debugger.run_frame(cpu, &[false; 16], &mut NullAudio, 8)
+}
+
+#[test]
+fn breakpoint_stops_and_resumes() {
+    // 200: v0 := 1, 202: v0 += 1, 204: jump 202
+    let mut cpu = common::load("chip8", &[0x6001, 0x7001, 0x1202]);
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(0x202);
+
+    assert_eq!(
+        frame(&mut debugger, &mut cpu),
+        Some(Stop::Breakpoint(0x202))
+    );
+    assert!(debugger.is_paused());
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.registers()[0], 1);
+
+    // Paused, nothing runs
+    assert_eq!(frame(&mut debugger, &mut cpu), None);
+    assert_eq!(cpu.registers()[0], 1);
+
+    // Resuming gets off the breakpoint and stops on it again the next time round
+    debugger.resume();
+    assert_eq!(
+        frame(&mut debugger, &mut cpu),
+        Some(Stop::Breakpoint(0x202))
+    );
+    assert_eq!(cpu.registers()[0], 2);
+
+    debugger.remove_breakpoint(0x202);
+    debugger.resume();
+    assert_eq!(frame(&mut debugger, &mut cpu), None);
+    assert!(!debugger.is_paused());
+}
+
+// 200: i := 300, 202: v0 := 5, 204: save v0, 206: i := 300, 208: load v0, 20A: jump 20A
+const SAVE_THEN_LOAD: [u16; 6] = [0xA300, 0x6005, 0xF055, 0xA300, 0xF065, 0x120A];
+
+#[test]
+fn write_watchpoint() {
+    let mut cpu = common::load("chip8", &SAVE_THEN_LOAD);
+    let mut debugger = Debugger::new();
+    debugger.add_watchpoint(Watchpoint {
+        address: 0x300,
+        read: false,
+        write: true,
+    });
+    assert_eq!(
+        frame(&mut debugger, &mut cpu),
+        Some(Stop::Watchpoint {
+            pc: 0x204,
+            access: MemoryAccess {
+                address: 0x300,
+                access: Access::Write,
+            },
+        })
+    );
+    assert_eq!(cpu.memory()[0x300], 5);
+    debugger.resume();
+    assert_eq!(frame(&mut debugger, &mut cpu), None);
+}
+
+#[test]
+fn read_watchpoint() {
+    let mut cpu = common::load("chip8", &SAVE_THEN_LOAD);
+    let mut debugger = Debugger::new();
+    debugger.add_watchpoint(Watchpoint {
+        address: 0x300,
+        read: true,
+        write: false,
+    });
+    // The write at 204 goes by, the read at 208 doesn't
+    assert_eq!(
+        frame(&mut debugger, &mut cpu),
+        Some(Stop::Watchpoint {
+            pc: 0x208,
+            access: MemoryAccess {
+                address: 0x300,
+                access: Access::Read,
+            },
+        })
+    );
+}
+
+#[test]
+fn condition_stops_when_it_becomes_true() {
+    // 200: v0 += 1, 202: jump 200
+    let mut cpu = common::load("chip8", &[0x7001, 0x1200]);
+    let mut debugger = Debugger::new();
+    debugger.add_condition(Condition {
+        register: Register::V(0),
+        compare: Compare::Eq,
+        value: 3,
+    });
+    debugger.add_condition(Condition {
+        register: Register::V(0),
+        compare: Compare::Ge,
+        value: 6,
+    });
+
+    assert_eq!(frame(&mut debugger, &mut cpu), Some(Stop::Condition(0)));
+    assert_eq!(cpu.registers()[0], 3);
+    debugger.resume();
+    assert_eq!(frame(&mut debugger, &mut cpu), Some(Stop::Condition(1)));
+    assert_eq!(cpu.registers()[0], 6);
+
+    // V0 >= 6 still holds, it only fires again once it has been false in between
+    debugger.resume();
+    assert_eq!(frame(&mut debugger, &mut cpu), None);
+    assert!(cpu.registers()[0] > 6);
+}
+
+#[test]
+fn unknown_opcode_break_leaves_the_policy_alone() {
+    for policy in [UnknownOpcodePolicy::Halt, UnknownOpcodePolicy::Skip] {
+        // 200: v0 := 1, 202: not an instruction, 204: jump 204
+        let mut cpu = common::load("chip8", &[0x6001, 0xE0FF, 0x1204]);
+        cpu.set_unknown_opcode_policy(policy);
+        let mut debugger = Debugger::new();
+        debugger.set_break_on_unknown_opcode(true);
+
+        assert_eq!(
+            frame(&mut debugger, &mut cpu),
+            Some(Stop::UnknownOpcode {
+                pc: 0x202,
+                opcode: 0xE0FF,
+            })
+        );
+        assert_eq!(cpu.unknown_opcode_policy(), policy);
+    }
+
+    // Without the break the machine's own policy decides
+    let mut cpu = common::load("chip8", &[0x6001, 0xE0FF, 0x1204]);
+    cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Skip);
+    let mut debugger = Debugger::new();
+    assert_eq!(frame(&mut debugger, &mut cpu), None);
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn machine_goes_on_after_skipping_an_unknown_opcode() {
+    // 200: not an instruction, 202: v0 := 7, 204: jump 204
+    let mut cpu = common::load("chip8", &[0xE0FF, 0x6007, 0x1204]);
+    let mut debugger = Debugger::new();
+    debugger.set_break_on_unknown_opcode(true);
+    let trapped = Some(Stop::UnknownOpcode {
+        pc: 0x200,
+        opcode: 0xE0FF,
+    });
+    assert_eq!(frame(&mut debugger, &mut cpu), trapped);
+
+    // Resuming alone just lands on it again
+    debugger.resume();
+    assert_eq!(frame(&mut debugger, &mut cpu), trapped);
+    assert!(!debugger.skip_unknown_opcode(&mut Chip8::new()));
+
+    assert!(debugger.skip_unknown_opcode(&mut cpu));
+    assert!(cpu.trap().is_none());
+    assert_eq!(cpu.pc(), 0x202);
+    debugger.resume();
+    assert_eq!(frame(&mut debugger, &mut cpu), None);
+    assert_eq!(cpu.registers()[0], 7);
+    assert_eq!(cpu.pc(), 0x204);
+}
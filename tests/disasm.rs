@@ -0,0 +1,112 @@
+// Disassembler listings, and Octo output going back through the assembler byte for byte.
+
+use chip_8::Mode;
+use chip_8::asm;
+use chip_8::disasm::{self, Options, Syntax};
+use std::fs;
+use std::path::Path;
+
+// 200: i := 208, 202: call 20A, 204: jump 206, 206: 0000 (not code), 208: a two line sprite,
+// 20A: v0 := 1, 20C: return, 20E: trailing bytes nothing reaches
+const ROM: [u8; 16] = [
+    0xA2, 0x08, 0x22, 0x0A, 0x12, 0x06, 0x00, 0x00, 0x3C, 0x42, 0x60, 0x01, 0x00, 0xEE, 0x12, 0x34,
+];
+
+// The listing with runs of whitespace squashed, so the column widths don't matter.
+fn listing(rom: &[u8], syntax: Syntax) -> Vec<String> {
+    let options = Options {
+        syntax,
+        mode: Mode::Chip8,
+        ..Options::default()
+    };
+    disasm::disassemble(rom, &options)
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect()
+}
+
+#[test]
+fn octo_listing() {
+    assert_eq!(
+        listing(&ROM, Syntax::Octo),
+        [
+            ": main",
+            "i := data_0208 # 0200: A208",
+            "sub_020A # 0202: 220A",
+            "jump label_0206 # 0204: 1206",
+            ": label_0206",
+            "0x00 0x00 # 0206",
+            ": data_0208",
+            "0x3C # 0208 ..####..",
+            "0x42 # 0209 .#....#.",
+            ": sub_020A",
+            "v0 := 0x01 # 020A: 6001",
+            "return # 020C: 00EE",
+            "0x12 0x34 # 020E",
+        ]
+    );
+}
+
+#[test]
+fn classic_listing() {
+    assert_eq!(
+        listing(&ROM, Syntax::Classic),
+        [
+            "LD I, data_0208 ; 0200: A208",
+            "CALL sub_020A ; 0202: 220A",
+            "JP label_0206 ; 0204: 1206",
+            "label_0206:",
+            "DB #00, #00 ; 0206",
+            "data_0208:",
+            "DB #3C ; 0208 ..####..",
+            "DB #42 ; 0209 .#....#.",
+            "sub_020A:",
+            "LD V0, 01 ; 020A: 6001",
+            "RET ; 020C: 00EE",
+            "DB #12, #34 ; 020E",
+        ]
+    );
+}
+
+#[test]
+fn data_is_whatever_the_code_never_reaches() {
+    // 200: skip if v0 == 0, 202: jump 206, 204: jump 204, 206: jump 206, then bytes that only look
+    // like code
+    let rom = [
+        0x30, 0x00, 0x12, 0x06, 0x12, 0x04, 0x12, 0x06, 0x60, 0x01, 0x70, 0x01,
+    ];
+    let lines = listing(&rom, Syntax::Octo);
+    // Both sides of the skip are followed
+    assert!(lines.contains(&"jump label_0206 # 0202: 1206".to_string()));
+    assert!(lines.contains(&"jump label_0204 # 0204: 1204".to_string()));
+    assert_eq!(lines.last().unwrap(), "0x60 0x01 0x70 0x01 # 0208");
+}
+
+#[test]
+fn octo_is_the_default_syntax() {
+    assert_eq!(Options::default().syntax, Syntax::default());
+    assert_eq!(Syntax::default(), Syntax::Octo);
+}
+
+// Every test ROM, disassembled as Octo and assembled again, comes out the same.
+#[test]
+fn octo_round_trip() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms");
+    let mut checked = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|extension| extension != "8o") {
+            continue;
+        }
+        let rom = asm::assemble_file(&path).unwrap().rom;
+        let source = disasm::disassemble(&rom, &Options::default());
+        let again = asm::assemble(&source)
+            .unwrap_or_else(|e| panic!("{}: {}\n{}", path.display(), e, source))
+            .rom;
+        assert_eq!(again, rom, "{}\n{}", path.display(), source);
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
@@ -0,0 +1,71 @@
+// Framebuffer golden tests. Each ROM in tests/roms is assembled, run headless for a second under every
+// profile it supports, and the screen has to match tests/golden/ROM.PROFILE.txt exactly. After a
+// deliberate change, regenerate them with UPDATE_GOLDEN=1 cargo test --test golden and look over the
+// diff before committing.
+
+mod common;
+
+use chip_8::asm;
+use chip_8::headless::{self, InputScript};
+use std::fs;
+use std::path::Path;
+
+// A second of game time at the launcher's default speed
+const FRAMES: u64 = 60;
+const INSTRUCTIONS_PER_FRAME: u32 = 8;
+
+fn check(rom: &str, profiles: &[&str]) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let source = fs::read_to_string(dir.join("roms").join(format!("{}.8o", rom))).unwrap();
+    let program = asm::assemble(&source).unwrap_or_else(|e| panic!("{}.8o {}", rom, e));
+
+    for profile in profiles {
+        let mut cpu = common::machine(profile);
+        cpu.load_rom(&program.rom).unwrap();
+        headless::run(
+            &mut cpu,
+            FRAMES,
+            INSTRUCTIONS_PER_FRAME,
+            &InputScript::new(),
+        )
+        .unwrap_or_else(|e| panic!("{} on {}: {}", rom, profile, e));
+        let screen = headless::screen_text(cpu.framebuffer());
+
+        let golden = dir.join("golden").join(format!("{}.{}.txt", rom, profile));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&golden, &screen).unwrap();
+            continue;
+        }
+        let expected =
+            fs::read_to_string(&golden).unwrap_or_else(|e| panic!("{}: {}", golden.display(), e));
+        assert!(
+            screen == expected,
+            "{} on {} doesn't match {}\ngot:\n{}expected:\n{}",
+            rom,
+            profile,
+            golden.display(),
+            screen,
+            expected
+        );
+    }
+}
+
+#[test]
+fn digits() {
+    check("digits", &["chip8", "chip48", "schip", "xochip"]);
+}
+
+#[test]
+fn edges() {
+    check("edges", &["chip8", "chip48", "schip", "xochip"]);
+}
+
+#[test]
+fn hires() {
+    check("hires", &["schip", "xochip"]);
+}
+
+#[test]
+fn planes() {
+    check("planes", &["xochip"]);
+}
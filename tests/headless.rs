@@ -0,0 +1,165 @@
+// The headless runner's pieces: input scripts, the text dumps, screenshots, and dumps of a ROM that
+// crashes.
+
+mod common;
+
+use chip_8::cli::{self, RunArgs};
+use chip_8::headless::{self, InputScript, KeyEvent};
+use chip_8::{Chip8, Snapshot};
+use std::fs;
+use std::path::PathBuf;
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("chip8-headless-{}-{}", std::process::id(), name))
+}
+
+fn event(frame: u64, key: u8, pressed: bool) -> KeyEvent {
+    KeyEvent {
+        frame,
+        key,
+        pressed,
+    }
+}
+
+#[test]
+fn input_script_parses() {
+    let script = InputScript::parse(
+        "# a comment\n\
+         120 5 down\n\
+         \n\
+         130 5 up   # let go\n\
+         100 a 10\n",
+    )
+    .unwrap();
+    assert_eq!(
+        script.events(),
+        [
+            event(100, 0xA, true),
+            event(110, 0xA, false),
+            event(120, 5, true),
+            event(130, 5, false),
+        ]
+    );
+
+    let mut keys = [false; 16];
+    script.apply(100, &mut keys);
+    assert!(keys[0xA]);
+    script.apply(110, &mut keys);
+    assert!(!keys[0xA]);
+}
+
+#[test]
+fn input_script_errors_name_the_line() {
+    for bad in [
+        "1 5",
+        "x 5 down",
+        "1 10 down",
+        "1 5 sideways",
+        "18446744073709551615 5 3",
+    ] {
+        let error = InputScript::parse(&format!("0 1 down\n{}", bad)).unwrap_err();
+        assert!(error.contains("line 2"), "{:?}: {}", bad, error);
+    }
+}
+
+#[test]
+fn press_past_the_last_frame_is_refused() {
+    let mut script = InputScript::new();
+    assert!(!script.press(u64::MAX, 5, 1));
+    assert!(script.events().is_empty());
+    assert!(
+        RunArgs::parse(
+            ["rom.ch8", "--headless", "--press", "18446744073709551615:5"]
+                .iter()
+                .map(|arg| arg.to_string()),
+            8,
+        )
+        .is_err()
+    );
+}
+
+// V0 := 12, I := 2AB, call 20A, then the 0 glyph at 0,0 drawn from the subroutine
+fn drawn() -> Chip8 {
+    let mut cpu = common::load(
+        "chip48",
+        &[
+            0x600C, 0xA2AB, 0x220A, 0x1206, 0x0000, 0x6000, 0xF029, 0xD005,
+        ],
+    );
+    common::step(&mut cpu, 6).unwrap();
+    cpu
+}
+
+#[test]
+fn text_dumps() {
+    let cpu = drawn();
+    let registers = headless::registers_text(&cpu);
+    assert!(registers.starts_with("V0=00\nV1=00\n"), "{}", registers);
+    assert!(
+        registers.contains("I=0000\nPC=0210\nSP=1\n"),
+        "{}",
+        registers
+    );
+    assert!(registers.ends_with("stack=0206\n"), "{}", registers);
+
+    let screen = headless::screen_text(cpu.framebuffer());
+    let lines: Vec<&str> = screen.lines().collect();
+    assert_eq!(lines.len(), 32);
+    assert!(lines.iter().all(|line| line.len() == 66));
+    assert_eq!(&lines[0][..6], "|#### ");
+    assert_eq!(&lines[1][..6], "|#  # ");
+    assert_eq!(&lines[5][..6], "|     ");
+}
+
+#[test]
+fn screenshot_is_a_scaled_png() {
+    let cpu = drawn();
+    let path = temp("shot.png");
+    let palette = [0x000000, 0xFF8000, 0, 0];
+    headless::save_screenshot(cpu.framebuffer(), &path, 3, &palette).unwrap();
+    let decoder = png::Decoder::new(fs::File::open(&path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        (reader.info().width, reader.info().height),
+        (64 * 3, 32 * 3)
+    );
+    // Top left is lit, in plane 1's colour, and three pixels wide
+    assert_eq!(data[..3], [0xFF, 0x80, 0x00]);
+    assert_eq!(data[6..9], [0xFF, 0x80, 0x00]);
+    // The glyph's inside is off
+    let row = 64 * 3 * 3;
+    assert_eq!(data[(3 * row + 3) * 3..][..3], [0, 0, 0]);
+}
+
+#[test]
+fn crashing_rom_still_gets_dumped() {
+    // 2200 calls itself until the stack overflows
+    let rom = temp("crash.ch8");
+    let registers = temp("crash-regs.txt");
+    let state = temp("crash-state.json");
+    fs::write(&rom, [0x22, 0x00]).unwrap();
+    let args = [
+        rom.display().to_string(),
+        "--headless".to_string(),
+        "--dump-registers".to_string(),
+        registers.display().to_string(),
+        "--dump-state".to_string(),
+        state.display().to_string(),
+    ];
+    let run = RunArgs::parse(args.into_iter(), 8).unwrap();
+
+    let error = cli::run_headless(&run).unwrap_err();
+    let text = fs::read_to_string(&registers).unwrap();
+    let snapshot = Snapshot::load_from_file(&state).unwrap();
+    for path in [&rom, &registers, &state] {
+        fs::remove_file(path).unwrap();
+    }
+    // The machine is dumped as the failed call left it, PC already past the 2200
+    assert!(error.contains("opcode 2200 at PC=0200"), "{}", error);
+    assert!(text.contains("PC=0202\nSP=10\n"), "{}", text);
+    assert_eq!(snapshot.pc, 0x202);
+}
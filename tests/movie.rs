@@ -0,0 +1,139 @@
+// Movies have to replay exactly: same machine, same random numbers, same keys on the same frames, same
+// screen at the end.
+
+mod common;
+
+use chip_8::headless::{self, InputScript};
+use chip_8::{Movie, Random, RngKind, asm, cli};
+
+// Scatters random digits while key 5 is held, so the final screen depends on both the seed and the keys.
+const SCATTER: &str = "
+: main
+	v3 := 5
+	loop
+		v0 := random 63
+		v1 := random 31
+		v2 := random 15
+		i := hex v2
+		if v3 key then sprite v0 v1 5
+	again
+";
+
+fn record(profile: &str, kind: RngKind, input: &InputScript, frames: u64) -> (Movie, Vec<u8>) {
+    let rom = asm::assemble(SCATTER).unwrap().rom;
+    let mut cpu = common::machine(profile);
+    cpu.set_random(Random::new(kind, 99));
+    cpu.load_rom(&rom).unwrap();
+    let mut movie = Movie::new(&cpu, &rom, 8, false);
+    headless::run(&mut cpu, frames, 8, input).unwrap();
+
+    let mut keys = [false; 16];
+    for frame in 0..frames {
+        input.apply(frame, &mut keys);
+        movie.push(&keys);
+    }
+    movie.finish(cpu.framebuffer());
+    (movie, rom)
+}
+
+fn play(movie: &Movie, rom: &[u8]) -> Result<(), String> {
+    let mut cpu = movie.setup(rom)?;
+    headless::run(
+        &mut cpu,
+        movie.len(),
+        movie.instructions_per_frame,
+        &movie.input_script(),
+    )
+    .map_err(|e| e.to_string())?;
+    movie.verify(cpu.framebuffer())
+}
+
+#[test]
+fn playback_matches_the_recording() {
+    let mut input = InputScript::new();
+    input.press(10, 5, 20);
+    input.press(45, 5, 3);
+    for profile in common::profiles() {
+        for kind in [RngKind::SplitMix, RngKind::CosmacVip] {
+            let (movie, rom) = record(profile, kind, &input, 60);
+            play(&movie, &rom).unwrap_or_else(|e| panic!("{} {:?}: {}", profile, kind, e));
+        }
+    }
+}
+
+#[test]
+fn movie_survives_a_round_trip_through_a_file() {
+    let mut input = InputScript::new();
+    input.press(3, 5, 40);
+    let (movie, rom) = record("xochip", RngKind::SplitMix, &input, 50);
+    let path = std::env::temp_dir().join(format!("chip8-movie-{}.json", std::process::id()));
+    movie.save_to_file(&path).unwrap();
+    let loaded = Movie::load_from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, movie);
+    play(&loaded, &rom).unwrap();
+}
+
+#[test]
+fn changed_keys_desync() {
+    let mut input = InputScript::new();
+    input.press(10, 5, 20);
+    let (mut movie, rom) = record("chip8", RngKind::SplitMix, &input, 40);
+
+    // Same length, but the key is let go early
+    let mut keys = [false; 16];
+    let mut edited = movie.restart();
+    for frame in 0..movie.len() {
+        keys[5] = (10..15).contains(&frame);
+        edited.push(&keys);
+    }
+    edited.final_hash = movie.final_hash.clone();
+    assert!(play(&edited, &rom).is_err());
+
+    // And a different ROM is turned away before anything runs
+    movie.rom_hash = "0".repeat(16);
+    assert!(play(&movie, &rom).is_err());
+}
+
+#[test]
+fn crashed_frame_is_not_recorded() {
+    // 2200 calls itself until the stack runs out on the 17th call, which is in frame 2 at 8 per frame
+    let dir = std::env::temp_dir();
+    let rom = dir.join(format!("chip8-crash-{}.ch8", std::process::id()));
+    let path = dir.join(format!("chip8-crash-{}.json", std::process::id()));
+    std::fs::write(&rom, [0x22, 0x00]).unwrap();
+    let args = [
+        rom.to_str().unwrap(),
+        "--headless",
+        "--frames",
+        "10",
+        "--record",
+    ];
+    let args = args.iter().map(|arg| arg.to_string());
+    let args = cli::RunArgs::parse(args.chain([path.display().to_string()]), 8).unwrap();
+
+    let error = cli::run_headless(&args).unwrap_err();
+    let movie = Movie::load_from_file(&path).unwrap();
+    std::fs::remove_file(&rom).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(error.contains("in frame 2"), "{}", error);
+    assert_eq!(movie.len(), 2);
+}
+
+#[test]
+fn absurd_run_lengths_are_rejected() {
+    let (movie, _) = record("chip8", RngKind::SplitMix, &InputScript::new(), 5);
+    let mut json = serde_json::to_value(&movie).unwrap();
+    json["frames"] = serde_json::json!([[0, 4_000_000_000u32], [1, 4_000_000_000u32]]);
+    let error = serde_json::from_value::<Movie>(json).unwrap_err();
+    assert!(error.to_string().contains("more than"), "{}", error);
+}
+
+#[test]
+fn movie_is_tied_to_the_rom_sha1() {
+    let (movie, rom) = record("chip8", RngKind::SplitMix, &InputScript::new(), 5);
+    assert_eq!(movie.rom_hash, chip_8::romdb::rom_sha1(&rom));
+    let mut other = rom.clone();
+    other.push(0);
+    assert!(movie.setup(&other).is_err());
+}
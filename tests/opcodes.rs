@@ -0,0 +1,755 @@
+// One test per opcode group, each run under every quirk profile. Where a quirk changes the result the
+// expectation comes from the profile's Quirks, so a preset that changes stays covered.
+
+mod common;
+
+use chip_8::bus::MemoryOverflow;
+use chip_8::font::SMALL_FONT_ADDR;
+use chip_8::quirks::MemoryIncrement;
+use chip_8::{EmulatorError, Fault, Random, RngKind, UnknownOpcodePolicy};
+use common::{has_schip, has_xochip, load, profiles, run, step, step_with_keys};
+
+#[test]
+fn clear_screen_00e0() {
+    for profile in profiles() {
+        // Draw the 0 glyph, then clear
+        let cpu = run(profile, &[0xA000, 0xD005, 0x00E0]);
+        assert!(
+            cpu.framebuffer().pixels().iter().all(|p| *p == 0),
+            "{}",
+            profile
+        );
+        assert_eq!(cpu.pc(), 0x206, "{}", profile);
+    }
+}
+
+#[test]
+fn jump_1nnn() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x1ABC]);
+        assert_eq!(cpu.pc(), 0xABC, "{}", profile);
+    }
+}
+
+#[test]
+fn call_2nnn_and_return_00ee() {
+    for profile in profiles() {
+        // 200: call 206, 202: v0 := 1, 204: jump 204, 206: v1 := 2, 208: return
+        let mut cpu = load(profile, &[0x2206, 0x6001, 0x1204, 0x6102, 0x00EE]);
+        step(&mut cpu, 1).unwrap();
+        assert_eq!(cpu.pc(), 0x206, "{}", profile);
+        assert_eq!(cpu.sp(), 1, "{}", profile);
+        assert_eq!(cpu.stack()[0], 0x202, "{}", profile);
+        step(&mut cpu, 3).unwrap();
+        assert_eq!(cpu.sp(), 0, "{}", profile);
+        assert_eq!(cpu.registers()[0..2], [1, 2], "{}", profile);
+        assert_eq!(cpu.pc(), 0x204, "{}", profile);
+    }
+}
+
+#[test]
+fn stack_holds_sixteen_calls() {
+    for profile in profiles() {
+        // Every call lands on the next word, which calls again
+        let program: Vec<u16> = (0..17).map(|j| 0x2202 + j * 2).collect();
+        let mut cpu = load(profile, &program);
+        step(&mut cpu, 16).unwrap();
+        assert_eq!(cpu.sp(), 16, "{}", profile);
+        assert_eq!(cpu.stack()[15], 0x220, "{}", profile);
+
+        let error = step(&mut cpu, 1).unwrap_err();
+        assert_eq!(
+            error,
+            EmulatorError::Cpu {
+                pc: 0x220,
+                opcode: 0x2222,
+                fault: Fault::StackOverflow,
+            },
+            "{}",
+            profile
+        );
+    }
+}
+
+#[test]
+fn return_with_empty_stack_underflows() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x00EE]);
+        let error = step(&mut cpu, 1).unwrap_err();
+        assert_eq!(
+            error,
+            EmulatorError::Cpu {
+                pc: 0x200,
+                opcode: 0x00EE,
+                fault: Fault::StackUnderflow,
+            },
+            "{}",
+            profile
+        );
+    }
+}
+
+#[test]
+fn skip_3xnn_4xnn() {
+    for profile in profiles() {
+        // v0 := 5, skip if v0 == 5 (taken), skip if v0 == 6 (not taken)
+        let mut cpu = load(profile, &[0x6005, 0x3005, 0x0000, 0x3006]);
+        step(&mut cpu, 3).unwrap();
+        assert_eq!(cpu.pc(), 0x208, "{}", profile);
+        let mut cpu = load(profile, &[0x6005, 0x4006, 0x0000, 0x4005]);
+        step(&mut cpu, 3).unwrap();
+        assert_eq!(cpu.pc(), 0x208, "{}", profile);
+    }
+}
+
+#[test]
+fn skip_5xy0_9xy0() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x6007, 0x6107, 0x5010, 0x0000, 0x9010]);
+        step(&mut cpu, 4).unwrap();
+        assert_eq!(cpu.pc(), 0x20A, "{}", profile);
+        let mut cpu = load(profile, &[0x6007, 0x6108, 0x9010, 0x0000, 0x5010]);
+        step(&mut cpu, 4).unwrap();
+        assert_eq!(cpu.pc(), 0x20A, "{}", profile);
+    }
+}
+
+#[test]
+fn set_6xnn_and_add_7xnn_wraps_without_carry() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x6AFE, 0x7A03, 0x6F55, 0x7F01]);
+        assert_eq!(cpu.registers()[0xA], 0x01, "{}", profile);
+        // 7XNN never touches the flag, on VF it's just an add
+        assert_eq!(cpu.registers()[0xF], 0x56, "{}", profile);
+    }
+}
+
+#[test]
+fn copy_8xy0() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x6142, 0x8010]);
+        assert_eq!(cpu.registers()[0], 0x42, "{}", profile);
+    }
+}
+
+#[test]
+fn logic_8xy1_8xy2_8xy3_and_vf_reset() {
+    for (opcode, expected) in [(0x8011, 0b1110), (0x8012, 0b1000), (0x8013, 0b0110)] {
+        for profile in profiles() {
+            let mut cpu = load(profile, &[0x600C, 0x610A, 0x6F07, opcode]);
+            step(&mut cpu, 4).unwrap();
+            assert_eq!(cpu.registers()[0], expected, "{} {:04X}", profile, opcode);
+            let vf = if cpu.quirks().vf_reset { 0 } else { 7 };
+            assert_eq!(cpu.registers()[0xF], vf, "{} {:04X}", profile, opcode);
+        }
+    }
+}
+
+#[test]
+fn add_8xy4_carry() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x60F0, 0x6120, 0x8014]);
+        assert_eq!(cpu.registers()[0], 0x10, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+
+        let cpu = run(profile, &[0x6010, 0x6120, 0x8014]);
+        assert_eq!(cpu.registers()[0], 0x30, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+    }
+}
+
+#[test]
+fn add_8xy4_into_vf_keeps_the_carry() {
+    for profile in profiles() {
+        // VF += V1 with a carry: the flag is written last and wins over the sum
+        let cpu = run(profile, &[0x6FF0, 0x6120, 0x8F14]);
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+        let cpu = run(profile, &[0x6F10, 0x6120, 0x8F14]);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+    }
+}
+
+#[test]
+fn subtract_8xy5_borrow() {
+    for profile in profiles() {
+        // No borrow, VF = 1
+        let cpu = run(profile, &[0x6030, 0x6110, 0x8015]);
+        assert_eq!(cpu.registers()[0], 0x20, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+        // Equal is no borrow either
+        let cpu = run(profile, &[0x6030, 0x6130, 0x8015]);
+        assert_eq!(cpu.registers()[0], 0x00, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+        // Borrow, VF = 0
+        let cpu = run(profile, &[0x6010, 0x6130, 0x8015]);
+        assert_eq!(cpu.registers()[0], 0xE0, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+        // Into VF: the flag wins
+        let cpu = run(profile, &[0x6F10, 0x6130, 0x8F15]);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+    }
+}
+
+#[test]
+fn subtract_reversed_8xy7_borrow() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x6010, 0x6130, 0x8017]);
+        assert_eq!(cpu.registers()[0], 0x20, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+        let cpu = run(profile, &[0x6030, 0x6110, 0x8017]);
+        assert_eq!(cpu.registers()[0], 0xE0, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+        let cpu = run(profile, &[0x6F30, 0x6110, 0x8F17]);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+    }
+}
+
+#[test]
+fn shifts_8xy6_8xye() {
+    for profile in profiles() {
+        // V0 = 0b1000_0001, V1 = 0b0100_0010
+        let cpu = run(profile, &[0x6081, 0x6142, 0x8016]);
+        let (result, flag) = if cpu.quirks().shift {
+            (0x40, 1)
+        } else {
+            (0x21, 0)
+        };
+        assert_eq!(cpu.registers()[0], result, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], flag, "{}", profile);
+
+        let cpu = run(profile, &[0x6081, 0x6142, 0x801E]);
+        let (result, flag) = if cpu.quirks().shift {
+            (0x02, 1)
+        } else {
+            (0x84, 0)
+        };
+        assert_eq!(cpu.registers()[0], result, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], flag, "{}", profile);
+
+        // Shifting VF itself leaves the shifted out bit
+        let cpu = run(profile, &[0x6F03, 0x8FF6]);
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+    }
+}
+
+#[test]
+fn set_index_annn() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0xA123]);
+        assert_eq!(cpu.index(), 0x123, "{}", profile);
+    }
+}
+
+#[test]
+fn jump_with_offset_bnnn() {
+    for profile in profiles() {
+        // V0 = 4, V3 = 8, jump to 0x300 + V0 (or + V3 with the jump quirk)
+        let cpu = run(profile, &[0x6004, 0x6308, 0xB300]);
+        let expected = if cpu.quirks().jump { 0x308 } else { 0x304 };
+        assert_eq!(cpu.pc(), expected, "{}", profile);
+    }
+}
+
+#[test]
+fn random_cxnn_is_masked() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0xC000, 0xC10F]);
+        step(&mut cpu, 2).unwrap();
+        assert_eq!(cpu.registers()[0], 0, "{}", profile);
+        assert!(cpu.registers()[1] <= 0x0F, "{}", profile);
+    }
+}
+
+#[test]
+fn random_cxnn_repeats_with_the_same_seed() {
+    for profile in profiles() {
+        for kind in [RngKind::SplitMix, RngKind::CosmacVip] {
+            // 200: v0 := random, 202: v1 := random, 204: v2 := random, 206: v3 := random
+            let program = [0xC0FF, 0xC1FF, 0xC2FF, 0xC3FF];
+            let mut first = load(profile, &program);
+            first.set_random(Random::new(kind, 1234));
+            step(&mut first, 2).unwrap();
+
+            // Restoring a save state also restores the generator
+            let saved = first.snapshot();
+            step(&mut first, 2).unwrap();
+            let mut second = load(profile, &program);
+            second.restore(&saved).unwrap();
+            step(&mut second, 2).unwrap();
+            assert_eq!(
+                first.registers(),
+                second.registers(),
+                "{} {:?}",
+                profile,
+                kind
+            );
+
+            let mut third = load(profile, &program);
+            third.set_random(Random::new(kind, 1234));
+            step(&mut third, 4).unwrap();
+            assert_eq!(
+                first.registers(),
+                third.registers(),
+                "{} {:?}",
+                profile,
+                kind
+            );
+        }
+    }
+}
+
+#[test]
+fn random_vip_repeats_after_64k_calls() {
+    let mut random = Random::new(RngKind::CosmacVip, 0xBEEF);
+    let start: Vec<u8> = (0..16).map(|_| random.next_byte()).collect();
+    for _ in 16..0x10000 {
+        random.next_byte();
+    }
+    let again: Vec<u8> = (0..16).map(|_| random.next_byte()).collect();
+    assert_eq!(start, again);
+}
+
+#[test]
+fn draw_dxyn_and_collision() {
+    for profile in profiles() {
+        // Font glyph 0 (F0 90 90 90 F0) at (2, 3)
+        let mut cpu = load(profile, &[0xA000, 0x6002, 0x6103, 0xD015, 0xD015]);
+        step(&mut cpu, 4).unwrap();
+        let fb = cpu.framebuffer();
+        assert_eq!(fb.pixel(2, 3), 1, "{}", profile);
+        assert_eq!(fb.pixel(5, 3), 1, "{}", profile);
+        assert_eq!(fb.pixel(3, 4), 0, "{}", profile);
+        assert_eq!(fb.pixel(2, 7), 1, "{}", profile);
+        assert_eq!(cpu.registers()[0xF], 0, "{}", profile);
+
+        // Drawing it again switches it all off and reports the collision
+        cpu.tick_timers();
+        step(&mut cpu, 1).unwrap();
+        assert!(
+            cpu.framebuffer().pixels().iter().all(|p| *p == 0),
+            "{}",
+            profile
+        );
+        assert_eq!(cpu.registers()[0xF], 1, "{}", profile);
+    }
+}
+
+#[test]
+fn draw_dxyn_wraps_the_start_position() {
+    for profile in profiles() {
+        // (66, 35) is (2, 3) on a 64x32 screen
+        let cpu = run(profile, &[0xA000, 0x6042, 0x6123, 0xD015]);
+        assert_eq!(cpu.framebuffer().pixel(2, 3), 1, "{}", profile);
+    }
+}
+
+#[test]
+fn draw_dxyn_clips_or_wraps_at_the_edge() {
+    for profile in profiles() {
+        // Glyph 0 at (62, 30): the right half and the bottom rows fall off the screen
+        let cpu = run(profile, &[0xA000, 0x603E, 0x611E, 0xD015]);
+        let fb = cpu.framebuffer();
+        assert_eq!(fb.pixel(62, 30), 1, "{}", profile);
+        assert_eq!(fb.pixel(63, 30), 1, "{}", profile);
+        let wrapped = if cpu.quirks().clipping { 0 } else { 1 };
+        assert_eq!(fb.pixel(0, 30), wrapped, "{}", profile);
+        assert_eq!(fb.pixel(62, 0), wrapped, "{}", profile);
+    }
+}
+
+#[test]
+fn draw_dxyn_display_wait() {
+    for profile in profiles() {
+        // Two draws back to back without a frame in between
+        let mut cpu = load(profile, &[0xA000, 0xD005, 0xD005]);
+        step(&mut cpu, 3).unwrap();
+        if cpu.quirks().display_wait {
+            // The second draw waits for the vertical blank
+            assert_eq!(cpu.pc(), 0x204, "{}", profile);
+            assert_eq!(cpu.framebuffer().pixel(0, 0), 1, "{}", profile);
+            cpu.tick_timers();
+            step(&mut cpu, 1).unwrap();
+        }
+        assert_eq!(cpu.pc(), 0x206, "{}", profile);
+        assert_eq!(cpu.framebuffer().pixel(0, 0), 0, "{}", profile);
+    }
+}
+
+#[test]
+fn key_skips_ex9e_exa1() {
+    for profile in profiles() {
+        let mut keys = [false; 16];
+        keys[0xA] = true;
+        // V0 = A: skip if pressed (taken), skip if not pressed (not taken)
+        let mut cpu = load(profile, &[0x600A, 0xE09E, 0x0000, 0xE0A1]);
+        step_with_keys(&mut cpu, 3, &keys).unwrap();
+        assert_eq!(cpu.pc(), 0x208, "{}", profile);
+
+        let mut cpu = load(profile, &[0x600B, 0xE0A1, 0x0000, 0xE09E]);
+        step_with_keys(&mut cpu, 3, &keys).unwrap();
+        assert_eq!(cpu.pc(), 0x208, "{}", profile);
+    }
+}
+
+#[test]
+fn timers_fx07_fx15_fx18() {
+    for profile in profiles() {
+        let mut cpu = run(profile, &[0x6005, 0xF015, 0x6103, 0xF118]);
+        assert_eq!(cpu.delay_timer(), 5, "{}", profile);
+        assert_eq!(cpu.sound_timer(), 3, "{}", profile);
+        for _ in 0..4 {
+            cpu.tick_timers();
+        }
+        assert_eq!(cpu.delay_timer(), 1, "{}", profile);
+        assert_eq!(cpu.sound_timer(), 0, "{}", profile);
+
+        let cpu = run(profile, &[0x6009, 0xF015, 0xF207]);
+        assert_eq!(cpu.registers()[2], 9, "{}", profile);
+    }
+}
+
+#[test]
+fn wait_for_key_fx0a() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0xF30A, 0x6001]);
+        let mut keys = [false; 16];
+        step_with_keys(&mut cpu, 3, &keys).unwrap();
+        assert!(cpu.is_waiting_for_key(), "{}", profile);
+        assert_eq!(cpu.pc(), 0x202, "{}", profile);
+
+        keys[7] = true;
+        step_with_keys(&mut cpu, 1, &keys).unwrap();
+        if cpu.quirks().key_wait_on_press {
+            assert!(!cpu.is_waiting_for_key(), "{}", profile);
+        } else {
+            // The VIP waits for the key to come back up
+            assert!(cpu.is_waiting_for_key(), "{}", profile);
+            keys[7] = false;
+            step_with_keys(&mut cpu, 1, &keys).unwrap();
+            assert!(!cpu.is_waiting_for_key(), "{}", profile);
+        }
+        assert_eq!(cpu.registers()[3], 7, "{}", profile);
+        step_with_keys(&mut cpu, 1, &keys).unwrap();
+        assert_eq!(cpu.registers()[0], 1, "{}", profile);
+    }
+}
+
+#[test]
+fn add_to_index_fx1e() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0xA100, 0x6020, 0xF01E]);
+        assert_eq!(cpu.index(), 0x120, "{}", profile);
+    }
+}
+
+#[test]
+fn font_fx29() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x600A, 0xF029]);
+        assert_eq!(cpu.index(), SMALL_FONT_ADDR + 0xA * 5, "{}", profile);
+        // Only the low nibble counts
+        let cpu = run(profile, &[0x6013, 0xF029]);
+        assert_eq!(cpu.index(), SMALL_FONT_ADDR + 3 * 5, "{}", profile);
+        let glyph = &cpu.memory()[cpu.index() as usize..][..5];
+        assert_eq!(glyph, [0xF0, 0x10, 0xF0, 0x10, 0xF0], "{}", profile);
+    }
+}
+
+#[test]
+fn bcd_fx33() {
+    for profile in profiles() {
+        for (value, digits) in [
+            (0u8, [0, 0, 0]),
+            (255, [2, 5, 5]),
+            (137, [1, 3, 7]),
+            (9, [0, 0, 9]),
+        ] {
+            let cpu = run(profile, &[0xA300, 0x6000 | value as u16, 0xF033]);
+            assert_eq!(cpu.memory()[0x300..0x303], digits, "{} {}", profile, value);
+            assert_eq!(cpu.index(), 0x300, "{} {}", profile, value);
+        }
+    }
+}
+
+#[test]
+fn store_fx55_and_load_fx65() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x6011, 0x6122, 0x6233, 0xA300, 0xF255]);
+        assert_eq!(
+            cpu.memory()[0x300..0x304],
+            [0x11, 0x22, 0x33, 0x00],
+            "{}",
+            profile
+        );
+        let expected = match cpu.quirks().memory_increment {
+            MemoryIncrement::None => 0x300,
+            MemoryIncrement::X => 0x302,
+            MemoryIncrement::XPlusOne => 0x303,
+        };
+        assert_eq!(cpu.index(), expected, "{}", profile);
+
+        // Load V0-V1 back from 0x301
+        let cpu = run(profile, &[0x6022, 0x6133, 0xA300, 0xF155, 0xA301, 0xF165]);
+        assert_eq!(cpu.registers()[0..2], [0x33, 0x00], "{}", profile);
+    }
+}
+
+#[test]
+fn machine_code_0nnn_is_ignored() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x0123, 0x6001]);
+        assert_eq!(cpu.registers()[0], 1, "{}", profile);
+    }
+}
+
+#[test]
+fn unknown_opcode_policies() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x5001, 0x6001]);
+        assert_eq!(
+            step(&mut cpu, 1).unwrap_err(),
+            EmulatorError::Cpu {
+                pc: 0x200,
+                opcode: 0x5001,
+                fault: Fault::UnknownOpcode,
+            },
+            "{}",
+            profile
+        );
+
+        let mut cpu = load(profile, &[0x5001, 0x6001]);
+        cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Skip);
+        step(&mut cpu, 2).unwrap();
+        assert_eq!(cpu.registers()[0], 1, "{}", profile);
+
+        let mut cpu = load(profile, &[0x5001, 0x6001]);
+        cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Trap);
+        step(&mut cpu, 2).unwrap();
+        assert!(cpu.trap().is_some(), "{}", profile);
+        assert_eq!(cpu.pc(), 0x200, "{}", profile);
+        assert_eq!(cpu.registers()[0], 0, "{}", profile);
+    }
+}
+
+#[test]
+fn schip_resolution_00fe_00ff() {
+    for profile in profiles() {
+        let cpu = run(profile, &[0x00FF]);
+        // On plain CHIP-8 00FF is a machine code call and does nothing
+        assert_eq!(
+            cpu.framebuffer().is_hires(),
+            has_schip(profile),
+            "{}",
+            profile
+        );
+        let cpu = run(profile, &[0x00FF, 0x00FE]);
+        assert!(!cpu.framebuffer().is_hires(), "{}", profile);
+    }
+}
+
+#[test]
+fn schip_scrolling_00cn_00fb_00fc() {
+    for profile in profiles().filter(|p| has_schip(p)) {
+        // The top row of the 0 glyph at (8, 8), scrolled down 2 and right 4, or left 4
+        let base = [0xA000, 0x6008, 0xD001];
+        let mut program = base.to_vec();
+        program.extend([0x00C2, 0x00FB]);
+        let cpu = run(profile, &program);
+        assert_eq!(cpu.framebuffer().pixel(12, 10), 1, "{}", profile);
+        assert_eq!(cpu.framebuffer().pixel(8, 8), 0, "{}", profile);
+
+        let mut program = base.to_vec();
+        program.push(0x00FC);
+        let cpu = run(profile, &program);
+        assert_eq!(cpu.framebuffer().pixel(4, 8), 1, "{}", profile);
+    }
+}
+
+#[test]
+fn schip_exit_00fd() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x00FD, 0x6001]);
+        step(&mut cpu, 2).unwrap();
+        assert_eq!(cpu.is_halted(), has_schip(profile), "{}", profile);
+        let expected = if has_schip(profile) { 0 } else { 1 };
+        assert_eq!(cpu.registers()[0], expected, "{}", profile);
+    }
+}
+
+#[test]
+fn schip_big_sprite_dxy0() {
+    for profile in profiles().filter(|p| has_schip(p)) {
+        // 32 bytes of 0xFF at 0x300 make a solid 16x16 block
+        let mut cpu = load(profile, &[0x00FF, 0xA300, 0xD000]);
+        for j in 0..32 {
+            cpu.poke(0x300 + j, 0xFF).unwrap();
+        }
+        step(&mut cpu, 3).unwrap();
+        let fb = cpu.framebuffer();
+        assert_eq!(fb.pixel(15, 15), 1, "{}", profile);
+        assert_eq!(fb.pixel(16, 0), 0, "{}", profile);
+        assert_eq!(fb.pixel(0, 16), 0, "{}", profile);
+    }
+}
+
+#[test]
+fn schip_big_font_fx30() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x6005, 0xF030]);
+        let result = step(&mut cpu, 2);
+        if has_schip(profile) {
+            result.unwrap();
+            // Ten bytes per digit, and they aren't the small font
+            let big = cpu.index() as usize;
+            assert_ne!(cpu.memory()[big..big + 10], [0; 10], "{}", profile);
+        } else {
+            assert!(result.is_err(), "{}", profile);
+        }
+    }
+}
+
+#[test]
+fn schip_rpl_flags_fx75_fx85() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x6011, 0x6122, 0xF175, 0x6000, 0x6100, 0xF185]);
+        let result = step(&mut cpu, 6);
+        if has_schip(profile) {
+            result.unwrap();
+            assert_eq!(cpu.rpl_flags()[0..2], [0x11, 0x22], "{}", profile);
+            assert_eq!(cpu.registers()[0..2], [0x11, 0x22], "{}", profile);
+        } else {
+            assert!(result.is_err(), "{}", profile);
+        }
+    }
+}
+
+#[test]
+fn xochip_register_ranges_5xy2_5xy3() {
+    for profile in profiles() {
+        let mut cpu = load(profile, &[0x6111, 0x6222, 0x6333, 0xA300, 0x5132, 0x5313]);
+        let result = step(&mut cpu, 5);
+        if !has_xochip(profile) {
+            assert!(result.is_err(), "{}", profile);
+            continue;
+        }
+        result.unwrap();
+        assert_eq!(
+            cpu.memory()[0x300..0x303],
+            [0x11, 0x22, 0x33],
+            "{}",
+            profile
+        );
+        // I doesn't move
+        assert_eq!(cpu.index(), 0x300, "{}", profile);
+        // Backwards: V3 gets the first byte
+        step(&mut cpu, 1).unwrap();
+        assert_eq!(cpu.registers()[1..4], [0x33, 0x22, 0x11], "{}", profile);
+    }
+}
+
+#[test]
+fn xochip_long_index_f000_and_skipping_it() {
+    for profile in profiles().filter(|p| has_xochip(p)) {
+        let mut cpu = load(profile, &[0xF000, 0xBEEF]);
+        step(&mut cpu, 1).unwrap();
+        assert_eq!(cpu.index(), 0xBEEF, "{}", profile);
+        assert_eq!(cpu.pc(), 0x204, "{}", profile);
+
+        // A skip jumps over all four bytes
+        let mut cpu = load(profile, &[0x3000, 0xF000, 0x1234, 0x6001]);
+        step(&mut cpu, 2).unwrap();
+        assert_eq!(cpu.index(), 0, "{}", profile);
+        assert_eq!(cpu.registers()[0], 1, "{}", profile);
+    }
+}
+
+#[test]
+fn xochip_planes_fn01_and_scroll_up_00dn() {
+    for profile in profiles().filter(|p| has_xochip(p)) {
+        // Plane 2 only, draw a dot at (0, 4), scroll up 4
+        let cpu = run(profile, &[0xF201, 0xA000, 0x6104, 0xD011]);
+        assert_eq!(cpu.framebuffer().pixel(0, 4), 2, "{}", profile);
+        let cpu = run(profile, &[0xF201, 0xA000, 0x6104, 0xD011, 0x00D4]);
+        assert_eq!(cpu.framebuffer().pixel(0, 0), 2, "{}", profile);
+        assert_eq!(cpu.framebuffer().pixel(0, 4), 0, "{}", profile);
+    }
+}
+
+#[test]
+fn xochip_audio_f002_fx3a() {
+    for profile in profiles().filter(|p| has_xochip(p)) {
+        let mut cpu = load(profile, &[0xA300, 0xF002, 0x6080, 0xF03A]);
+        for j in 0..16 {
+            cpu.poke(0x300 + j, j as u8).unwrap();
+        }
+        step(&mut cpu, 4).unwrap();
+        let expected: Vec<u8> = (0..16).collect();
+        assert_eq!(cpu.audio_pattern()[..], expected[..], "{}", profile);
+        assert_eq!(cpu.pitch(), 0x80, "{}", profile);
+    }
+}
+
+// With I on the last byte of a 64K memory, every opcode that goes through I runs off the end.
+#[test]
+fn xochip_memory_past_the_end_faults() {
+    for profile in profiles().filter(|p| has_xochip(p)) {
+        for opcode in [0xF033, 0xF155, 0xF165, 0xD012, 0x5012, 0x5013, 0xF002] {
+            let mut cpu = load(profile, &[opcode]);
+            cpu.set_memory_overflow(MemoryOverflow::Fault);
+            cpu.set_index(0xFFFF);
+            assert_eq!(
+                step(&mut cpu, 1).unwrap_err(),
+                EmulatorError::Cpu {
+                    pc: 0x200,
+                    opcode,
+                    fault: Fault::MemoryOutOfBounds { address: 0x10000 },
+                },
+                "{} {:04X}",
+                profile,
+                opcode
+            );
+        }
+    }
+}
+
+#[test]
+fn xochip_pc_past_the_end_faults() {
+    for profile in profiles().filter(|p| has_xochip(p)) {
+        // A plain instruction in the last word, a skip over it, and F000 NNNN ending there
+        for (pc, code) in [
+            (0xFFFE, vec![0x6001]),
+            (0xFFFC, vec![0x3000, 0x6001]),
+            (0xFFFC, vec![0xF000, 0x1234]),
+        ] {
+            let mut cpu = load(profile, &[]);
+            cpu.set_memory_overflow(MemoryOverflow::Fault);
+            for (j, byte) in code
+                .iter()
+                .flat_map(|op: &u16| op.to_be_bytes())
+                .enumerate()
+            {
+                cpu.poke(pc + j, byte).unwrap();
+            }
+            cpu.set_pc(pc as u16);
+            assert_eq!(
+                step(&mut cpu, 1).unwrap_err(),
+                EmulatorError::Cpu {
+                    pc: pc as u16,
+                    opcode: code[0],
+                    fault: Fault::MemoryOutOfBounds { address: 0x10000 },
+                },
+                "{} {:04X?}",
+                profile,
+                code
+            );
+        }
+
+        // Wrapping is still the default
+        let mut cpu = load(profile, &[]);
+        cpu.poke(0xFFFE, 0x60).unwrap();
+        cpu.poke(0xFFFF, 0x01).unwrap();
+        cpu.set_pc(0xFFFE);
+        step(&mut cpu, 1).unwrap();
+        assert_eq!(cpu.pc(), 0, "{}", profile);
+    }
+}
@@ -0,0 +1,40 @@
+// Phosphor persistence: lit pixels show at once, dark ones fade out instead of vanishing.
+
+mod common;
+
+use chip_8::Phosphor;
+
+const PALETTE: [u32; 4] = [0x000000, 0xc8c8c8, 0x640000, 0xffffff];
+
+// The first pixel's RGB after blending in the current screen.
+fn first_pixel(phosphor: &mut Phosphor, cpu: &chip_8::Chip8) -> [u8; 3] {
+    let mut rgb = vec![];
+    phosphor.blend(cpu.framebuffer(), &PALETTE, &mut rgb);
+    assert_eq!(rgb.len(), cpu.framebuffer().pixels().len() * 3);
+    [rgb[0], rgb[1], rgb[2]]
+}
+
+#[test]
+fn dark_pixels_fade_and_lit_ones_do_not() {
+    // Draws the top row of the 0 glyph at 0,0, then XORs it away again. CHIP-48 has no display wait, so
+    // both draws happen right away
+    let mut cpu = common::load("chip48", &[0x6000, 0xF029, 0xD001, 0xD001]);
+    let mut phosphor = Phosphor::new(0.5);
+
+    common::step(&mut cpu, 3).unwrap();
+    assert_eq!(first_pixel(&mut phosphor, &cpu), [200, 200, 200]);
+
+    common::step(&mut cpu, 1).unwrap();
+    assert_eq!(first_pixel(&mut phosphor, &cpu), [100, 100, 100]);
+    assert_eq!(first_pixel(&mut phosphor, &cpu), [50, 50, 50]);
+}
+
+#[test]
+fn no_decay_is_the_plain_screen() {
+    let mut cpu = common::load("chip48", &[0x6000, 0xF029, 0xD001, 0xD001]);
+    let mut phosphor = Phosphor::new(0.0);
+    common::step(&mut cpu, 3).unwrap();
+    assert_eq!(first_pixel(&mut phosphor, &cpu), [200, 200, 200]);
+    common::step(&mut cpu, 1).unwrap();
+    assert_eq!(first_pixel(&mut phosphor, &cpu), [0, 0, 0]);
+}
@@ -0,0 +1,87 @@
+// Rewind keeps most frames as patches against the frame after them. Stepping back has to rebuild every
+// one of them exactly, memory and screen, even across a switch between lo-res and hi-res.
+
+mod common;
+
+use chip_8::rewind::{DEFAULT_BUDGET, DEFAULT_SECONDS};
+use chip_8::{Chip8, Rewind, Snapshot, asm};
+
+// Writes to memory and draws on every pass, and flips to hi-res and back on the way.
+const SCRIBBLE: &str = "
+: main
+	loop
+		i := buffer
+		v0 += 1
+		save v0
+		sprite v0 v0 1
+		if v0 == 4 then hires
+		if v0 == 9 then lores
+	again
+: buffer
+	0
+";
+
+// Runs `frames` frames into `rewind` and hands back the snapshot taken after each one.
+fn record(rewind: &mut Rewind, frames: usize) -> (Chip8, Vec<Snapshot>) {
+    let rom = asm::assemble(SCRIBBLE).unwrap().rom;
+    let mut cpu = common::machine("schip");
+    cpu.load_rom(&rom).unwrap();
+    let mut snapshots = vec![];
+    for _ in 0..frames {
+        cpu.tick_timers();
+        common::step(&mut cpu, 7).unwrap();
+        rewind.record(&cpu);
+        snapshots.push(cpu.snapshot());
+    }
+    (cpu, snapshots)
+}
+
+#[test]
+fn stepping_back_rebuilds_every_frame() {
+    let mut rewind = Rewind::new(DEFAULT_SECONDS, 1, DEFAULT_BUDGET);
+    let (mut cpu, snapshots) = record(&mut rewind, 16);
+    assert!(snapshots.iter().any(|s| s.framebuffer.is_hires()));
+    assert!(snapshots.iter().any(|s| !s.framebuffer.is_hires()));
+    assert_eq!(rewind.len(), snapshots.len() - 1);
+
+    for expected in snapshots.iter().rev().skip(1) {
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(&cpu.snapshot(), expected);
+    }
+    assert!(rewind.is_empty());
+}
+
+#[test]
+fn oldest_frame_still_counts_as_a_step() {
+    let mut rewind = Rewind::new(DEFAULT_SECONDS, 1, DEFAULT_BUDGET);
+    let (mut cpu, snapshots) = record(&mut rewind, 2);
+    assert!(rewind.step_back(&mut cpu).unwrap());
+    assert_eq!(cpu.snapshot(), snapshots[0]);
+    // Nothing older, it stays put
+    assert!(rewind.step_back(&mut cpu).unwrap());
+    assert_eq!(cpu.snapshot(), snapshots[0]);
+
+    rewind.clear();
+    assert!(!rewind.step_back(&mut cpu).unwrap());
+}
+
+#[test]
+fn byte_budget_drops_the_oldest_frames() {
+    let mut unlimited = Rewind::new(DEFAULT_SECONDS, 1, DEFAULT_BUDGET);
+    record(&mut unlimited, 16);
+    let budget = unlimited.bytes_used() / unlimited.len() * 4;
+
+    let mut rewind = Rewind::new(DEFAULT_SECONDS, 1, budget);
+    let (mut cpu, snapshots) = record(&mut rewind, 16);
+    assert!(rewind.bytes_used() <= budget);
+    assert!(!rewind.is_empty() && rewind.len() < snapshots.len() - 1);
+
+    // What's left still goes back correctly, as far as it reaches
+    let kept = rewind.len();
+    for expected in snapshots.iter().rev().skip(1).take(kept) {
+        rewind.step_back(&mut cpu).unwrap();
+        assert_eq!(&cpu.snapshot(), expected);
+    }
+    assert!(rewind.is_empty());
+    assert_eq!(rewind.bytes_used(), 0);
+}
@@ -0,0 +1,155 @@
+// ROM profiles: hashing, and turning chip-8-database entries into the quirks and settings we use.
+
+use chip_8::display::{self, PALETTE};
+use chip_8::quirks::MemoryIncrement;
+use chip_8::romdb::{parse_colour, rom_sha1};
+use chip_8::{Mode, Quirks, RomDatabase, RomProfile};
+
+// Trimmed down programs.json, the real one has a lot more fields per entry.
+const PROGRAMS: &str = r##"[
+  {
+    "title": "Blinky",
+    "description": "Pac-Man clone",
+    "authors": ["Hans Christian Egeberg"],
+    "roms": {
+      "2A4F1E5C3D1B0E9F8A7C6B5D4E3F2A1B0C9D8E7F": {
+        "file": "blinky.ch8",
+        "platforms": ["superchip", "xochip"],
+        "tickrate": 50,
+        "quirkyPlatforms": { "superchip": { "memoryLeaveIUnchanged": false, "memoryIncrementByX": true, "wrap": true } },
+        "colors": { "pixels": ["#000000", "#ffcc00", "nonsense"], "buzzer": "#ffffff" },
+        "keys": { "up": 3, "down": 6, "left": 7, "right": 8, "a": 15, "player2Up": 1 }
+      }
+    }
+  },
+  {
+    "title": "MegaChip demo",
+    "roms": { "00000000000000000000000000000000000000aa": { "platforms": ["megachip8"] } }
+  },
+  {
+    "title": "Pong",
+    "roms": {
+      "1111111111111111111111111111111111111111": { "platforms": ["modernChip8"], "tickrate": 9 }
+    }
+  }
+]"##;
+
+#[test]
+fn sha1_matches_the_standard_test_vector() {
+    assert_eq!(rom_sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn imports_chip8_database_entries() {
+    let mut database = RomDatabase::default();
+    assert_eq!(database.import_chip8_database(PROGRAMS).unwrap(), 3);
+
+    // Hashes are looked up in any case
+    let blinky = database
+        .get("2a4f1e5c3d1b0e9f8a7c6b5d4e3f2a1b0c9d8e7f")
+        .expect("imported");
+    assert_eq!(blinky.title.as_deref(), Some("Blinky"));
+    assert_eq!(blinky.description.as_deref(), Some("Pac-Man clone"));
+    assert_eq!(blinky.mode(), Some(Mode::SuperChip));
+    assert_eq!(
+        blinky.machine_quirks(),
+        Some(Quirks {
+            memory_increment: MemoryIncrement::X,
+            clipping: false,
+            ..Quirks::schip()
+        })
+    );
+    assert_eq!(blinky.instructions_per_frame, Some(50));
+    assert_eq!(blinky.colors, ["#000000", "#ffcc00"]);
+    assert_eq!(
+        blinky.apply_palette(PALETTE),
+        [0x000000, 0xffcc00, PALETTE[2], PALETTE[3]]
+    );
+    let gamepad: Vec<_> = blinky
+        .gamepad
+        .iter()
+        .map(|(button, key)| (button.as_str(), key.as_str()))
+        .collect();
+    assert_eq!(
+        gamepad,
+        [
+            ("a", "F"),
+            ("dpdown", "6"),
+            ("dpleft", "7"),
+            ("dpright", "8"),
+            ("dpup", "3")
+        ]
+    );
+
+    // Platforms we don't emulate still get the title
+    let megachip = database
+        .get("00000000000000000000000000000000000000aa")
+        .unwrap();
+    assert_eq!(megachip.title.as_deref(), Some("MegaChip demo"));
+    assert_eq!(megachip.machine_quirks(), None);
+
+    let pong = database
+        .get("1111111111111111111111111111111111111111")
+        .unwrap();
+    assert_eq!(pong.mode(), Some(Mode::Chip8));
+    let quirks = pong.machine_quirks().unwrap();
+    assert!(!quirks.vf_reset && !quirks.display_wait);
+}
+
+#[test]
+fn import_keeps_hand_edits() {
+    let mut database = RomDatabase::default();
+    database.insert(
+        "1111111111111111111111111111111111111111",
+        RomProfile {
+            title: Some("My Pong".to_string()),
+            platform: Some("xochip".to_string()),
+            ..RomProfile::default()
+        },
+    );
+    database.import_chip8_database(PROGRAMS).unwrap();
+
+    let pong = database
+        .get("1111111111111111111111111111111111111111")
+        .unwrap()
+        .clone();
+    assert_eq!(pong.title.as_deref(), Some("My Pong"));
+    assert_eq!(pong.mode(), Some(Mode::XoChip));
+    assert_eq!(pong.machine_quirks(), Some(Quirks::xochip()));
+    // ...but what it didn't say comes from the import
+    assert_eq!(pong.instructions_per_frame, Some(9));
+
+    // And the whole thing survives a trip through the file
+    let rom = [0x00, 0xe0, 0x12, 0x00];
+    database.insert(&rom_sha1(&rom), pong.clone());
+    let path = std::env::temp_dir().join(format!("chip8-romdb-{}.json", std::process::id()));
+    database.save_to_file(&path).unwrap();
+    let loaded = RomDatabase::load_from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, database);
+    assert_eq!(loaded.profile_for(&rom), Some(pong).as_ref());
+}
+
+#[test]
+fn profile_colours_go_over_its_palette() {
+    let profile = RomProfile {
+        palette: Some("GameBoy".to_string()),
+        colors: vec!["#123456".to_string()],
+        ..RomProfile::default()
+    };
+    let gameboy = display::palette("gameboy").unwrap();
+    assert_eq!(
+        profile.apply_palette(PALETTE),
+        [0x123456, gameboy[1], gameboy[2], gameboy[3]]
+    );
+    assert_eq!(RomProfile::default().apply_palette(gameboy), gameboy);
+}
+
+#[test]
+fn parses_colours() {
+    assert_eq!(parse_colour("#1a2B3c"), Some(0x1a2b3c));
+    assert_eq!(parse_colour("#fc0"), Some(0xffcc00));
+    assert_eq!(parse_colour("ffcc00"), None);
+    assert_eq!(parse_colour("#ffcc0"), None);
+    assert_eq!(parse_colour("#+fcc00"), None);
+}
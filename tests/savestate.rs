@@ -0,0 +1,90 @@
+// Save states from disk can't be trusted: restore() has to turn a broken one down instead of loading it
+// and panicking later.
+
+mod common;
+
+use chip_8::{Movie, Snapshot};
+use serde_json::{Value, json};
+
+// A snapshot of a running machine, with `change` applied to its JSON.
+fn tampered(profile: &str, change: impl FnOnce(&mut Value)) -> Snapshot {
+    let mut cpu = common::load(profile, &[0xA000, 0xD005, 0x1202]);
+    common::step(&mut cpu, 2).unwrap();
+    let mut json = serde_json::to_value(cpu.snapshot()).unwrap();
+    change(&mut json);
+    serde_json::from_value(json).expect("still a well formed snapshot")
+}
+
+fn restore(profile: &str, snapshot: &Snapshot) -> Result<(), String> {
+    common::machine(profile)
+        .restore(snapshot)
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn untouched_snapshot_restores() {
+    for profile in common::profiles() {
+        restore(profile, &tampered(profile, |_| {})).unwrap();
+    }
+}
+
+#[test]
+fn zero_width_screen_is_rejected() {
+    let snapshot = tampered("chip8", |json| json["framebuffer"]["width"] = json!(0));
+    assert!(restore("chip8", &snapshot).is_err());
+}
+
+#[test]
+fn hires_screen_needs_super_chip() {
+    let hires = |json: &mut Value| {
+        json["framebuffer"]["width"] = json!(128);
+        json["framebuffer"]["height"] = json!(64);
+        json["framebuffer"]["pixels"] = json!("00".repeat(128 * 64));
+    };
+    assert!(restore("chip8", &tampered("chip8", hires)).is_err());
+    restore("schip", &tampered("schip", hires)).unwrap();
+}
+
+#[test]
+fn pixel_count_must_match_the_screen() {
+    let snapshot = tampered("chip8", |json| {
+        json["framebuffer"]["pixels"] = json!("00".repeat(64 * 31))
+    });
+    assert!(restore("chip8", &snapshot).is_err());
+}
+
+#[test]
+fn planes_must_exist_in_the_mode() {
+    let snapshot = tampered("chip8", |json| json["framebuffer"]["planes"] = json!(3));
+    assert!(restore("chip8", &snapshot).is_err());
+    let snapshot = tampered("chip8", |json| {
+        json["framebuffer"]["pixels"] = json!(format!("02{}", "00".repeat(64 * 32 - 1)))
+    });
+    assert!(restore("chip8", &snapshot).is_err());
+    let snapshot = tampered("xochip", |json| json["framebuffer"]["planes"] = json!(3));
+    restore("xochip", &snapshot).unwrap();
+}
+
+#[test]
+fn key_wait_register_must_exist() {
+    let snapshot = tampered("chip8", |json| {
+        json["key_wait"] = json!({ "register": 16, "pressed": null })
+    });
+    assert!(restore("chip8", &snapshot).is_err());
+    let snapshot = tampered("chip8", |json| {
+        json["key_wait"] = json!({ "register": 3, "pressed": null })
+    });
+    restore("chip8", &snapshot).unwrap();
+}
+
+#[test]
+fn movie_with_a_broken_start_is_rejected() {
+    let rom = [0xA0, 0x00, 0xD0, 0x05, 0x12, 0x02];
+    let mut cpu = common::machine("chip8");
+    cpu.load_rom(&rom).unwrap();
+    let mut movie = Movie::new(&cpu, &rom, 8, true);
+    movie.start = Some(tampered("chip8", |json| {
+        json["framebuffer"]["width"] = json!(0)
+    }));
+    assert!(movie.setup(&rom).is_err());
+}